@@ -5,10 +5,11 @@
 	clippy::missing_docs_in_private_items
 )]
 
-use crate::RequestClient;
+use crate::{BasePath, RequestClient};
 use leptos::*;
 use leptos_meta::*;
 use leptos_router::*;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use serde_json::Value;
 use std::collections::HashMap;
 
@@ -16,11 +17,13 @@ use std::collections::HashMap;
 #[component]
 pub fn App() -> impl IntoView {
 	provide_meta_context();
+	let base_path = use_context::<BasePath>().unwrap_or_default();
 
 	view! {
 		<Meta name="application-name" content="Aedron Patchouli" />
 		<Meta name="description" content="Friendly media server" />
 		<Meta name="color-scheme" content="dark" />
+		<Meta name="base-path" content=base_path.0.clone() />
 		<Title formatter=|text| format!("{text} — Aedron Patchouli") />
 
 		<Router fallback=|| template! { <h1>"NOT FOUND"</h1> }.into_view()>
@@ -28,7 +31,7 @@ pub fn App() -> impl IntoView {
 				<h1>"Aedron Patchouli"</h1>
 			</header>
 			<main>
-				<Routes>
+				<Routes base=base_path.0.trim_end_matches('/').to_owned()>
 					<Route path="/" view=LibrariesIndex />
 					<Route path="/:library" view=LibraryShow />
 				</Routes>
@@ -37,6 +40,20 @@ pub fn App() -> impl IntoView {
 	}
 }
 
+/// Sends a request and decodes its JSON response
+///
+/// Error responses are turned into errors carrying their message.
+async fn fetch_json<T: DeserializeOwned>(
+	request: reqwest::RequestBuilder,
+) -> Result<T, ServerFnError> {
+	let response = request.send().await?;
+	if response.status().is_success() {
+		Ok(response.json().await?)
+	} else {
+		Err(ServerFnError::ServerError(response.text().await?))
+	}
+}
+
 fn fetch_fallback(errors: RwSignal<Errors>) -> impl IntoView {
 	view! {
 		<p>
@@ -54,16 +71,26 @@ fn fetch_fallback(errors: RwSignal<Errors>) -> impl IntoView {
 	}
 }
 
+/// Library listed by the API
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Library {
+	name: String,
+	display_name: String,
+	icon: Option<String>,
+	description: Option<String>,
+}
+
 #[component]
 fn LibrariesIndex() -> impl IntoView {
+	let base_path = store_value(use_context::<BasePath>().unwrap_or_default());
 	let client = use_context::<RequestClient>();
-	let libraries = create_resource::<_, Result<HashMap<String, String>, ServerFnError>, _>(
+	let libraries = create_resource::<_, Result<Vec<Library>, ServerFnError>, _>(
 		|| (),
 		move |()| {
 			let client = client.clone();
 			async move {
 				Ok(if let Some(client) = client {
-					client.get("/api/libraries").send().await?.json().await?
+					fetch_json(client.get("api/libraries")).await?
 				} else {
 					Default::default()
 				})
@@ -77,9 +104,12 @@ fn LibrariesIndex() -> impl IntoView {
 				<nav><ul>
 					{move || libraries.get().map(|libraries| libraries.map(|libraries| {
 						libraries.iter()
-							.map(|(url, display)| template! {
+							.map(|Library { name, display_name, icon, description }| template! {
 								<li>
-									<a href=format!("/{url}")>{display}</a>
+									<a href=base_path.with_value(|base_path| format!("{}{name}", base_path.0)) title=description.clone()>
+										{icon.clone().map(|icon| template! { <img src=icon alt="" /> })}
+										{display_name}
+									</a>
 								</li>
 							})
 							.collect_view()
@@ -115,12 +145,7 @@ fn LibraryShow() -> impl IntoView {
 			let client = client.clone();
 			async move {
 				Ok(if let Some(client) = client {
-					client
-						.get(&format!("/api/libraries/{library}"))
-						.send()
-						.await?
-						.json()
-						.await?
+					fetch_json(client.get(&format!("api/libraries/{library}"))).await?
 				} else {
 					Default::default()
 				})
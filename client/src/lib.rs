@@ -159,15 +159,38 @@ pub fn hydrate() {
 		let Ok(origin) = window.location().origin() else {
 			unreachable!()
 		};
-		let Ok(base_url) = Url::parse(&origin) else {
+		let base_path = window
+			.document()
+			.and_then(|document| {
+				document
+					.query_selector("meta[name=base-path]")
+					.ok()
+					.flatten()
+			})
+			.and_then(|meta| meta.get_attribute("content"))
+			.map_or_else(BasePath::default, BasePath);
+		let Ok(base_url) = Url::parse(&format!("{origin}{}", base_path.0)) else {
 			unreachable!()
 		};
 		provide_context(RequestClient::build(builder, base_url).unwrap());
+		provide_context(base_path);
 
 		view! { <App /> }
 	});
 }
 
+/// Path prefix of all routes of the app
+///
+/// It always starts and ends with a `/`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BasePath(pub String);
+impl Default for BasePath {
+	#[inline]
+	fn default() -> Self {
+		Self("/".to_owned())
+	}
+}
+
 /// Wrapper around [`reqwest::Client`] that adds a base URL
 #[derive(Debug, Clone)]
 pub struct RequestClient {
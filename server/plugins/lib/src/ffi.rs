@@ -9,15 +9,19 @@ use rusqlite::{
 use serde::{Serialize, Serializer};
 use std::{
 	cmp::Ordering,
-	ffi::{c_char, CStr, CString, FromBytesUntilNulError, NulError},
+	collections::HashMap,
+	ffi::{c_char, CStr, CString, FromBytesWithNulError, NulError},
 	fmt::{self, Debug, Display, Formatter},
+	hash::BuildHasher,
 	marker::PhantomData,
 	ops::{Deref, DerefMut},
 	slice::{Iter, IterMut},
+	str::Utf8Error,
+	time::Duration as StdDuration,
 };
 #[cfg(feature = "server")]
 use time::format_description::well_known::Iso8601;
-use time::{Date, Time};
+use time::{Date, Duration, Time};
 
 /// FFI-safe [`slice`]
 #[repr(C)]
@@ -271,6 +275,10 @@ impl<T: Serialize> Serialize for FfiBoxedSlice<T> {
 }
 
 /// FFI-safe [`str`]
+///
+/// # Invariant
+/// The pointed data is nul-terminated and valid UTF-8.
+/// Every constructor enforces it, so that [`to_str`](Self::to_str) does not need to check it again.
 #[repr(C)]
 #[derive(Clone, Copy)]
 pub struct FfiStr<'s> {
@@ -282,13 +290,17 @@ pub struct FfiStr<'s> {
 impl<'s> FfiStr<'s> {
 	/// Constructs a new instance
 	///
+	/// The given string must end with its only nul byte.
+	/// A string containing interior nul bytes is rejected
+	/// instead of being silently truncated at the first one.
+	///
 	/// # Errors
 	/// This function returns a [`FromBytesWithNulError`]
 	/// if the given string is not nul-terminated
 	/// or contains interior nul bytes.
 	#[inline]
-	pub const fn new(s: &'s str) -> Result<Self, FromBytesUntilNulError> {
-		match CStr::from_bytes_until_nul(s.as_bytes()) {
+	pub const fn new(s: &'s str) -> Result<Self, FromBytesWithNulError> {
+		match CStr::from_bytes_with_nul(s.as_bytes()) {
 			Ok(cstr) => Ok(Self {
 				ptr: cstr.as_ptr(),
 				_phantom: PhantomData,
@@ -300,26 +312,29 @@ impl<'s> FfiStr<'s> {
 	/// Constructs back a string slice
 	#[inline]
 	pub fn to_str(&self) -> &'_ str {
-		// SAFETY: This struct can only be constructed from a `&str`,
+		// SAFETY: This struct can only be constructed from a `&str` or a `CStr` checked to be UTF-8,
 		// and there is no way to get the ownership of the data.
 		unsafe { std::str::from_utf8_unchecked(CStr::from_ptr(self.ptr).to_bytes()) }
 	}
 }
 impl<'s> TryFrom<&'s str> for FfiStr<'s> {
-	type Error = FromBytesUntilNulError;
+	type Error = FromBytesWithNulError;
 
 	#[inline]
 	fn try_from(s: &'s str) -> Result<Self, Self::Error> {
 		Self::new(s)
 	}
 }
-impl<'s> From<&'s CStr> for FfiStr<'s> {
+impl<'s> TryFrom<&'s CStr> for FfiStr<'s> {
+	type Error = Utf8Error;
+
+	/// Checks that the given string is valid UTF-8
 	#[inline]
-	fn from(s: &'s CStr) -> Self {
-		Self {
+	fn try_from(s: &'s CStr) -> Result<Self, Self::Error> {
+		s.to_str().map(|_| Self {
 			ptr: s.as_ptr(),
 			_phantom: PhantomData,
-		}
+		})
 	}
 }
 impl<'s> Deref for FfiStr<'s> {
@@ -388,6 +403,9 @@ macro_rules! new_ffistr {
 pub use new_ffistr;
 
 /// FFI-safe [`String`]
+///
+/// # Invariant
+/// The owned data is nul-terminated and valid UTF-8, as it can only be constructed from a [`String`].
 #[repr(C)]
 pub struct FfiString {
 	/// Pointer to the data
@@ -506,6 +524,77 @@ impl Serialize for FfiString {
 	}
 }
 
+/// FFI-safe pair of values
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FfiPair<K, V> {
+	/// First value
+	pub key: K,
+	/// Second value
+	pub value: V,
+}
+impl<K, V> From<(K, V)> for FfiPair<K, V> {
+	#[inline]
+	fn from((key, value): (K, V)) -> Self {
+		Self { key, value }
+	}
+}
+
+/// FFI-safe map of strings
+///
+/// Lookups are linear, which is fine for the few entries of a configuration.
+#[repr(C)]
+#[derive(Debug, Default, Clone)]
+pub struct FfiMap {
+	/// Entries of the map
+	pairs: FfiBoxedSlice<FfiPair<FfiString, FfiString>>,
+}
+impl FfiMap {
+	/// Returns the value associated with the given key
+	#[inline]
+	pub fn get(&self, key: &str) -> Option<&str> {
+		self.pairs
+			.iter()
+			.find(|pair| pair.key.to_str() == key)
+			.map(|pair| pair.value.to_str())
+	}
+
+	/// Returns the number of entries
+	#[inline]
+	pub const fn len(&self) -> usize {
+		self.pairs.len()
+	}
+
+	/// Checks if the map is empty
+	#[inline]
+	pub const fn is_empty(&self) -> bool {
+		self.pairs.is_empty()
+	}
+
+	/// Returns an [`Iterator`] over the entries
+	#[inline]
+	pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+		self.pairs
+			.iter()
+			.map(|pair| (pair.key.to_str(), pair.value.to_str()))
+	}
+}
+impl<S: BuildHasher> TryFrom<HashMap<String, String, S>> for FfiMap {
+	type Error = NulError;
+
+	fn try_from(map: HashMap<String, String, S>) -> Result<Self, Self::Error> {
+		map.into_iter()
+			.map(|(key, value)| {
+				Ok(FfiPair::from((
+					FfiString::new(key)?,
+					FfiString::new(value)?,
+				)))
+			})
+			.collect::<Result<_, _>>()
+			.map(|pairs| Self { pairs })
+	}
+}
+
 /// FFI-safe [`Date`]
 #[repr(transparent)]
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
@@ -635,6 +724,113 @@ impl Serialize for FfiTime {
 	}
 }
 
+/// FFI-safe [`Duration`]
+///
+/// The duration is `seconds + nanoseconds / 1e9`, so that negative durations have positive nanoseconds.
+/// Conversions saturate if the duration does not fit.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct FfiDuration {
+	/// Whole seconds, rounded towards negative infinity
+	seconds: i64,
+	/// Fractional part, in nanoseconds
+	nanoseconds: u32,
+}
+impl FfiDuration {
+	/// Returns the number of whole milliseconds, rounded towards negative infinity
+	///
+	/// It saturates if the duration does not fit.
+	#[inline]
+	pub const fn whole_milliseconds(&self) -> i64 {
+		self.seconds
+			.saturating_mul(1000)
+			.saturating_add((self.nanoseconds / 1_000_000) as i64)
+	}
+
+	/// Returns the number of seconds as a floating point value
+	#[inline]
+	pub fn as_seconds_f64(&self) -> f64 {
+		#[allow(clippy::cast_precision_loss)]
+		let seconds = self.seconds as f64;
+		seconds + f64::from(self.nanoseconds) / 1e9
+	}
+}
+impl From<StdDuration> for FfiDuration {
+	#[inline]
+	fn from(duration: StdDuration) -> Self {
+		match i64::try_from(duration.as_secs()) {
+			Ok(seconds) => Self {
+				seconds,
+				nanoseconds: duration.subsec_nanos(),
+			},
+			Err(_) => Self {
+				seconds: i64::MAX,
+				nanoseconds: 999_999_999,
+			},
+		}
+	}
+}
+impl From<Duration> for FfiDuration {
+	#[inline]
+	fn from(duration: Duration) -> Self {
+		let seconds = duration.whole_seconds();
+		let nanoseconds = duration.subsec_nanoseconds();
+		if nanoseconds < 0 {
+			match seconds.checked_sub(1) {
+				Some(seconds) => Self {
+					seconds,
+					nanoseconds: 1_000_000_000 - nanoseconds.unsigned_abs(),
+				},
+				None => Self {
+					seconds: i64::MIN,
+					nanoseconds: 0,
+				},
+			}
+		} else {
+			Self {
+				seconds,
+				nanoseconds: nanoseconds.unsigned_abs(),
+			}
+		}
+	}
+}
+impl From<FfiDuration> for Duration {
+	#[inline]
+	fn from(duration: FfiDuration) -> Self {
+		// NOTE: `nanoseconds` is always less than one second
+		#[allow(clippy::cast_possible_wrap)]
+		Self::new(duration.seconds, duration.nanoseconds as i32)
+	}
+}
+#[cfg(feature = "server")]
+impl From<FfiDuration> for Value {
+	#[inline]
+	fn from(duration: FfiDuration) -> Self {
+		duration.whole_milliseconds().into()
+	}
+}
+#[cfg(feature = "server")]
+impl ToSql for FfiDuration {
+	#[inline]
+	fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+		Ok(ToSqlOutput::Owned((*self).into()))
+	}
+}
+#[cfg(feature = "server")]
+impl From<FfiDuration> for serde_json::Value {
+	#[inline]
+	fn from(duration: FfiDuration) -> Self {
+		duration.as_seconds_f64().into()
+	}
+}
+#[cfg(feature = "server")]
+impl Serialize for FfiDuration {
+	#[inline]
+	fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+		self.as_seconds_f64().serialize(serializer)
+	}
+}
+
 /// FFI-safe [`Option`]
 #[repr(C)]
 #[derive(Debug, Clone, Copy)]
@@ -653,6 +849,50 @@ impl<T> FfiOption<T> {
 			Self::None => None,
 		}
 	}
+
+	/// Maps an `FfiOption<T>` to `FfiOption<U>` by applying a function to a contained value
+	///
+	/// See [`Option::map`].
+	#[inline]
+	pub fn map<U>(self, f: impl FnOnce(T) -> U) -> FfiOption<U> {
+		match self {
+			Self::Some(value) => FfiOption::Some(f(value)),
+			Self::None => FfiOption::None,
+		}
+	}
+
+	/// Returns [`None`](Self::None) if the option is `None`, otherwise calls `f` with the wrapped value and returns the result
+	///
+	/// See [`Option::and_then`].
+	#[inline]
+	pub fn and_then<U>(self, f: impl FnOnce(T) -> FfiOption<U>) -> FfiOption<U> {
+		match self {
+			Self::Some(value) => f(value),
+			Self::None => FfiOption::None,
+		}
+	}
+
+	/// Returns the contained [`Some`](Self::Some) value or a provided default
+	///
+	/// See [`Option::unwrap_or`].
+	#[inline]
+	pub fn unwrap_or(self, default: T) -> T {
+		match self {
+			Self::Some(value) => value,
+			Self::None => default,
+		}
+	}
+
+	/// Returns the contained [`Some`](Self::Some) value or computes it from a closure
+	///
+	/// See [`Option::unwrap_or_else`].
+	#[inline]
+	pub fn unwrap_or_else(self, f: impl FnOnce() -> T) -> T {
+		match self {
+			Self::Some(value) => value,
+			Self::None => f(),
+		}
+	}
 }
 impl<T> From<Option<T>> for FfiOption<T> {
 	#[inline]
@@ -735,3 +975,225 @@ impl<T, E> From<FfiResult<T, E>> for Result<T, E> {
 		}
 	}
 }
+
+/// FFI-safe error, made of a message and a code
+#[repr(C)]
+#[derive(Debug, Clone)]
+pub struct FfiError {
+	/// Description of the error
+	pub message: FfiString,
+	/// Code of the error
+	///
+	/// Its meaning is defined by the plugin, and `0` tells nothing more than the message.
+	pub code: u32,
+}
+impl FfiError {
+	/// Constructs a new instance
+	///
+	/// Nul bytes of the message are replaced by `U+FFFD REPLACEMENT CHARACTER`.
+	#[inline]
+	pub fn new(code: u32, message: impl Into<String>) -> Self {
+		let mut message = message.into();
+		if message.contains('\0') {
+			message = message.replace('\0', "\u{fffd}");
+		}
+		Self {
+			message: FfiString::new(message).unwrap_or_else(|_err| unreachable!()),
+			code,
+		}
+	}
+}
+impl Display for FfiError {
+	#[inline]
+	fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+		if self.code == 0 {
+			Display::fmt(&self.message, f)
+		} else {
+			write!(f, "{} (code {})", self.message, self.code)
+		}
+	}
+}
+impl std::error::Error for FfiError {}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn nul_terminated_str_is_accepted() {
+		assert_eq!(FfiStr::new("ab\0").unwrap().to_str(), "ab");
+	}
+
+	#[test]
+	fn str_without_nul_is_rejected() {
+		assert!(FfiStr::new("ab").is_err());
+	}
+
+	#[test]
+	fn str_with_interior_nul_is_rejected() {
+		assert!(FfiStr::new("a\0b\0").is_err());
+	}
+
+	#[test]
+	fn utf8_cstr_is_accepted() {
+		let cstr = CStr::from_bytes_with_nul("é\0".as_bytes()).unwrap();
+		assert_eq!(FfiStr::try_from(cstr).unwrap().to_str(), "é");
+	}
+
+	#[test]
+	fn non_utf8_cstr_is_rejected() {
+		let cstr = CStr::from_bytes_with_nul(b"\xff\0").unwrap();
+		assert!(FfiStr::try_from(cstr).is_err());
+	}
+
+	/// Returns a map of the given entries
+	fn map(entries: &[(&str, &str)]) -> FfiMap {
+		entries
+			.iter()
+			.map(|&(key, value)| (key.to_owned(), value.to_owned()))
+			.collect::<HashMap<_, _>>()
+			.try_into()
+			.unwrap()
+	}
+
+	#[test]
+	fn map_lookups() {
+		let map = map(&[("ffprobe", "/usr/bin/ffprobe"), ("covers", "")]);
+		assert_eq!(map.get("ffprobe"), Some("/usr/bin/ffprobe"));
+		assert_eq!(map.get("covers"), Some(""));
+		assert_eq!(map.get("ffmpeg"), None);
+		assert_eq!(map.get("FFPROBE"), None);
+		assert_eq!(FfiMap::default().get("ffprobe"), None);
+	}
+
+	#[test]
+	fn map_round_trip() {
+		let entries = HashMap::from([
+			("a".to_owned(), "1".to_owned()),
+			("b".to_owned(), "2".to_owned()),
+		]);
+		let map = FfiMap::try_from(entries.clone()).unwrap();
+		assert_eq!(map.len(), 2);
+		assert_eq!(
+			map.iter()
+				.map(|(key, value)| (key.to_owned(), value.to_owned()))
+				.collect::<HashMap<_, _>>(),
+			entries
+		);
+	}
+
+	#[test]
+	fn map_rejects_nul_bytes() {
+		assert!(FfiMap::try_from(HashMap::from([("a\0".to_owned(), "1".to_owned())])).is_err());
+		assert!(FfiMap::try_from(HashMap::from([("a".to_owned(), "1\0".to_owned())])).is_err());
+	}
+
+	#[test]
+	fn pair_from_tuple() {
+		assert_eq!(
+			FfiPair::from(("key", 1)),
+			FfiPair {
+				key: "key",
+				value: 1
+			}
+		);
+	}
+
+	#[test]
+	fn std_duration_is_converted() {
+		let duration = FfiDuration::from(StdDuration::from_millis(1500));
+		assert_eq!(
+			duration,
+			FfiDuration {
+				seconds: 1,
+				nanoseconds: 500_000_000
+			}
+		);
+		assert_eq!(duration.whole_milliseconds(), 1500);
+		assert!((duration.as_seconds_f64() - 1.5).abs() < f64::EPSILON);
+	}
+
+	#[test]
+	fn negative_duration_has_positive_nanoseconds() {
+		let duration = FfiDuration::from(Duration::milliseconds(-1500));
+		assert_eq!(
+			duration,
+			FfiDuration {
+				seconds: -2,
+				nanoseconds: 500_000_000
+			}
+		);
+		assert_eq!(duration.whole_milliseconds(), -1500);
+		assert!((duration.as_seconds_f64() + 1.5).abs() < f64::EPSILON);
+		assert_eq!(Duration::from(duration), Duration::milliseconds(-1500));
+	}
+
+	#[test]
+	fn duration_conversions_saturate() {
+		let max = FfiDuration::from(StdDuration::MAX);
+		assert_eq!(
+			max,
+			FfiDuration {
+				seconds: i64::MAX,
+				nanoseconds: 999_999_999
+			}
+		);
+		assert_eq!(max.whole_milliseconds(), i64::MAX);
+
+		let min = FfiDuration::from(Duration::MIN);
+		assert_eq!(
+			min,
+			FfiDuration {
+				seconds: i64::MIN,
+				nanoseconds: 0
+			}
+		);
+		assert_eq!(min.whole_milliseconds(), i64::MIN);
+	}
+
+	#[test]
+	fn option_map() {
+		assert_eq!(FfiOption::Some(2).map(|n| n * 3).as_ref(), Some(&6));
+		assert_eq!(
+			FfiOption::<i32>::None
+				.map(|_| -> i32 { panic!("map called its function on None") })
+				.as_ref(),
+			None
+		);
+	}
+
+	#[test]
+	fn option_and_then() {
+		let half = |n: i32| {
+			if n % 2 == 0 {
+				FfiOption::Some(n / 2)
+			} else {
+				FfiOption::None
+			}
+		};
+		assert_eq!(FfiOption::Some(4).and_then(half).as_ref(), Some(&2));
+		assert_eq!(FfiOption::Some(3).and_then(half).as_ref(), None);
+		assert_eq!(
+			FfiOption::<i32>::None
+				.and_then(|_| -> FfiOption<i32> { panic!("and_then called its function on None") })
+				.as_ref(),
+			None
+		);
+	}
+
+	#[test]
+	fn option_unwrap_or() {
+		assert_eq!(FfiOption::Some(1).unwrap_or(2), 1);
+		assert_eq!(FfiOption::None.unwrap_or(2), 2);
+	}
+
+	#[test]
+	fn option_unwrap_or_else() {
+		assert_eq!(
+			FfiOption::Some(1)
+				.unwrap_or_else(|| panic!("unwrap_or_else called its function on Some")),
+			1
+		);
+		assert_eq!(FfiOption::None.unwrap_or_else(|| 2), 2);
+	}
+}
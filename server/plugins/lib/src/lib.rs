@@ -75,12 +75,14 @@ use rusqlite::types::{FromSql, FromSqlError, FromSqlResult, ToSql, ToSqlOutput,
 use serde::{Deserialize, Serialize};
 use std::{
 	cmp::Ordering,
+	error::Error,
 	fmt::{self, Display, Formatter},
 	num::ParseIntError,
 	str::FromStr,
 };
 
 pub mod ffi;
+pub mod log;
 #[cfg(feature = "media")]
 pub mod media;
 
@@ -103,6 +105,20 @@ pub struct Version {
 	pub patch: u8,
 }
 impl Version {
+	/// Parses a version, tolerating the decorations commonly found around it
+	///
+	/// Unlike [`from_str`](Self::from_str), a leading `v` or `V` is stripped,
+	/// and anything after a `-` or a `+` (pre-release and build metadata) is ignored.
+	///
+	/// # Errors
+	/// This function returns an error if a part of the version is not a number.
+	pub fn parse_lenient(s: &str) -> Result<Self, VersionParseError> {
+		let s = s.trim();
+		let s = s.strip_prefix(['v', 'V']).unwrap_or(s);
+		let s = s.split(['-', '+']).next().unwrap_or_default();
+		s.parse().map_err(VersionParseError)
+	}
+
 	/// Checks if this version is compatible with an other
 	#[inline]
 	pub fn is_compatible(&self, other: &Self) -> bool {
@@ -177,5 +193,40 @@ impl ToSql for Version {
 	}
 }
 
+/// Error returned by [`Version::parse_lenient`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VersionParseError(ParseIntError);
+impl Display for VersionParseError {
+	#[inline]
+	fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+		write!(f, "invalid version: {}", self.0)
+	}
+}
+impl Error for VersionParseError {
+	#[inline]
+	fn source(&self) -> Option<&(dyn Error + 'static)> {
+		Some(&self.0)
+	}
+}
+
 /// Signature of the `plugin_version` function that plugins must export
 pub type PluginVersion = extern "C" fn() -> Version;
+
+/// Configuration passed to plugins on initialization
+#[repr(C)]
+#[derive(Debug, Clone)]
+pub struct PluginConfig {
+	/// Maximum number of metadata extractions the server runs at the same time, across all plugins
+	pub max_extractions: usize,
+	/// Options of the plugin, set in the server's configuration
+	pub options: ffi::FfiMap,
+}
+
+/// Signature of the `plugin_init` function that plugins may export
+///
+/// It is called once, after the plugin is loaded.
+pub type PluginInit = extern "C" fn(config: &PluginConfig);
+/// Signature of the `plugin_shutdown` function that plugins may export
+///
+/// It is called once, before the plugin is unloaded.
+pub type PluginShutdown = extern "C" fn();
@@ -0,0 +1,86 @@
+//! Provides the forwarding of log records from plugins to the server
+//!
+//! The server passes a [`LogCallback`] to plugins that export a [`SetLogger`] function.
+//! Plugins then emit records with [`plugin_log`], which are dropped if the server did not set a callback.
+
+use crate::ffi::FfiStr;
+use std::sync::OnceLock;
+
+/// Level of a log record
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LogLevel {
+	/// Very serious errors
+	Error = 1,
+	/// Hazardous situations
+	Warn,
+	/// Useful information
+	Info,
+	/// Lower priority information
+	Debug,
+	/// Very low priority, often extremely verbose, information
+	Trace,
+}
+
+/// Signature of the callback the server passes to plugins to forward log records
+///
+/// The target is the name of the plugin crate.
+pub type LogCallback = extern "C" fn(target: FfiStr<'_>, level: LogLevel, message: FfiStr<'_>);
+/// Signature of the `set_logger` function that plugins may export
+pub type SetLogger = extern "C" fn(callback: LogCallback);
+
+/// Callback set by the server
+static CALLBACK: OnceLock<LogCallback> = OnceLock::new();
+
+/// Sets the callback to forward log records to
+///
+/// Only the first callback is kept.
+#[inline]
+pub fn set_callback(callback: LogCallback) {
+	let _ = CALLBACK.set(callback);
+}
+
+/// Forwards a log record to the server, if it set a callback
+///
+/// Nul bytes are removed from the target and the message.
+pub fn log(target: &str, level: LogLevel, message: &str) {
+	/// Converts a string to a nul-terminated string without interior nul bytes
+	fn to_nul_terminated(s: &str) -> String {
+		let mut s = s.replace('\0', "");
+		s.push('\0');
+		s
+	}
+
+	if let Some(callback) = CALLBACK.get() {
+		let target = to_nul_terminated(target);
+		let message = to_nul_terminated(message);
+		if let (Ok(target), Ok(message)) = (FfiStr::new(&target), FfiStr::new(&message)) {
+			callback(target, level, message);
+		}
+	}
+}
+
+/// Emits a log record from a plugin
+///
+/// The first argument is a [`LogLevel`] variant, followed by [`format!`] arguments.
+#[macro_export]
+macro_rules! plugin_log {
+	($level:ident, $($arg:tt)+) => {
+		if $crate::log::is_enabled() {
+			$crate::log::log(
+				::core::env!("CARGO_CRATE_NAME"),
+				$crate::log::LogLevel::$level,
+				&::std::format!($($arg)+),
+			)
+		}
+	};
+}
+pub use plugin_log;
+
+/// Checks if the server set a callback
+///
+/// This avoids formatting records that would be dropped.
+#[inline]
+pub fn is_enabled() -> bool {
+	CALLBACK.get().is_some()
+}
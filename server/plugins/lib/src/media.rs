@@ -1,6 +1,6 @@
 //! Provides the types for media plugins
 
-use crate::{ffi::*, Version};
+use crate::{ffi::*, log::LogLevel, PluginConfig, Version};
 #[cfg(feature = "server")]
 use rusqlite::{
 	types::{ToSqlOutput, Value},
@@ -8,22 +8,81 @@ use rusqlite::{
 };
 #[cfg(feature = "server")]
 use serde::{Serialize, Serializer};
-pub use time::{Date, Time};
+pub use time::{Date, Duration, Time};
 
 /// Version of the media plugin library
 pub const PLUGLIB_VERSION: Version = Version {
 	major: 0,
-	minor: 2,
+	minor: 6,
 	patch: 0,
 };
 
+/// Fingerprint of the ABI of media plugins
+///
+/// It hashes the target platform and the layout of the types that cross the plugin boundary,
+/// so that the server can reject a plugin built for another platform before reading any of its structures.
+pub const ABI_FINGERPRINT: u64 = {
+	/// Feeds bytes to a [FNV-1a](https://en.wikipedia.org/wiki/Fowler%E2%80%93Noll%E2%80%93Vo_hash_function) hash
+	const fn feed(mut hash: u64, bytes: &[u8]) -> u64 {
+		let mut i = 0;
+		while i < bytes.len() {
+			hash ^= bytes[i] as u64;
+			hash = hash.wrapping_mul(0x0100_0000_01b3);
+			i += 1;
+		}
+		hash
+	}
+	/// Feeds the layout of a type to the hash
+	const fn feed_layout<T>(hash: u64) -> u64 {
+		let hash = feed(hash, &std::mem::size_of::<T>().to_le_bytes());
+		feed(hash, &std::mem::align_of::<T>().to_le_bytes())
+	}
+
+	let mut hash = 0xcbf2_9ce4_8422_2325;
+	hash = feed(hash, std::env::consts::ARCH.as_bytes());
+	hash = feed(hash, std::env::consts::OS.as_bytes());
+	hash = feed(hash, &1_u16.to_ne_bytes());
+	hash = feed_layout::<usize>(hash);
+	hash = feed_layout::<Version>(hash);
+	hash = feed_layout::<PluginConfig>(hash);
+	hash = feed_layout::<LogLevel>(hash);
+	hash = feed_layout::<FfiStr<'static>>(hash);
+	hash = feed_layout::<FfiString>(hash);
+	hash = feed_layout::<FfiBoxedSlice<u8>>(hash);
+	hash = feed_layout::<FfiMap>(hash);
+	hash = feed_layout::<FfiTime>(hash);
+	hash = feed_layout::<FfiDuration>(hash);
+	hash = feed_layout::<FfiOption<FfiString>>(hash);
+	hash = feed_layout::<FfiError>(hash);
+	hash = feed_layout::<Media>(hash);
+	hash = feed_layout::<MetadataField>(hash);
+	hash = feed_layout::<MetadataFieldType>(hash);
+	hash = feed_layout::<MetadataFieldValue>(hash);
+	hash = feed_layout::<FfiResult<FfiBoxedSlice<FfiOption<MetadataFieldValue>>, FfiError>>(hash);
+	hash
+};
+
 /// Signature of the `describe_media` function that media plugins must export
 pub type DescribeMedia = extern "C" fn() -> Media;
 /// Signature of the `supported_types` function that media plugins must export
 pub type SupportedTypes = extern "C" fn() -> FfiBoxedSlice<FfiStr<'static>>;
+/// Signature of the `supported_extensions` function that media plugins may export
+///
+/// It lists file extensions (without the leading dot) supported by the plugin,
+/// so that files are also accepted when the MIME type of their extension is unknown to the server.
+/// They are compared ignoring ASCII case.
+pub type SupportedExtensions = extern "C" fn() -> FfiBoxedSlice<FfiStr<'static>>;
 /// Signature of the `extract_metadata` function that media plugins must export
+///
+/// On failure, it returns an error telling why the metadata could not be extracted.
 pub type ExtractMetadata =
-	extern "C" fn(path: FfiStr<'_>) -> FfiResult<FfiBoxedSlice<FfiOption<MetadataFieldValue>>, ()>;
+	extern "C" fn(
+		path: FfiStr<'_>,
+	) -> FfiResult<FfiBoxedSlice<FfiOption<MetadataFieldValue>>, FfiError>;
+/// Signature of the `extract_raw_metadata` function that media plugins may export
+///
+/// It returns the full output of the extractor, serialized as JSON.
+pub type ExtractRawMetadata = extern "C" fn(path: FfiStr<'_>) -> FfiOption<FfiString>;
 
 /// Description of the media type provided by the plugin
 #[repr(C)]
@@ -33,6 +92,12 @@ pub struct Media {
 	pub name: FfiStr<'static>,
 	/// Identifier of the media, for data purposes
 	pub ident: FfiStr<'static>,
+	/// URL of an icon of the media, for display purposes
+	///
+	/// Inline SVG images can be given as `data:` URLs.
+	pub icon: FfiOption<FfiStr<'static>>,
+	/// Description of the media, for display purposes
+	pub description: FfiOption<FfiStr<'static>>,
 	/// Metadata fields of the media
 	pub fields: FfiBoxedSlice<MetadataField>,
 }
@@ -51,6 +116,117 @@ pub struct MetadataField {
 	pub is_list: bool,
 }
 
+/// Builder of [`Media`]
+#[derive(Debug, Clone)]
+pub struct MediaBuilder {
+	/// Name of the media, for display purposes
+	name: FfiStr<'static>,
+	/// Identifier of the media, for data purposes
+	ident: FfiStr<'static>,
+	/// URL of an icon of the media, for display purposes
+	icon: Option<FfiStr<'static>>,
+	/// Description of the media, for display purposes
+	description: Option<FfiStr<'static>>,
+	/// Metadata fields of the media
+	fields: Vec<MetadataField>,
+}
+impl MediaBuilder {
+	/// Constructs a new builder of a media without fields
+	#[inline]
+	pub const fn new(ident: FfiStr<'static>, name: FfiStr<'static>) -> Self {
+		Self {
+			name,
+			ident,
+			icon: None,
+			description: None,
+			fields: Vec::new(),
+		}
+	}
+
+	/// Sets the URL of the icon of the media
+	#[inline]
+	#[must_use]
+	pub const fn icon(mut self, icon: FfiStr<'static>) -> Self {
+		self.icon = Some(icon);
+		self
+	}
+
+	/// Sets the description of the media
+	#[inline]
+	#[must_use]
+	pub const fn description(mut self, description: FfiStr<'static>) -> Self {
+		self.description = Some(description);
+		self
+	}
+
+	/// Appends a metadata field
+	#[inline]
+	#[must_use]
+	pub fn field(mut self, field: impl Into<MetadataField>) -> Self {
+		self.fields.push(field.into());
+		self
+	}
+
+	/// Builds the [`Media`]
+	#[inline]
+	pub fn build(self) -> Media {
+		Media {
+			name: self.name,
+			ident: self.ident,
+			icon: self.icon.into(),
+			description: self.description.into(),
+			fields: FfiBoxedSlice::from(self.fields.into_boxed_slice()),
+		}
+	}
+}
+
+/// Builder of [`MetadataField`]
+///
+/// The optional attributes take their default values until they are set.
+#[derive(Debug, Clone, Copy)]
+pub struct MetadataFieldBuilder {
+	/// Field being built
+	field: MetadataField,
+}
+impl MetadataFieldBuilder {
+	/// Constructs a new builder of a field holding a single value
+	#[inline]
+	pub const fn new(
+		ident: FfiStr<'static>,
+		name: FfiStr<'static>,
+		r#type: MetadataFieldType,
+	) -> Self {
+		Self {
+			field: MetadataField {
+				name,
+				ident,
+				r#type,
+				is_list: false,
+			},
+		}
+	}
+
+	/// Sets whether the field is a list of values
+	#[inline]
+	#[must_use]
+	pub const fn list(mut self, is_list: bool) -> Self {
+		self.field.is_list = is_list;
+		self
+	}
+
+	/// Builds the [`MetadataField`]
+	#[inline]
+	pub const fn build(self) -> MetadataField {
+		self.field
+	}
+}
+impl From<MetadataFieldBuilder> for MetadataField {
+	#[inline]
+	fn from(builder: MetadataFieldBuilder) -> Self {
+		builder.build()
+	}
+}
+
 /// Data type of a [`MetadataField`]
 #[repr(u8)]
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -69,6 +245,8 @@ pub enum MetadataFieldType {
 	Date,
 	/// Time value
 	Time,
+	/// Duration value
+	Duration,
 }
 #[cfg(feature = "server")]
 impl MetadataFieldType {
@@ -76,7 +254,8 @@ impl MetadataFieldType {
 	#[inline]
 	pub const fn to_sql(&self) -> &'static str {
 		match self {
-			Self::Integer | Self::Boolean => "INTEGER",
+			// NOTE: Durations are stored in milliseconds
+			Self::Integer | Self::Boolean | Self::Duration => "INTEGER",
 			Self::Real => "REAL",
 			Self::Text | Self::Date | Self::Time => "TEXT",
 			Self::Blob => "BLOB",
@@ -102,9 +281,93 @@ pub enum MetadataFieldValue {
 	Date(i32),
 	/// Time value
 	Time(FfiTime),
+	/// Duration value
+	Duration(FfiDuration),
 	/// List of values
 	List(FfiBoxedSlice<Self>),
 }
+impl MetadataFieldValue {
+	/// Returns the value if it is an [`Integer`](Self::Integer)
+	#[inline]
+	pub const fn as_i64(&self) -> Option<i64> {
+		match self {
+			Self::Integer(value) => Some(*value),
+			_ => None,
+		}
+	}
+
+	/// Returns the value if it is a [`Real`](Self::Real)
+	#[inline]
+	pub const fn as_f64(&self) -> Option<f64> {
+		match self {
+			Self::Real(value) => Some(*value),
+			_ => None,
+		}
+	}
+
+	/// Returns the value if it is a [`Text`](Self::Text)
+	#[inline]
+	pub fn as_str(&self) -> Option<&str> {
+		match self {
+			Self::Text(value) => Some(value.to_str()),
+			_ => None,
+		}
+	}
+
+	/// Returns the value if it is a [`Blob`](Self::Blob)
+	#[inline]
+	pub const fn as_bytes(&self) -> Option<&[u8]> {
+		match self {
+			Self::Blob(value) => Some(value.to_slice()),
+			_ => None,
+		}
+	}
+
+	/// Returns the value if it is a [`Boolean`](Self::Boolean)
+	#[inline]
+	pub const fn as_bool(&self) -> Option<bool> {
+		match self {
+			Self::Boolean(value) => Some(*value),
+			_ => None,
+		}
+	}
+
+	/// Returns the value if it is a valid [`Date`](Self::Date)
+	#[inline]
+	pub fn as_date(&self) -> Option<Date> {
+		match self {
+			Self::Date(value) => Date::from_julian_day(*value).ok(),
+			_ => None,
+		}
+	}
+
+	/// Returns the value if it is a [`Time`](Self::Time)
+	#[inline]
+	pub fn as_time(&self) -> Option<Time> {
+		match self {
+			Self::Time(value) => Some((*value).into()),
+			_ => None,
+		}
+	}
+
+	/// Returns the value if it is a [`Duration`](Self::Duration)
+	#[inline]
+	pub fn as_duration(&self) -> Option<Duration> {
+		match self {
+			Self::Duration(value) => Some((*value).into()),
+			_ => None,
+		}
+	}
+
+	/// Returns the values if it is a [`List`](Self::List)
+	#[inline]
+	pub const fn as_list(&self) -> Option<&[Self]> {
+		match self {
+			Self::List(list) => Some(list.to_slice()),
+			_ => None,
+		}
+	}
+}
 impl From<i64> for MetadataFieldValue {
 	#[inline]
 	fn from(value: i64) -> Self {
@@ -181,6 +444,24 @@ impl From<Time> for MetadataFieldValue {
 		Self::from(FfiTime::from(value))
 	}
 }
+impl From<FfiDuration> for MetadataFieldValue {
+	#[inline]
+	fn from(value: FfiDuration) -> Self {
+		Self::Duration(value)
+	}
+}
+impl From<Duration> for MetadataFieldValue {
+	#[inline]
+	fn from(value: Duration) -> Self {
+		Self::from(FfiDuration::from(value))
+	}
+}
+impl From<std::time::Duration> for MetadataFieldValue {
+	#[inline]
+	fn from(value: std::time::Duration) -> Self {
+		Self::from(FfiDuration::from(value))
+	}
+}
 impl From<FfiBoxedSlice<MetadataFieldValue>> for MetadataFieldValue {
 	#[inline]
 	fn from(list: FfiBoxedSlice<MetadataFieldValue>) -> Self {
@@ -199,7 +480,11 @@ impl From<MetadataFieldValue> for Value {
 			MetadataFieldValue::Boolean(value) => value.into(),
 			MetadataFieldValue::Date(value) => FfiDate(value).into(),
 			MetadataFieldValue::Time(value) => value.into(),
-			MetadataFieldValue::List(_list) => unimplemented!(),
+			MetadataFieldValue::Duration(value) => value.into(),
+			// NOTE: Like `ToSql`, lists are encoded as JSON arrays
+			MetadataFieldValue::List(list) => serde_json::to_string(list.to_slice())
+				.expect("metadata values can always be serialized")
+				.into(),
 		}
 	}
 }
@@ -215,6 +500,7 @@ impl ToSql for MetadataFieldValue {
 			Self::Boolean(value) => value.to_sql(),
 			Self::Date(value) => value.to_sql(),
 			Self::Time(value) => value.to_sql(),
+			Self::Duration(value) => value.to_sql(),
 			Self::List(list) => list.to_sql(),
 		}
 	}
@@ -230,7 +516,11 @@ impl From<MetadataFieldValue> for serde_json::Value {
 			MetadataFieldValue::Boolean(value) => value.into(),
 			MetadataFieldValue::Date(value) => FfiDate(value).into(),
 			MetadataFieldValue::Time(value) => value.into(),
-			MetadataFieldValue::List(_list) => unimplemented!(),
+			MetadataFieldValue::Duration(value) => value.into(),
+			// NOTE: Like `ToSql`, lists are encoded as JSON arrays
+			MetadataFieldValue::List(list) => serde_json::to_string(list.to_slice())
+				.expect("metadata values can always be serialized")
+				.into(),
 		}
 	}
 }
@@ -245,11 +535,112 @@ impl Serialize for MetadataFieldValue {
 			Self::Boolean(value) => value.serialize(serializer),
 			Self::Date(value) => value.serialize(serializer),
 			Self::Time(value) => value.serialize(serializer),
+			Self::Duration(value) => value.serialize(serializer),
 			Self::List(list) => list.serialize(serializer),
 		}
 	}
 }
 
+#[cfg(all(test, feature = "server"))]
+mod tests {
+	use super::*;
+
+	/// Returns a list of lists of metadata values, and its JSON representation
+	fn nested_list() -> (MetadataFieldValue, serde_json::Value) {
+		let inner = [
+			MetadataFieldValue::from(1),
+			MetadataFieldValue::try_from("one").unwrap(),
+		]
+		.into_iter()
+		.collect::<FfiBoxedSlice<_>>();
+		let empty = FfiBoxedSlice::<MetadataFieldValue>::default();
+		let list = [
+			MetadataFieldValue::from(inner),
+			MetadataFieldValue::from(empty),
+		]
+		.into_iter()
+		.collect::<FfiBoxedSlice<_>>();
+		(
+			MetadataFieldValue::from(list),
+			serde_json::json!([[1, "one"], []]),
+		)
+	}
+
+	#[test]
+	fn nested_list_to_sql_value() {
+		let (list, expected) = nested_list();
+		let Value::Text(json) = Value::from(list) else {
+			panic!("the list was not converted to text");
+		};
+		assert_eq!(
+			serde_json::from_str::<serde_json::Value>(&json).unwrap(),
+			expected
+		);
+	}
+
+	#[test]
+	fn nested_list_to_json_value() {
+		let (list, expected) = nested_list();
+		let serde_json::Value::String(json) = serde_json::Value::from(list) else {
+			panic!("the list was not converted to a string");
+		};
+		assert_eq!(
+			serde_json::from_str::<serde_json::Value>(&json).unwrap(),
+			expected
+		);
+	}
+
+	#[test]
+	fn nested_list_conversions_match_to_sql() {
+		let (list, _) = nested_list();
+		let ToSqlOutput::Owned(to_sql) = list.to_sql().unwrap() else {
+			panic!("the list was not converted to an owned value");
+		};
+		assert_eq!(Value::from(nested_list().0), to_sql);
+	}
+
+	#[test]
+	fn accessors_match_their_variant() {
+		let date = Date::from_calendar_date(2004, time::Month::May, 1).unwrap();
+		let time = Time::from_hms(12, 34, 56).unwrap();
+		let duration = Duration::milliseconds(1500);
+		assert_eq!(MetadataFieldValue::from(7).as_i64(), Some(7));
+		assert_eq!(MetadataFieldValue::from(1.5).as_f64(), Some(1.5));
+		assert_eq!(
+			MetadataFieldValue::try_from("text").unwrap().as_str(),
+			Some("text")
+		);
+		assert_eq!(
+			MetadataFieldValue::from(&b"blob"[..]).as_bytes(),
+			Some(&b"blob"[..])
+		);
+		assert_eq!(MetadataFieldValue::from(true).as_bool(), Some(true));
+		assert_eq!(MetadataFieldValue::from(date).as_date(), Some(date));
+		assert_eq!(MetadataFieldValue::from(time).as_time(), Some(time));
+		assert_eq!(
+			MetadataFieldValue::from(duration).as_duration(),
+			Some(duration)
+		);
+		let (list, _) = nested_list();
+		assert_eq!(list.as_list().map(<[_]>::len), Some(2));
+	}
+
+	#[test]
+	fn accessors_reject_other_variants() {
+		let text = MetadataFieldValue::try_from("7").unwrap();
+		assert_eq!(text.as_i64(), None);
+		assert_eq!(text.as_f64(), None);
+		assert_eq!(text.as_bytes(), None);
+		assert_eq!(text.as_bool(), None);
+		assert_eq!(text.as_date(), None);
+		assert_eq!(text.as_time(), None);
+		assert_eq!(text.as_duration(), None);
+		assert!(text.as_list().is_none());
+		assert_eq!(MetadataFieldValue::from(7).as_str(), None);
+		assert_eq!(MetadataFieldValue::Date(i32::MAX).as_date(), None);
+	}
+}
+
 #[doc(hidden)]
 #[macro_export]
 macro_rules! new_metadata_field {
@@ -260,23 +651,30 @@ macro_rules! new_metadata_field {
 		$crate::media::new_metadata_field!($name, $ident, $type, true)
 	};
 	($name:literal, $ident:ident, $type:ident, $is_list:expr) => {
-		$crate::media::MetadataField {
-			name: $crate::ffi::new_ffistr!($name),
-			ident: $crate::ffi::new_ffistr!(::core::stringify!($ident)),
-			r#type: $crate::media::MetadataFieldType::$type,
-			is_list: $is_list,
-		}
+		$crate::media::MetadataFieldBuilder::new(
+			$crate::ffi::new_ffistr!(::core::stringify!($ident)),
+			$crate::ffi::new_ffistr!($name),
+			$crate::media::MetadataFieldType::$type,
+		)
+		.list($is_list)
+		.build()
 	};
 }
 /// Utility macro that creates a media plugin
 #[macro_export]
 macro_rules! make_plugin {
 	(
-		$media_ident:ident $media_name:literal ;
+		$media_ident:ident $media_name:literal
+		$( icon $media_icon:literal )?
+		$( description $media_description:literal )? ;
 		$( $field_ident:ident $field_name:literal : $( $field_type:ident )+ ),* $(,)?
 	) => {
 		$crate::media::assert_plugin!();
 
+		/// Fingerprint of the ABI of the plugin library
+		#[no_mangle]
+		pub static ABI_FINGERPRINT: u64 = $crate::media::ABI_FINGERPRINT;
+
 		/// Version of the plugin library
 		#[no_mangle]
 		pub static PLUGLIB_VERSION: $crate::Version = $crate::media::PLUGLIB_VERSION;
@@ -284,19 +682,26 @@ macro_rules! make_plugin {
 		/// Returns the plugin's version
 		#[no_mangle]
 		pub extern "C" fn plugin_version() -> $crate::Version {
-			::core::env!("CARGO_PKG_VERSION").parse().unwrap_or_default()
+			$crate::Version::parse_lenient(::core::env!("CARGO_PKG_VERSION")).unwrap_or_default()
+		}
+
+		/// Sets the callback to forward log records to
+		#[no_mangle]
+		pub extern "C" fn set_logger(callback: $crate::log::LogCallback) {
+			$crate::log::set_callback(callback);
 		}
 
 		/// Returns a description of the media type provided by the plugin
 		#[no_mangle]
 		pub extern "C" fn describe_media() -> $crate::media::Media {
-			$crate::media::Media {
-				name: $crate::ffi::new_ffistr!($media_name),
-				ident: $crate::ffi::new_ffistr!(::core::stringify!($media_ident)),
-				fields: $crate::ffi::FfiBoxedSlice::from(Box::from([
-					$( $crate::media::new_metadata_field!($field_ident $field_name : $( $field_type )+) ),*
-				].as_slice())),
-			}
+			$crate::media::MediaBuilder::new(
+				$crate::ffi::new_ffistr!(::core::stringify!($media_ident)),
+				$crate::ffi::new_ffistr!($media_name),
+			)
+			$( .icon($crate::ffi::new_ffistr!($media_icon)) )?
+			$( .description($crate::ffi::new_ffistr!($media_description)) )?
+			$( .field($crate::media::new_metadata_field!($field_ident $field_name : $( $field_type )+)) )*
+			.build()
 		}
 	};
 }
@@ -304,13 +709,26 @@ macro_rules! make_plugin {
 /// Asserts that the media plugin export the correct symbols
 ///
 /// You need not use this macro if you created your plugin with [`make_plugin`].
+/// The optional symbols that the plugin exports can be asserted with `assert_plugin!(optional: ...)`,
+/// e.g. `assert_plugin!(optional: supported_extensions, extract_raw_metadata)`.
 #[macro_export]
 macro_rules! assert_plugin {
+	(optional: $( $symbol:ident ),+ $(,)?) => {
+		$( $crate::media::assert_plugin!(@optional $symbol); )+
+	};
+	(@optional supported_extensions) => {
+		const _: $crate::media::SupportedExtensions = supported_extensions;
+	};
+	(@optional extract_raw_metadata) => {
+		const _: $crate::media::ExtractRawMetadata = extract_raw_metadata;
+	};
 	() => {
 		#[doc(hidden)]
 		mod asserts {
 			use super::*;
 
+			static _ASSERT_ABI_FINGERPRINT: u64 = ABI_FINGERPRINT;
+
 			static _ASSERT_PLUGLIB_VERSION: $crate::Version = PLUGLIB_VERSION;
 
 			const _ASSERT_PLUGIN_VERSION: $crate::PluginVersion = plugin_version;
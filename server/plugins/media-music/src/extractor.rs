@@ -0,0 +1,288 @@
+//! Provides the backends extracting the metadata of music files
+
+use pluglib::{
+	ffi::{FfiMap, FfiStr},
+	log::plugin_log,
+};
+use std::path::Path;
+
+mod ffprobe;
+#[cfg(feature = "native")]
+mod native;
+
+/// Metadata of a music file, as read by an [`Extractor`]
+#[derive(Debug, Default)]
+pub(crate) struct Metadata {
+	/// Title of the music
+	pub(crate) title: Option<Box<str>>,
+	/// Artists of the music
+	pub(crate) artists: Vec<Box<str>>,
+	/// Artist of the album, or of the music if absent
+	pub(crate) album_artist: Option<Box<str>>,
+	/// Title of the album
+	pub(crate) album: Option<Box<str>>,
+	/// Number of the track in the album
+	pub(crate) track: Option<i64>,
+	/// Genres of the music
+	pub(crate) genres: Vec<Box<str>>,
+	/// Year of release
+	pub(crate) year: Option<i64>,
+	/// Cover embedded in the file
+	pub(crate) cover: Option<Box<[u8]>>,
+	/// Lyrics embedded in the file
+	pub(crate) lyrics: Option<Box<str>>,
+	/// ReplayGain of the track, in dB
+	pub(crate) replaygain_track: Option<f64>,
+	/// ReplayGain of the album, in dB
+	pub(crate) replaygain_album: Option<f64>,
+	/// MusicBrainz identifier of the track
+	pub(crate) mb_track_id: Option<Box<str>>,
+	/// MusicBrainz identifier of the album
+	pub(crate) mb_album_id: Option<Box<str>>,
+	/// MusicBrainz identifier of the artist
+	pub(crate) mb_artist_id: Option<Box<str>>,
+	/// Duration of the music, in seconds
+	pub(crate) duration: Option<f64>,
+}
+
+/// Backend extracting the metadata of music files
+pub(crate) trait Extractor: Sync {
+	/// Initializes the backend with the options of the plugin
+	#[inline]
+	fn init(&self, _options: &FfiMap) {}
+
+	/// Lists the MIME types supported by the backend
+	fn supported_types(&self) -> Vec<FfiStr<'static>>;
+
+	/// Extracts the metadata of the given file
+	///
+	/// # Errors
+	/// This function returns a description of the failure if the metadata cannot be extracted.
+	fn extract(&self, path: &Path) -> Result<Metadata, String>;
+
+	/// Extracts the raw metadata of the given file, serialized as JSON
+	#[inline]
+	fn extract_raw(&self, _path: &Path) -> Option<String> {
+		None
+	}
+}
+
+/// Name of the group of the albums of various artists
+const VARIOUS_ARTISTS: &str = "Various Artists";
+
+/// Reads the album artist, falling back to the artist
+///
+/// Compilations are all put under [`VARIOUS_ARTISTS`].
+pub(crate) fn album_artist(album_artist: Option<&str>, artist: Option<&str>) -> Option<Box<str>> {
+	let album_artist = album_artist
+		.or(artist)
+		.map(str::trim)
+		.filter(|s| !s.is_empty())?;
+	Some(
+		if ["various artists", "various", "va", "v.a."]
+			.iter()
+			.any(|various| album_artist.eq_ignore_ascii_case(various))
+		{
+			VARIOUS_ARTISTS.into()
+		} else {
+			album_artist.into()
+		},
+	)
+}
+
+/// Parses a `ReplayGain` tag value (e.g. `-6.35 dB`) into a number of dB
+///
+/// Malformed values are ignored with a warning.
+pub(crate) fn parse_gain(value: &str) -> Option<f64> {
+	let value = value.trim();
+	let value = value
+		.strip_suffix("dB")
+		.or_else(|| value.strip_suffix("db"))
+		.unwrap_or(value);
+	let gain = value
+		.trim()
+		.parse::<f64>()
+		.ok()
+		.filter(|gain| gain.is_finite());
+	if gain.is_none() {
+		plugin_log!(Warn, "Ignoring the malformed ReplayGain value {value:?}");
+	}
+	gain
+}
+
+/// Parses the number at the start of a tag value
+///
+/// It reads track numbers followed by the total (e.g. `3/12`), and years of dates (e.g. `2004-05-01`).
+/// Values that do not start with a number are ignored.
+pub(crate) fn parse_leading_number(value: &str) -> Option<i64> {
+	let value = value.trim_start();
+	let end = value
+		.find(|c: char| !c.is_ascii_digit())
+		.unwrap_or(value.len());
+	value[..end].parse().ok()
+}
+
+/// Parses genre tag values
+///
+/// Each value may hold several genres separated by semicolons.
+/// Duplicates are removed, keeping the first occurrence.
+pub(crate) fn parse_genres<'v>(values: impl IntoIterator<Item = &'v str>) -> Vec<Box<str>> {
+	unique(values.into_iter().flat_map(|value| value.split(';')))
+}
+
+/// Parses artist tag values
+///
+/// Values of a multi-value tag (e.g. `ARTISTS`) are split on semicolons and nul characters.
+/// Values of a single-value tag (e.g. `ARTIST`) are also split on slashes and featurings (` feat. `).
+/// Duplicates are removed, keeping the first occurrence.
+pub(crate) fn parse_artists<'v>(
+	values: impl IntoIterator<Item = &'v str>,
+	multi_value: bool,
+) -> Vec<Box<str>> {
+	/// Separator of featured artists, in lowercase
+	const FEATURING: &str = " feat. ";
+
+	unique(
+		values
+			.into_iter()
+			.flat_map(|value| value.split(|c| c == ';' || c == '\0' || (!multi_value && c == '/')))
+			.flat_map(|artist| {
+				let mut artists = Vec::new();
+				let mut start = 0;
+				if !multi_value {
+					// NOTE: ASCII lowercasing keeps the byte offsets
+					for (i, _) in artist.to_ascii_lowercase().match_indices(FEATURING) {
+						artists.push(&artist[start..i]);
+						start = i + FEATURING.len();
+					}
+				}
+				artists.push(&artist[start..]);
+				artists
+			}),
+	)
+}
+
+/// Trims the given values, and removes the empty ones and the duplicates
+///
+/// The first occurrence of each value is kept.
+fn unique<'v>(values: impl IntoIterator<Item = &'v str>) -> Vec<Box<str>> {
+	let mut unique = Vec::<Box<str>>::new();
+	for value in values
+		.into_iter()
+		.map(str::trim)
+		.filter(|value| !value.is_empty())
+	{
+		if !unique.iter().any(|known| **known == *value) {
+			unique.push(value.into());
+		}
+	}
+	unique
+}
+
+/// Parses a `MusicBrainz` identifier, which is a UUID
+///
+/// If the tag holds several identifiers, only the first one is kept.
+/// Malformed values are ignored.
+pub(crate) fn parse_mbid(value: &str) -> Option<Box<str>> {
+	let value = value.split([';', '/', ',']).next()?.trim();
+	let is_uuid = value.len() == 36
+		&& value.char_indices().all(|(i, c)| match i {
+			8 | 13 | 18 | 23 => c == '-',
+			_ => c.is_ascii_hexdigit(),
+		});
+	is_uuid.then(|| value.to_ascii_lowercase().into())
+}
+
+/// Returns the selected backend
+///
+/// The backend defaults to `ffprobe`.
+/// If the plugin is built with the `native` feature,
+/// setting the `AEPA_MUSIC_EXTRACTOR` environment variable to `native`
+/// selects a pure-Rust backend that spawns no process.
+pub(crate) fn get() -> &'static dyn Extractor {
+	#[cfg(feature = "native")]
+	if std::env::var("AEPA_MUSIC_EXTRACTOR").is_ok_and(|s| s.eq_ignore_ascii_case("native")) {
+		return &native::Native;
+	}
+	&ffprobe::Ffprobe
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn single_artist() {
+		assert_eq!(
+			parse_artists(["Daft Punk"], false),
+			[Box::from("Daft Punk")]
+		);
+	}
+
+	#[test]
+	fn artists_separated_by_semicolons() {
+		assert_eq!(
+			parse_artists(["Simon; Garfunkel;;Simon "], false),
+			[Box::from("Simon"), Box::from("Garfunkel")]
+		);
+	}
+
+	#[test]
+	fn featured_artists() {
+		assert_eq!(
+			parse_artists(["Gorillaz Feat. De La Soul feat. Gorillaz"], false),
+			[Box::from("Gorillaz"), Box::from("De La Soul")]
+		);
+	}
+
+	#[test]
+	fn dedicated_artists_tag() {
+		assert_eq!(
+			parse_artists(["AC/DC;Brian Johnson feat. Someone\0AC/DC"], true),
+			[Box::from("AC/DC"), Box::from("Brian Johnson feat. Someone")]
+		);
+	}
+
+	#[test]
+	fn unique_keeps_first_occurrences() {
+		assert_eq!(
+			unique([" b", "a", "", "b ", "  ", "a"]),
+			[Box::from("b"), Box::from("a")]
+		);
+	}
+
+	#[test]
+	fn valid_mbid() {
+		assert_eq!(
+			parse_mbid(" 5B11F4CE-A62D-471E-81FC-A69A8278C7DA ").as_deref(),
+			Some("5b11f4ce-a62d-471e-81fc-a69a8278c7da")
+		);
+		assert_eq!(
+			parse_mbid(
+				"5b11f4ce-a62d-471e-81fc-a69a8278c7da; 056e4f3e-d505-4dad-8ec1-d04f521cbb56"
+			)
+			.as_deref(),
+			Some("5b11f4ce-a62d-471e-81fc-a69a8278c7da")
+		);
+	}
+
+	#[test]
+	fn missing_mbid() {
+		assert_eq!(parse_mbid(""), None);
+		assert_eq!(parse_mbid("  "), None);
+	}
+
+	#[test]
+	fn malformed_mbid() {
+		for value in [
+			"5b11f4ce-a62d-471e-81fc-a69a8278c7d",
+			"5b11f4ce-a62d-471e-81fc-a69a8278c7dab",
+			"5b11f4cea62d471e81fca69a8278c7da",
+			"5b11f4ce_a62d_471e_81fc_a69a8278c7da",
+			"5b11f4ce-a62d-471e-81fc-a69a8278c7dg",
+			"https://musicbrainz.org/recording/5b11f4ce-a62d-471e-81fc-a69a8278c7da",
+		] {
+			assert_eq!(parse_mbid(value), None, "{value:?}");
+		}
+	}
+}
@@ -0,0 +1,404 @@
+//! Provides the [`Ffprobe`] backend
+
+use super::{
+	album_artist, parse_artists, parse_gain, parse_genres, parse_leading_number, parse_mbid,
+	Extractor, Metadata,
+};
+use pluglib::{
+	ffi::{new_ffistr, FfiMap, FfiStr},
+	log::plugin_log,
+};
+use serde::Deserialize;
+use std::{
+	borrow::Cow,
+	collections::HashMap,
+	io,
+	path::{Path, PathBuf},
+	process::{Command, Stdio},
+	sync::OnceLock,
+};
+
+/// Path of the `ffprobe` binary
+static FFPROBE: OnceLock<PathBuf> = OnceLock::new();
+/// Path of the `ffmpeg` binary
+static FFMPEG: OnceLock<PathBuf> = OnceLock::new();
+/// MIME types of `ffprobe` formats, read from the `formats` option
+///
+/// The MIME types are nul-terminated, so that they can be passed through FFI.
+static FORMATS: OnceLock<HashMap<Box<str>, Vec<Box<str>>>> = OnceLock::new();
+
+/// Finds a binary
+///
+/// Its path is read from the option with the same name (e.g. `ffprobe`),
+/// from the `AEPA_<NAME>` environment variable (e.g. `AEPA_FFPROBE`),
+/// or searched in the `PATH`.
+/// If it is not found, the bare name is returned.
+fn find_binary(name: &str, options: &FfiMap) -> PathBuf {
+	let path = options
+		.get(name)
+		.map(PathBuf::from)
+		.or_else(|| std::env::var_os(format!("AEPA_{}", name.to_uppercase())).map(PathBuf::from))
+		.or_else(|| {
+			let file_name = format!("{name}{}", std::env::consts::EXE_SUFFIX);
+			std::env::split_paths(&std::env::var_os("PATH")?)
+				.map(|dir| dir.join(&file_name))
+				.find(|path| path.is_file())
+		})
+		.unwrap_or_else(|| name.into());
+	plugin_log!(Debug, "Using {}", path.display());
+	path
+}
+
+/// Parses the MIME types of `ffprobe` formats
+///
+/// Entries are separated by semicolons, and are made of a format and comma-separated MIME types
+/// separated by an equals sign (e.g. `aiff=audio/aiff,audio/x-aiff;wv=audio/x-wavpack`).
+/// Invalid entries are skipped.
+fn parse_formats(s: &str) -> HashMap<Box<str>, Vec<Box<str>>> {
+	s.split(';')
+		.filter(|entry| !entry.trim().is_empty())
+		.filter_map(|entry| {
+			let Some((format, mimes)) = entry.split_once('=') else {
+				plugin_log!(Warn, "Ignoring the format {entry:?} without MIME types");
+				return None;
+			};
+			let mimes = mimes
+				.split(',')
+				.map(str::trim)
+				.filter(|mime| !mime.is_empty() && !mime.contains('\0'))
+				.map(|mime| format!("{mime}\0").into_boxed_str())
+				.collect::<Vec<_>>();
+			Some((format.trim().into(), mimes))
+		})
+		.collect()
+}
+
+/// Returns the MIME types of `ffprobe` formats that supplement the built-in ones
+#[inline]
+fn formats() -> &'static HashMap<Box<str>, Vec<Box<str>>> {
+	FORMATS.get_or_init(HashMap::new)
+}
+
+/// Returns the path of the `ffprobe` binary
+#[inline]
+fn ffprobe() -> &'static Path {
+	FFPROBE.get_or_init(|| find_binary("ffprobe", &FfiMap::default()))
+}
+
+/// Returns the path of the `ffmpeg` binary
+#[inline]
+fn ffmpeg() -> &'static Path {
+	FFMPEG.get_or_init(|| find_binary("ffmpeg", &FfiMap::default()))
+}
+
+/// Backend running `ffprobe` and `ffmpeg`
+#[derive(Debug, Clone, Copy)]
+pub(super) struct Ffprobe;
+impl Extractor for Ffprobe {
+	/// Resolves the paths of the binaries, and reads the MIME types of formats.
+	fn init(&self, options: &FfiMap) {
+		FFPROBE.get_or_init(|| find_binary("ffprobe", options));
+		FFMPEG.get_or_init(|| find_binary("ffmpeg", options));
+		FORMATS.get_or_init(|| parse_formats(options.get("formats").unwrap_or_default()));
+	}
+
+	/// The MIME types of the `formats` option replace the built-in ones of the same formats.
+	fn supported_types(&self) -> Vec<FfiStr<'static>> {
+		Command::new(ffprobe())
+			.args(["-v", "quiet", "-formats"])
+			.stdin(Stdio::null())
+			.stderr(Stdio::null())
+			.output()
+			.map(|out| out.stdout)
+			.and_then(|out| {
+				String::from_utf8(out).map_err(|err| io::Error::new(io::ErrorKind::Other, err))
+			})
+			.map(|data| {
+				data.lines()
+					.skip(4)
+					.filter_map(|s| {
+						let format = s.trim().split_ascii_whitespace().nth(1)?;
+						if let Some(mimes) = formats().get(format) {
+							return Some(
+								mimes
+									.iter()
+									.filter_map(|mime| FfiStr::new(mime).ok())
+									.collect(),
+							);
+						}
+						/// Generates match branches for the given formats
+						macro_rules! match_format {
+							($(
+								$format:expr => [$( $mime:literal ),+ $(,)?]
+							),* $(,)?) => {
+								match format {
+									$(
+										$format => Some(vec![$( new_ffistr!($mime) ),+]),
+									)*
+									_ => None,
+								}
+							};
+						}
+						match_format! {
+							"acc" => ["audio/aac"],
+							"adts" => ["audio/aac", "audio/aacp"],
+							"caf" => ["audio/x-caf"],
+							"flac" => ["audio/flac"],
+							"matroska,webm" => ["audio/webm"],
+							"mp3" => ["audio/mp3", "audio/mpeg"],
+							"ogg" => ["audio/ogg"],
+							"wav" => ["audio/wav", "audio/x-wav"],
+						}
+					})
+					.flatten()
+					.collect()
+			})
+			.unwrap_or_default()
+	}
+
+	fn extract(&self, path: &Path) -> Result<Metadata, String> {
+		let metadata = parse_metadata(&probe(path, &["-show_format"])?)?;
+		Ok(Metadata {
+			cover: extract_embedded_cover(path),
+			..metadata
+		})
+	}
+
+	/// This is the full output of `ffprobe`, including the streams.
+	fn extract_raw(&self, path: &Path) -> Option<String> {
+		probe(path, &["-show_format", "-show_streams"])
+			.ok()
+			.and_then(|output| String::from_utf8(output).ok())
+	}
+}
+
+/// Parses the metadata from the JSON output of `ffprobe`
+///
+/// The cover is not part of the output, it is extracted by [`extract_embedded_cover`].
+fn parse_metadata(output: &[u8]) -> Result<Metadata, String> {
+	let data = serde_json::from_slice::<FfprobeData>(output)
+		.map_err(|err| format!("could not parse the output of ffprobe: {err}"))?;
+
+	let tags = &data.format.tags;
+
+	Ok(Metadata {
+		title: tags.get(&["title"]).map(Box::from),
+		artists: match tags.get(&["artists"]) {
+			Some(artists) => parse_artists([artists], true),
+			None => parse_artists(tags.get(&["artist"]), false),
+		},
+		album_artist: album_artist(
+			tags.get(&["album_artist", "albumartist", "album artist"]),
+			tags.get(&["artist"]),
+		),
+		album: tags.get(&["album"]).map(Box::from),
+		track: tags
+			.get(&["track", "tracknumber"])
+			.and_then(parse_leading_number),
+		genres: parse_genres(tags.get(&["genre"])),
+		year: tags
+			.get(&["date", "year", "originaldate"])
+			.and_then(parse_leading_number),
+		cover: None,
+		lyrics: tags
+			.get(&["lyrics", "unsyncedlyrics"])
+			// ID3 `USLT` frames are suffixed with their language
+			.or_else(|| tags.find(|key| key.to_ascii_lowercase().starts_with("lyrics-")))
+			.map(Box::from),
+		replaygain_track: tags.get(&["replaygain_track_gain"]).and_then(parse_gain),
+		replaygain_album: tags.get(&["replaygain_album_gain"]).and_then(parse_gain),
+		mb_track_id: tags
+			.get(&["musicbrainz_trackid", "musicbrainz track id"])
+			.and_then(parse_mbid),
+		mb_album_id: tags
+			.get(&["musicbrainz_albumid", "musicbrainz album id"])
+			.and_then(parse_mbid),
+		mb_artist_id: tags
+			.get(&["musicbrainz_artistid", "musicbrainz artist id"])
+			.and_then(parse_mbid),
+		duration: data
+			.format
+			.duration
+			.and_then(|duration| duration.parse::<f64>().ok())
+			.filter(|duration| duration.is_finite()),
+	})
+}
+
+/// Root structure of `ffprobe` output
+///
+/// Missing objects default to empty ones, so that a file without tags is still indexed.
+#[derive(Default, Deserialize)]
+struct FfprobeData<'data> {
+	#[serde(borrow, default)]
+	format: FfprobeDataFormat<'data>,
+}
+
+/// Structure of [`FfprobeData.format`](FfprobeData#structfield.format)
+#[derive(Default, Deserialize)]
+struct FfprobeDataFormat<'data> {
+	/// Duration in seconds, as a decimal string
+	duration: Option<&'data str>,
+	#[serde(borrow, default)]
+	tags: FfprobeDataFormatTags<'data>,
+}
+
+/// Structure of [`FfprobeDataFormat.tags`](FfprobeDataFormat#structfield.tags)
+#[derive(Default, Deserialize)]
+struct FfprobeDataFormatTags<'data> {
+	/// Tags, whose keys vary between containers
+	#[serde(flatten, borrow)]
+	entries: HashMap<&'data str, Cow<'data, str>>,
+}
+impl<'data> FfprobeDataFormatTags<'data> {
+	/// Returns the value of the first tag whose key matches the given predicate
+	fn find(&self, predicate: impl Fn(&str) -> bool) -> Option<&str> {
+		self.entries
+			.iter()
+			.find_map(|(key, value)| predicate(key).then_some(&**value))
+	}
+
+	/// Returns the value of the tag with one of the given keys, ignoring case
+	///
+	/// The keys are tried in the given order.
+	fn get(&self, keys: &[&str]) -> Option<&str> {
+		keys.iter()
+			.find_map(|wanted| self.find(|key| key.eq_ignore_ascii_case(wanted)))
+	}
+}
+
+/// Runs `ffprobe` on the given media file and returns its JSON output
+fn probe(path: &Path, args: &[&str]) -> Result<Vec<u8>, String> {
+	let output = Command::new(ffprobe())
+		.args(["-v", "quiet", "-print_format", "json"])
+		.args(args)
+		.arg(path)
+		.stdin(Stdio::null())
+		.stderr(Stdio::null())
+		.output()
+		.map_err(|err| format!("could not run ffprobe: {err}"))?;
+	if output.status.success() {
+		Ok(output.stdout)
+	} else {
+		Err(format!("ffprobe exited with {}", output.status))
+	}
+}
+
+/// Extracts the cover embedded in the given media file
+fn extract_embedded_cover(path: &Path) -> Option<Box<[u8]>> {
+	let output = Command::new(ffmpeg())
+		.args(["-v", "quiet", "-i"])
+		.arg(path)
+		.args([
+			"-an",
+			"-c:v",
+			"copy",
+			"-frames:v",
+			"1",
+			"-f",
+			"image2pipe",
+			"-",
+		])
+		.stdin(Stdio::null())
+		.stderr(Stdio::null())
+		.output()
+		.ok()?;
+	(output.status.success() && !output.stdout.is_empty()).then(|| output.stdout.into_boxed_slice())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn track_with_total_and_full_date() {
+		let metadata = parse_metadata(
+			br#"{
+				"format": {
+					"duration": "215.040000",
+					"tags": {
+						"title": "Song",
+						"track": "3/12",
+						"date": "2004-05-01",
+						"genre": "Rock;Pop; Rock"
+					}
+				}
+			}"#,
+		)
+		.unwrap();
+		assert_eq!(metadata.track, Some(3));
+		assert_eq!(metadata.year, Some(2004));
+		assert_eq!(metadata.genres, [Box::from("Rock"), Box::from("Pop")]);
+		assert_eq!(metadata.duration, Some(215.04));
+	}
+
+	#[test]
+	fn uppercase_tags() {
+		let metadata = parse_metadata(
+			br#"{
+				"format": {
+					"tags": {
+						"TRACK": "07",
+						"YEAR": "1999",
+						"GENRE": "Jazz"
+					}
+				}
+			}"#,
+		)
+		.unwrap();
+		assert_eq!(metadata.track, Some(7));
+		assert_eq!(metadata.year, Some(1999));
+		assert_eq!(metadata.genres, [Box::from("Jazz")]);
+	}
+
+	#[test]
+	fn uppercase_title_and_artist() {
+		let metadata = parse_metadata(
+			br#"{
+				"format": {
+					"tags": {
+						"TITLE": "Song",
+						"ARTIST": "Band"
+					}
+				}
+			}"#,
+		)
+		.unwrap();
+		assert_eq!(metadata.title.as_deref(), Some("Song"));
+		assert_eq!(metadata.artists, [Box::from("Band")]);
+		assert_eq!(metadata.album_artist.as_deref(), Some("Band"));
+	}
+
+	#[test]
+	fn missing_tags() {
+		for output in [
+			&b"{}"[..],
+			br#"{"format": {}}"#,
+			br#"{"format": {"tags": {"track": "", "date": "unknown"}}}"#,
+		] {
+			let metadata = parse_metadata(output).unwrap();
+			assert_eq!(metadata.track, None);
+			assert_eq!(metadata.year, None);
+			assert!(metadata.genres.is_empty());
+			assert_eq!(metadata.duration, None);
+		}
+	}
+
+	#[test]
+	fn formats_are_parsed() {
+		let formats = parse_formats(" aiff = audio/aiff, audio/x-aiff ;wv=audio/x-wavpack;");
+		assert_eq!(formats.len(), 2);
+		assert_eq!(
+			formats["aiff"],
+			[Box::from("audio/aiff\0"), Box::from("audio/x-aiff\0")]
+		);
+		assert_eq!(formats["wv"], [Box::from("audio/x-wavpack\0")]);
+	}
+
+	#[test]
+	fn invalid_formats_are_skipped() {
+		let formats = parse_formats("aiff;wv=audio/x-wavpack,,audio/\0wv;;");
+		assert_eq!(formats.len(), 1);
+		assert_eq!(formats["wv"], [Box::from("audio/x-wavpack\0")]);
+		assert!(parse_formats("").is_empty());
+	}
+}
@@ -0,0 +1,78 @@
+//! Provides the [`Native`] backend
+
+use super::{
+	album_artist, parse_artists, parse_gain, parse_genres, parse_leading_number, parse_mbid,
+	Extractor, Metadata,
+};
+use lofty::{Accessor, AudioFile, ItemKey, PictureType, TaggedFileExt};
+use pluglib::ffi::{new_ffistr, FfiStr};
+use std::path::Path;
+
+/// Backend reading the tags with [`lofty`], without spawning any process
+#[derive(Debug, Clone, Copy)]
+pub(super) struct Native;
+impl Extractor for Native {
+	fn supported_types(&self) -> Vec<FfiStr<'static>> {
+		vec![
+			new_ffistr!("audio/aac"),
+			new_ffistr!("audio/aacp"),
+			new_ffistr!("audio/flac"),
+			new_ffistr!("audio/mp3"),
+			new_ffistr!("audio/mpeg"),
+			new_ffistr!("audio/ogg"),
+			new_ffistr!("audio/wav"),
+			new_ffistr!("audio/x-wav"),
+		]
+	}
+
+	fn extract(&self, path: &Path) -> Result<Metadata, String> {
+		let file = lofty::read_from_path(path).map_err(|err| err.to_string())?;
+		let duration = Some(file.properties().duration().as_secs_f64());
+		let Some(tag) = file.primary_tag().or_else(|| file.first_tag()) else {
+			return Ok(Metadata {
+				duration,
+				..Default::default()
+			});
+		};
+
+		Ok(Metadata {
+			title: tag.title().map(Box::from),
+			artists: parse_artists(tag.get_strings(&ItemKey::TrackArtist), false),
+			album_artist: album_artist(
+				tag.get_string(&ItemKey::AlbumArtist),
+				tag.artist().as_deref(),
+			),
+			album: tag.album().map(Box::from),
+			track: tag.track().map(i64::from),
+			genres: parse_genres(tag.get_strings(&ItemKey::Genre)),
+			year: tag.year().map(i64::from).or_else(|| {
+				tag.get_string(&ItemKey::RecordingDate)
+					.and_then(parse_leading_number)
+			}),
+			cover: tag
+				.pictures()
+				.iter()
+				.find(|picture| picture.pic_type() == PictureType::CoverFront)
+				.or_else(|| tag.pictures().first())
+				.map(|picture| Box::from(picture.data())),
+			lyrics: tag.get_string(&ItemKey::Lyrics).map(Box::from),
+			replaygain_track: tag
+				.get_string(&ItemKey::ReplayGainTrackGain)
+				.and_then(parse_gain),
+			replaygain_album: tag
+				.get_string(&ItemKey::ReplayGainAlbumGain)
+				.and_then(parse_gain),
+			mb_track_id: tag
+				.get_string(&ItemKey::MusicBrainzRecordingId)
+				.or_else(|| tag.get_string(&ItemKey::MusicBrainzTrackId))
+				.and_then(parse_mbid),
+			mb_album_id: tag
+				.get_string(&ItemKey::MusicBrainzReleaseId)
+				.and_then(parse_mbid),
+			mb_artist_id: tag
+				.get_string(&ItemKey::MusicBrainzArtistId)
+				.and_then(parse_mbid),
+			duration,
+		})
+	}
+}
@@ -70,115 +70,253 @@
 #![forbid(clippy::undocumented_unsafe_blocks)]
 
 use pluglib::{
-	ffi::{new_ffistr, FfiBoxedSlice, FfiOption, FfiResult, FfiStr},
+	ffi::{FfiBoxedSlice, FfiError, FfiOption, FfiResult, FfiStr, FfiString},
 	media::*,
+	PluginConfig,
 };
-use serde::Deserialize;
 use std::{
-	io,
-	process::{Command, Stdio},
+	collections::HashMap,
+	path::{Path, PathBuf},
+	sync::{Mutex, OnceLock, PoisonError},
+	time::{Duration, SystemTime},
 };
 
+mod extractor;
+
 make_plugin! {
 	music "Music";
 	title "Title": Text,
 	artists "Artists": Text list,
+	cover "Cover": Blob,
+	lyrics "Lyrics": Text,
+	replaygain_track "Track gain": Real,
+	replaygain_album "Album gain": Real,
+	mb_track_id "MusicBrainz track ID": Text,
+	mb_album_id "MusicBrainz album ID": Text,
+	mb_artist_id "MusicBrainz artist ID": Text,
+	album_artist "Album artist": Text,
+	duration "Duration": Duration,
+	album "Album": Text,
+	track "Track": Integer,
+	genre "Genre": Text list,
+	year "Year": Integer,
+}
+
+/// Default names (without extension) of the image files that may contain a cover
+const DEFAULT_COVER_NAMES: &str = "cover,folder,front";
+/// Names (without extension) of the image files that may contain a cover, read from the `covers` option
+static COVER_NAMES: OnceLock<Vec<Box<str>>> = OnceLock::new();
+/// Cover files of directories, with the modification time of the directories when they were searched
+type CoverCache = HashMap<PathBuf, (SystemTime, Option<PathBuf>)>;
+/// Cover files found by [`find_sibling_cover`], by directory
+///
+/// A directory is searched again once files are added to or removed from it,
+/// which changes its modification time.
+static SIBLING_COVERS: OnceLock<Mutex<CoverCache>> = OnceLock::new();
+/// Extensions of the image files that may contain a cover
+const COVER_EXTENSIONS: [&str; 4] = ["jpg", "jpeg", "png", "webp"];
+/// Maximum size (in bytes) of a lyrics file
+const MAX_LYRICS_SIZE: u64 = 1 << 20;
+
+/// Initializes the plugin
+///
+/// The names of the cover files and the selected backend are read from the options of the plugin.
+#[no_mangle]
+pub extern "C" fn plugin_init(config: &PluginConfig) {
+	COVER_NAMES.get_or_init(|| {
+		parse_cover_names(config.options.get("covers").unwrap_or(DEFAULT_COVER_NAMES))
+	});
+	extractor::get().init(&config.options);
 }
 
 /// Lists the types supported by the plugin
 #[no_mangle]
 pub extern "C" fn supported_types() -> FfiBoxedSlice<FfiStr<'static>> {
-	Command::new("ffprobe")
-		.args(["-v", "quiet", "-formats"])
-		.stdin(Stdio::null())
-		.stderr(Stdio::null())
-		.output()
-		.map(|out| out.stdout)
-		.and_then(|out| {
-			String::from_utf8(out).map_err(|err| io::Error::new(io::ErrorKind::Other, err))
-		})
-		.map(|data| {
-			data.lines()
-				.skip(4)
-				.filter_map(|s| {
-					let format = s.trim().split_ascii_whitespace().nth(1)?;
-					/// Generates match branches for the given formats
-					macro_rules! match_format {
-						($(
-							$format:expr => [$( $mime:literal ),+ $(,)?]
-						),* $(,)?) => {
-							match format {
-								$(
-									$format => Some(vec![$( new_ffistr!($mime) ),+]),
-								)*
-								_ => None,
-							}
-						};
-					}
-					match_format! {
-						"acc" => ["audio/aac"],
-						"adts" => ["audio/aac", "audio/aacp"],
-						"caf" => ["audio/x-caf"],
-						"flac" => ["audio/flac"],
-						"matroska,webm" => ["audio/webm"],
-						"mp3" => ["audio/mp3", "audio/mpeg"],
-						"ogg" => ["audio/ogg"],
-						"wav" => ["audio/wav", "audio/x-wav"],
-					}
-				})
-				.flatten()
-				.collect()
-		})
-		.unwrap_or_default()
+	extractor::get().supported_types().into_iter().collect()
 }
 
-/// Root structure of `ffprobe` output
-#[derive(Deserialize)]
-struct FfprobeData<'data> {
-	#[serde(borrow)]
-	format: FfprobeDataFormat<'data>,
+/// Parses the names (without extension) of the image files that may contain a cover
+///
+/// They are separated by commas, ordered by preference, and compared without case.
+fn parse_cover_names(s: &str) -> Vec<Box<str>> {
+	s.split(',')
+		.map(|name| name.trim().to_lowercase().into_boxed_str())
+		.filter(|name| !name.is_empty())
+		.collect()
 }
 
-/// Structure of [`FfprobeData.format`](FfprobeData#structfield.format)
-#[derive(Deserialize)]
-struct FfprobeDataFormat<'data> {
-	#[serde(borrow)]
-	tags: FfprobeDataFormatTags<'data>,
+/// Returns the cover embedded in the given media file, or else the one next to it
+fn find_cover(embedded: Option<Box<[u8]>>, path: &Path) -> Option<Box<[u8]>> {
+	embedded.or_else(|| find_sibling_cover(path))
+}
+
+/// Finds a cover image next to the given media file
+///
+/// The candidate names (without extension) are read from the `covers` option,
+/// as a comma-separated list ordered by preference.
+/// They default to [`DEFAULT_COVER_NAMES`].
+/// The cover file of each directory is [cached](SIBLING_COVERS), but it is read every time.
+fn find_sibling_cover(path: &Path) -> Option<Box<[u8]>> {
+	let dir = path.parent()?;
+	let modified = std::fs::metadata(dir)
+		.and_then(|meta| meta.modified())
+		.ok()?;
+	let covers = SIBLING_COVERS.get_or_init(Mutex::default);
+
+	let cached = covers
+		.lock()
+		.unwrap_or_else(PoisonError::into_inner)
+		.get(dir)
+		.filter(|(cached_modified, _)| *cached_modified == modified)
+		.map(|(_, cover)| cover.clone());
+	let cover = cached.unwrap_or_else(|| {
+		let cover = search_sibling_cover(dir);
+		covers
+			.lock()
+			.unwrap_or_else(PoisonError::into_inner)
+			.insert(dir.to_owned(), (modified, cover.clone()));
+		cover
+	})?;
+	std::fs::read(cover).ok().map(Vec::into_boxed_slice)
+}
+
+/// Searches a directory for the preferred cover image
+fn search_sibling_cover(dir: &Path) -> Option<PathBuf> {
+	let names = COVER_NAMES.get_or_init(|| parse_cover_names(DEFAULT_COVER_NAMES));
+
+	let mut candidates = std::fs::read_dir(dir)
+		.ok()?
+		.filter_map(|res| res.ok())
+		.map(|entry| entry.path())
+		.filter_map(|path| {
+			let extension = path.extension()?.to_str()?.to_lowercase();
+			if !COVER_EXTENSIONS.contains(&extension.as_str()) {
+				return None;
+			}
+			let stem = path.file_stem()?.to_str()?.to_lowercase();
+			let rank = names.iter().position(|name| **name == *stem)?;
+			Some((rank, path))
+		})
+		.collect::<Vec<_>>();
+	candidates.sort();
+	candidates.into_iter().next().map(|(_, path)| path)
 }
 
-/// Structure of [`FfprobeDataFormat.tags`](FfprobeDataFormat#structfield.tags)
-#[derive(Deserialize)]
-struct FfprobeDataFormatTags<'data> {
-	title: Option<&'data str>,
-	artist: Option<&'data str>,
-	#[serde(alias = "ARTISTS")]
-	artists: Option<&'data str>,
+/// Reads the lyrics file next to the given media file
+///
+/// It must have the same name as the media file with the `lrc` extension.
+/// Files larger than [`MAX_LYRICS_SIZE`] are ignored.
+fn find_sibling_lyrics(path: &Path) -> Option<Box<str>> {
+	let path = path.with_extension("lrc");
+	if std::fs::metadata(&path).ok()?.len() > MAX_LYRICS_SIZE {
+		return None;
+	}
+	std::fs::read_to_string(path)
+		.ok()
+		.filter(|lyrics| !lyrics.trim().is_empty())
+		.map(Box::from)
 }
 
+/// Converts text values into a list value
+///
+/// Without values, the field is left empty.
+fn text_list(values: Vec<Box<str>>) -> Option<MetadataFieldValue> {
+	(!values.is_empty()).then(|| {
+		values
+			.into_iter()
+			.filter_map(|s| MetadataFieldValue::try_from(s).ok())
+			.collect::<FfiBoxedSlice<_>>()
+			.into()
+	})
+}
+
+/// [Code](FfiError#structfield.code) of the errors of the extractor backend
+const EXTRACTOR_ERROR: u32 = 1;
+
 /// Extracts the metadata of the given media file
 #[no_mangle]
 pub extern "C" fn extract_metadata(
 	path: FfiStr<'_>,
-) -> FfiResult<FfiBoxedSlice<FfiOption<MetadataFieldValue>>, ()> {
+) -> FfiResult<FfiBoxedSlice<FfiOption<MetadataFieldValue>>, FfiError> {
 	(|| {
-		let output = Command::new("ffprobe")
-			.args(["-v", "quiet", "-show_format", "-print_format", "json"])
-			.arg(&*path)
-			.stdin(Stdio::null())
-			.stderr(Stdio::null())
-			.output()
-			.map_err(|_err| ())?;
-		if !output.status.success() {
-			return Err(());
-		}
-		let data = serde_json::from_slice::<FfprobeData>(&output.stdout).map_err(|_err| ())?;
-
-		Ok(
-			[data.format.tags.title.and_then(|s| s.try_into().ok()), None]
-				.into_iter()
-				.map(From::from)
-				.collect(),
-		)
+		let path = Path::new(&*path);
+		let metadata = extractor::get()
+			.extract(path)
+			.map_err(|err| FfiError::new(EXTRACTOR_ERROR, err))?;
+		let cover = find_cover(metadata.cover, path);
+		let lyrics = metadata.lyrics.or_else(|| find_sibling_lyrics(path));
+
+		Ok([
+			metadata.title.and_then(|s| s.try_into().ok()),
+			text_list(metadata.artists),
+			cover.map(MetadataFieldValue::from),
+			lyrics.and_then(|s| s.try_into().ok()),
+			metadata.replaygain_track.map(MetadataFieldValue::from),
+			metadata.replaygain_album.map(MetadataFieldValue::from),
+			metadata.mb_track_id.and_then(|s| s.try_into().ok()),
+			metadata.mb_album_id.and_then(|s| s.try_into().ok()),
+			metadata.mb_artist_id.and_then(|s| s.try_into().ok()),
+			metadata.album_artist.and_then(|s| s.try_into().ok()),
+			metadata
+				.duration
+				.and_then(|seconds| Duration::try_from_secs_f64(seconds).ok())
+				.map(MetadataFieldValue::from),
+			metadata.album.and_then(|s| s.try_into().ok()),
+			metadata.track.map(MetadataFieldValue::from),
+			text_list(metadata.genres),
+			metadata.year.map(MetadataFieldValue::from),
+		]
+		.into_iter()
+		.map(From::from)
+		.collect())
 	})()
 	.into()
 }
+
+/// Extracts the raw metadata of the given media file
+#[no_mangle]
+pub extern "C" fn extract_raw_metadata(path: FfiStr<'_>) -> FfiOption<FfiString> {
+	extractor::get()
+		.extract_raw(Path::new(&*path))
+		.and_then(|output| FfiString::new(output).ok())
+		.into()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use tempfile::TempDir;
+
+	/// Creates a temporary directory with a media file, and returns them
+	fn media_dir() -> (TempDir, PathBuf) {
+		let dir = tempfile::tempdir().unwrap();
+		let path = dir.path().join("song.mp3");
+		std::fs::write(&path, "song").unwrap();
+		(dir, path)
+	}
+
+	#[test]
+	fn embedded_cover_only() {
+		let (_dir, path) = media_dir();
+		assert_eq!(
+			find_cover(Some(Box::from(*b"embedded")), &path).as_deref(),
+			Some(&b"embedded"[..])
+		);
+	}
+
+	#[test]
+	fn sibling_cover_only() {
+		let (dir, path) = media_dir();
+		std::fs::write(dir.path().join("Folder.PNG"), "folder").unwrap();
+		std::fs::write(dir.path().join("cover.jpg"), "cover").unwrap();
+		std::fs::write(dir.path().join("cover.txt"), "text").unwrap();
+		assert_eq!(find_cover(None, &path).as_deref(), Some(&b"cover"[..]));
+	}
+
+	#[test]
+	fn no_cover() {
+		let (dir, path) = media_dir();
+		std::fs::write(dir.path().join("back.jpg"), "back").unwrap();
+		assert_eq!(find_cover(None, &path), None);
+	}
+}
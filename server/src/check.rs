@@ -0,0 +1,241 @@
+//! Provides the validation of the configuration without starting the server
+
+use crate::{config::Config, db, plugins::PluginStore, tls::Identity, EXE_NAME};
+use client::reqwest::Url;
+use std::{
+	fmt::{self, Display, Formatter},
+	fs::{self, File, OpenOptions},
+	io,
+	path::{Path, PathBuf},
+};
+
+/// Severity of a [`Problem`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) enum Severity {
+	/// The server can start, but some feature will not work as expected
+	Warning,
+	/// The server cannot start or run properly
+	Fatal,
+}
+
+/// Problem found in the configuration
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Problem {
+	/// Severity of the problem
+	pub(crate) severity: Severity,
+	/// Description of the problem
+	pub(crate) message: String,
+}
+impl Problem {
+	/// Constructs a new [`Warning`](Severity::Warning)
+	#[inline]
+	const fn warning(message: String) -> Self {
+		Self {
+			severity: Severity::Warning,
+			message,
+		}
+	}
+
+	/// Constructs a new [`Fatal`](Severity::Fatal) problem
+	#[inline]
+	const fn fatal(message: String) -> Self {
+		Self {
+			severity: Severity::Fatal,
+			message,
+		}
+	}
+}
+impl Display for Problem {
+	#[inline]
+	fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+		self.message.fmt(f)
+	}
+}
+
+/// Checks the configuration against the environment
+///
+/// Nothing is modified, and the files created to check permissions are removed.
+pub(crate) fn check_config(config: &Config) -> Vec<Problem> {
+	let mut problems = Vec::new();
+
+	if let Some(ref url) = config.public_url {
+		if let Err(err) = Url::parse(url) {
+			problems.push(Problem::fatal(format!(
+				"The public URL {url:?} is invalid: {err}"
+			)));
+		}
+	}
+
+	let mut identity_files = true;
+	for (name, path) in [
+		("certificate", &config.tls.certificate),
+		("private key", &config.tls.key),
+	] {
+		match File::open(path) {
+			Ok(_) => {}
+			Err(ref err) if err.kind() == io::ErrorKind::NotFound => {
+				problems.push(Problem::warning(format!(
+					"The TLS {name} {} does not exist, a new identity will be generated",
+					path.display()
+				)));
+				identity_files = false;
+			}
+			Err(err) => {
+				problems.push(Problem::fatal(format!(
+					"The TLS {name} {} cannot be read: {err}",
+					path.display()
+				)));
+				identity_files = false;
+			}
+		}
+	}
+	if identity_files {
+		match Identity::read(&config.tls.key, &config.tls.certificate)
+			.map_err(Box::<dyn std::error::Error>::from)
+			.and_then(|identity| identity.key_matches())
+		{
+			Ok(true) => {}
+			Ok(false) => {
+				problems.push(Problem::fatal(format!(
+					"The TLS private key {} does not match the certificate {}; if the server generated them, delete both files so that a new identity is generated",
+					config.tls.key.display(),
+					config.tls.certificate.display()
+				)));
+			}
+			Err(err) => {
+				problems.push(Problem::fatal(format!(
+					"The TLS identity cannot be used: {err}"
+				)));
+			}
+		}
+	}
+
+	let db_file = db::path();
+	let db_access = if config.database.read_only {
+		File::open(&db_file)
+	} else if db_file.exists() {
+		OpenOptions::new().write(true).open(&db_file)
+	} else {
+		check_dir_writable(db_file.parent().unwrap_or_else(|| Path::new("")))
+	};
+	if let Err(err) = db_access {
+		problems.push(Problem::fatal(format!(
+			"The database {} cannot be {}: {err}",
+			db_file.display(),
+			if config.database.read_only {
+				"read"
+			} else {
+				"written"
+			}
+		)));
+	}
+
+	let plugin_dirs = PluginStore::get_plugin_dirs();
+	let mut plugin_files = Vec::new();
+	for dir in &plugin_dirs {
+		match fs::read_dir(dir) {
+			Ok(entries) => {
+				plugin_files.extend(entries.filter_map(|res| res.ok()).map(|entry| entry.path()));
+			}
+			Err(ref err) if err.kind() == io::ErrorKind::NotFound => {}
+			Err(err) => {
+				problems.push(Problem::fatal(format!(
+					"The plugins directory {} cannot be read: {err}",
+					dir.display()
+				)));
+			}
+		}
+	}
+
+	for (name, media_config) in &config.media {
+		if config.plugins.disabled.contains(name) {
+			problems.push(Problem::warning(format!(
+				"Library {name} is not served, since its plugin is disabled"
+			)));
+			continue;
+		}
+		if !plugin_files.iter().any(|path| {
+			path.extension().is_some_and(|ext| ext == "media")
+				&& path.file_stem().is_some_and(|stem| *stem == **name)
+		}) {
+			problems.push(Problem::fatal(format!(
+				"Library {name} has no media plugin in {}",
+				plugin_dirs
+					.iter()
+					.map(|dir| dir.display().to_string())
+					.collect::<Vec<_>>()
+					.join(", ")
+			)));
+		}
+		if media_config.paths.is_empty() {
+			problems.push(Problem::warning(format!(
+				"Library {name} has no root directory"
+			)));
+		}
+		for path in &media_config.paths {
+			match fs::metadata(path) {
+				Ok(meta) if meta.is_dir() => {}
+				Ok(_) => {
+					problems.push(Problem::fatal(format!(
+						"The root directory {} of library {name} is not a directory",
+						path.display()
+					)));
+				}
+				Err(err) => {
+					problems.push(Problem::fatal(format!(
+						"The root directory {} of library {name} cannot be read: {err}",
+						path.display()
+					)));
+				}
+			}
+		}
+
+		let ffprobe = media_config
+			.options
+			.get("ffprobe")
+			.map(PathBuf::from)
+			.or_else(|| std::env::var_os("AEPA_FFPROBE").map(PathBuf::from))
+			.or_else(|| find_in_path("ffprobe"));
+		match ffprobe {
+			Some(path) if path.is_file() => {}
+			Some(path) => {
+				problems.push(Problem::warning(format!(
+					"The ffprobe binary {} of library {name} does not exist",
+					path.display()
+				)));
+			}
+			None => {
+				problems.push(Problem::warning(format!(
+					"Library {name} cannot find ffprobe in the PATH"
+				)));
+			}
+		}
+		if media_config.waveform && find_in_path("ffmpeg").is_none() {
+			problems.push(Problem::warning(format!(
+				"Library {name} computes waveforms, but ffmpeg is not in the PATH"
+			)));
+		}
+	}
+
+	problems.sort_by_key(|problem| std::cmp::Reverse(problem.severity));
+	problems
+}
+
+/// Searches a binary in the `PATH`
+fn find_in_path(name: &str) -> Option<PathBuf> {
+	let file_name = format!("{name}{}", std::env::consts::EXE_SUFFIX);
+	std::env::split_paths(&std::env::var_os("PATH")?)
+		.map(|dir| dir.join(&file_name))
+		.find(|path| path.is_file())
+}
+
+/// Checks that a file can be created in a directory, without leaving it behind
+fn check_dir_writable(dir: &Path) -> io::Result<File> {
+	let probe = dir.join(format!(".{EXE_NAME}-check"));
+	let file = OpenOptions::new()
+		.write(true)
+		.create_new(true)
+		.open(&probe)?;
+	fs::remove_file(&probe)?;
+	Ok(file)
+}
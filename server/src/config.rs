@@ -1,21 +1,73 @@
 //! Provides the server's configuration
 
+use crate::EXE_NAME;
 use config::{ConfigError, Environment, File};
 use serde::Deserialize;
 use std::{
-	collections::HashMap,
+	collections::{HashMap, HashSet},
+	mem,
 	net::{IpAddr, Ipv4Addr},
+	num::NonZeroUsize,
 	path::PathBuf,
 };
 
 /// Builds the server's configuration
-#[inline]
+///
+/// The sources are merged in this order, each one overriding the previous ones:
+/// 1. the system configuration file (`/etc/aedron-patchouli/config.*` on Unix)
+/// 2. the user configuration file (`$XDG_CONFIG_HOME/aedron-patchouli/config.*`,
+///    which defaults to `~/.config/aedron-patchouli/config.*`)
+/// 3. the `config.*` file in the working directory
+/// 4. the `AEPA_*` environment variables
+///
+/// The format of a file (TOML, YAML or JSON, if enabled) is given by its extension.
+/// Missing files are skipped.
 pub(crate) fn build_config() -> Result<Config, ConfigError> {
-	config::Config::builder()
-		.add_source(File::with_name("config").required(false))
+	config_files()
+		.into_iter()
+		.fold(config::Config::builder(), |builder, path| {
+			builder.add_source(File::from(path).required(false))
+		})
 		.add_source(Environment::with_prefix("AEPA"))
 		.build()
 		.and_then(|config| config.try_deserialize())
+		.map(|mut config: Config| {
+			config.base_path = normalize_base_path(&config.base_path);
+			for (name, media_config) in &mut config.media {
+				media_config.dedup_paths(name);
+			}
+			config
+		})
+}
+
+/// Returns the paths of the configuration files, without extension, from the lowest precedence
+///
+/// See [`build_config`].
+fn config_files() -> Vec<PathBuf> {
+	/// Name of the configuration files
+	const CONFIG_FILE: &str = "config";
+
+	let mut files = Vec::new();
+	#[cfg(unix)]
+	files.push(PathBuf::from("/etc").join(EXE_NAME).join(CONFIG_FILE));
+	if let Some(config_dir) = std::env::var_os("XDG_CONFIG_HOME")
+		.map(PathBuf::from)
+		.or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+	{
+		files.push(config_dir.join(EXE_NAME).join(CONFIG_FILE));
+	}
+	files.push(PathBuf::from(CONFIG_FILE));
+	files
+}
+
+/// Makes sure that a base path starts and ends with a `/`
+fn normalize_base_path(path: &str) -> String {
+	let path = path.trim_matches('/');
+	if path.is_empty() {
+		"/".to_owned()
+	} else {
+		format!("/{path}/")
+	}
 }
 
 /// Root configuration structure
@@ -27,12 +79,59 @@ pub(crate) struct Config {
 	/// Port to bind the server to
 	#[serde(default = "Config::default_port")]
 	pub(crate) port: u16,
+	/// Public URL of the app, when it is served behind a reverse proxy
+	///
+	/// It is used to generate absolute links.
+	#[serde(default)]
+	pub(crate) public_url: Option<String>,
+	/// Path prefix of all routes, when the app is not served at the root of its domain
+	///
+	/// It always starts and ends with a `/`.
+	#[serde(default = "Config::default_base_path")]
+	pub(crate) base_path: String,
+	/// Token granting access to the administration endpoints
+	///
+	/// These endpoints are disabled if it is not set.
+	#[serde(default)]
+	pub(crate) admin_token: Option<String>,
+	/// Maximum number of clients streaming the logs at the same time
+	///
+	/// Clients over the limit are rejected, and `0` disables the log stream.
+	#[serde(default = "Config::default_max_log_subscribers")]
+	pub(crate) max_log_subscribers: usize,
 	/// Configuration of the TLS
 	#[serde(default)]
 	pub(crate) tls: TlsConfig,
+	/// Configuration of the TCP listener
+	#[serde(default)]
+	pub(crate) listener: ListenerConfig,
 	/// Configuration of media plugins
 	#[serde(default)]
 	pub(crate) media: HashMap<String, MediaConfig>,
+	/// Default output format of date/time values in API responses
+	#[serde(default)]
+	pub(crate) datetime_format: DateTimeFormat,
+	/// Configuration of the indexing of media files
+	#[serde(default)]
+	pub(crate) indexing: IndexingConfig,
+	/// Configuration of the database
+	#[serde(default)]
+	pub(crate) database: DatabaseConfig,
+	/// Configuration of the loading of plugins
+	#[serde(default)]
+	pub(crate) plugins: PluginsConfig,
+	/// Configuration of the downloads of media archives
+	#[serde(default)]
+	pub(crate) download: DownloadConfig,
+	/// Send the headers enabling cross-origin isolation with the pages and assets?
+	///
+	/// Some browser features (e.g. `SharedArrayBuffer`) require it,
+	/// but the pages cannot embed cross-origin resources that do not allow it anymore.
+	#[serde(default)]
+	pub(crate) cross_origin_isolation: bool,
+	/// Minimum size (in bytes) of a response body to be compressed
+	#[serde(default = "Config::default_compression_threshold")]
+	pub(crate) compression_threshold: u16,
 }
 impl Config {
 	/// Default value for [`addr`](Self#structfield.addr)
@@ -46,6 +145,24 @@ impl Config {
 	const fn default_port() -> u16 {
 		2372
 	}
+
+	/// Default value for [`base_path`](Self#structfield.base_path)
+	#[inline]
+	fn default_base_path() -> String {
+		"/".to_owned()
+	}
+
+	/// Default value for [`max_log_subscribers`](Self#structfield.max_log_subscribers)
+	#[inline]
+	const fn default_max_log_subscribers() -> usize {
+		8
+	}
+
+	/// Default value for [`compression_threshold`](Self#structfield.compression_threshold)
+	#[inline]
+	const fn default_compression_threshold() -> u16 {
+		1024
+	}
 }
 impl Default for Config {
 	#[inline]
@@ -53,8 +170,20 @@ impl Default for Config {
 		Self {
 			addr: Self::default_addr(),
 			port: Self::default_port(),
+			public_url: Default::default(),
+			base_path: Self::default_base_path(),
+			admin_token: Default::default(),
+			max_log_subscribers: Self::default_max_log_subscribers(),
 			tls: Default::default(),
+			listener: Default::default(),
 			media: Default::default(),
+			datetime_format: Default::default(),
+			compression_threshold: Self::default_compression_threshold(),
+			indexing: Default::default(),
+			database: Default::default(),
+			plugins: Default::default(),
+			download: Default::default(),
+			cross_origin_isolation: Default::default(),
 		}
 	}
 }
@@ -96,10 +225,528 @@ impl Default for TlsConfig {
 	}
 }
 
+/// Configuration of the TCP listener
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub(crate) struct ListenerConfig {
+	/// Maximum number of pending connections
+	#[serde(default = "ListenerConfig::default_backlog")]
+	pub(crate) backlog: u32,
+	/// Duration (in seconds) of inactivity before TCP keepalive probes are sent
+	///
+	/// TCP keepalive is disabled if it is not set.
+	#[serde(default)]
+	pub(crate) keepalive: Option<u64>,
+	/// Whether to disable Nagle's algorithm on accepted connections
+	#[serde(default)]
+	pub(crate) nodelay: bool,
+}
+impl ListenerConfig {
+	/// Default value for [`backlog`](Self#structfield.backlog)
+	#[inline]
+	const fn default_backlog() -> u32 {
+		1024
+	}
+}
+impl Default for ListenerConfig {
+	#[inline]
+	fn default() -> Self {
+		Self {
+			backlog: Self::default_backlog(),
+			keepalive: Default::default(),
+			nodelay: Default::default(),
+		}
+	}
+}
+
+/// Configuration of the indexing of media files
+#[derive(Debug, Clone, Deserialize)]
+pub(crate) struct IndexingConfig {
+	/// Maximum number of metadata extractions running at the same time, across all plugins
+	///
+	/// Defaults to the [available parallelism](std::thread::available_parallelism).
+	#[serde(default)]
+	pub(crate) max_extractions: Option<NonZeroUsize>,
+	/// Maximum number of media plugins indexing their media at the same time
+	///
+	/// There is no limit if it is not set.
+	#[serde(default)]
+	pub(crate) max_scans: Option<NonZeroUsize>,
+	/// Number of threads walking the directories and extracting the metadata of media
+	///
+	/// They are dedicated to indexing, so that it does not compete with the other parallel work of the server.
+	/// Defaults to the [available parallelism](std::thread::available_parallelism).
+	#[serde(default)]
+	pub(crate) threads: Option<NonZeroUsize>,
+	/// Minimum free space (in bytes) on the filesystem of the database to write indexed media
+	///
+	/// Indexing is aborted if there is less free space, and `0` disables the check.
+	#[serde(default = "IndexingConfig::default_min_free_space")]
+	pub(crate) min_free_space: u64,
+	/// Maximum time (in milliseconds) to read the metadata of a file
+	///
+	/// Files whose metadata cannot be read in time (e.g. on an unreachable network filesystem) are skipped,
+	/// and the media already indexed from them are kept.
+	/// It does not apply to the listing of directories and to the symbolic links followed by the walk.
+	/// There is no limit if it is not set.
+	#[serde(default)]
+	pub(crate) stat_timeout: Option<u64>,
+	/// Number of consecutive failed extractions after which a file is quarantined
+	///
+	/// Quarantined files are not probed again until they are modified, and `0` disables the quarantine.
+	#[serde(default = "IndexingConfig::default_quarantine_after")]
+	pub(crate) quarantine_after: u32,
+	/// Number of indexed media between two commits to the database
+	///
+	/// Committed media are kept if indexing is interrupted, and are not probed again by the next one.
+	/// `0` commits the media of a library once, when all of them are indexed.
+	#[serde(default = "IndexingConfig::default_commit_interval")]
+	pub(crate) commit_interval: usize,
+}
+impl IndexingConfig {
+	/// Default value for [`min_free_space`](Self#structfield.min_free_space)
+	#[inline]
+	const fn default_min_free_space() -> u64 {
+		64 * 1024 * 1024
+	}
+
+	/// Default value for [`quarantine_after`](Self#structfield.quarantine_after)
+	#[inline]
+	const fn default_quarantine_after() -> u32 {
+		3
+	}
+
+	/// Default value for [`commit_interval`](Self#structfield.commit_interval)
+	#[inline]
+	const fn default_commit_interval() -> usize {
+		1000
+	}
+
+	/// Returns the configured [`max_extractions`](Self#structfield.max_extractions), or its default value
+	pub(crate) fn max_extractions(&self) -> usize {
+		self.max_extractions.map_or_else(
+			|| std::thread::available_parallelism().map_or(1, |num| num.get()),
+			|num| num.get(),
+		)
+	}
+}
+impl Default for IndexingConfig {
+	#[inline]
+	fn default() -> Self {
+		Self {
+			max_extractions: Default::default(),
+			max_scans: Default::default(),
+			threads: Default::default(),
+			min_free_space: Self::default_min_free_space(),
+			stat_timeout: Default::default(),
+			quarantine_after: Self::default_quarantine_after(),
+			commit_interval: Self::default_commit_interval(),
+		}
+	}
+}
+
+/// Configuration of the loading of plugins
+#[derive(Debug, Clone, Deserialize)]
+pub(crate) struct PluginsConfig {
+	/// Number of times the loading of a plugin is retried after a transient error
+	///
+	/// Such errors happen when the file of the plugin is being written.
+	#[serde(default = "PluginsConfig::default_load_retries")]
+	pub(crate) load_retries: u32,
+	/// Delay (in milliseconds) before the first retry, doubled after each retry
+	#[serde(default = "PluginsConfig::default_load_retry_delay")]
+	pub(crate) load_retry_delay: u64,
+	/// Refuse to start if no media plugin is loaded?
+	#[serde(default)]
+	pub(crate) required: bool,
+	/// Handling of the plugins found in the database but not loaded
+	#[serde(default)]
+	pub(crate) removed: RemovedPlugins,
+	/// Names of the plugins not to load
+	///
+	/// Their files are left in place, and their data is kept in the database
+	/// whatever the handling of [removed](Self#structfield.removed) plugins.
+	#[serde(default)]
+	pub(crate) disabled: HashSet<String>,
+}
+impl PluginsConfig {
+	/// Default value for [`load_retries`](Self#structfield.load_retries)
+	#[inline]
+	const fn default_load_retries() -> u32 {
+		3
+	}
+
+	/// Default value for [`load_retry_delay`](Self#structfield.load_retry_delay)
+	#[inline]
+	const fn default_load_retry_delay() -> u64 {
+		100
+	}
+}
+impl Default for PluginsConfig {
+	#[inline]
+	fn default() -> Self {
+		Self {
+			load_retries: Self::default_load_retries(),
+			load_retry_delay: Self::default_load_retry_delay(),
+			required: Default::default(),
+			removed: Default::default(),
+			disabled: Default::default(),
+		}
+	}
+}
+
+/// Handling of the plugins found in the database but not loaded
+///
+/// This happens when the file of a plugin is deleted.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum RemovedPlugins {
+	/// The data of the plugin is kept, so that it is available again once the plugin is restored
+	Leave,
+	/// Same as [`Leave`](Self::Leave), but a warning is logged
+	#[default]
+	Warn,
+	/// The data of the plugin is deleted, except for the user data
+	Drop,
+}
+
+/// Configuration of the downloads of media archives
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub(crate) struct DownloadConfig {
+	/// Maximum number of files in an archive
+	#[serde(default = "DownloadConfig::default_max_files")]
+	pub(crate) max_files: u16,
+	/// Maximum total size (in bytes) of the files in an archive
+	///
+	/// Archives cannot exceed 4 GiB anyway.
+	#[serde(default = "DownloadConfig::default_max_size")]
+	pub(crate) max_size: u64,
+}
+impl DownloadConfig {
+	/// Default value for [`max_files`](Self#structfield.max_files)
+	#[inline]
+	const fn default_max_files() -> u16 {
+		1000
+	}
+
+	/// Default value for [`max_size`](Self#structfield.max_size)
+	#[inline]
+	const fn default_max_size() -> u64 {
+		2 << 30
+	}
+}
+impl Default for DownloadConfig {
+	#[inline]
+	fn default() -> Self {
+		Self {
+			max_files: Self::default_max_files(),
+			max_size: Self::default_max_size(),
+		}
+	}
+}
+
+/// Configuration of the database
+#[derive(Debug, Default, Clone, Copy, Deserialize)]
+pub(crate) struct DatabaseConfig {
+	/// [Journal mode](https://www.sqlite.org/pragma.html#pragma_journal_mode)
+	#[serde(default)]
+	pub(crate) journal_mode: JournalMode,
+	/// [Synchronization mode](https://www.sqlite.org/pragma.html#pragma_synchronous)
+	#[serde(default)]
+	pub(crate) synchronous: Synchronous,
+	/// Duration (in milliseconds) above which SQL statements are logged as slow
+	///
+	/// Slow statements are not logged if it is not set.
+	#[serde(default)]
+	pub(crate) slow_query_threshold: Option<u64>,
+	/// Whether to open the database read-only, e.g. when it is on a read-only filesystem
+	///
+	/// Media are not indexed and the endpoints that write to the database are disabled.
+	#[serde(default)]
+	pub(crate) read_only: bool,
+}
+
+/// Journal mode of the database
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum JournalMode {
+	/// The rollback journal is deleted after each transaction
+	Delete,
+	/// The rollback journal is truncated after each transaction
+	Truncate,
+	/// The header of the rollback journal is zeroed after each transaction
+	Persist,
+	/// The rollback journal is stored in memory
+	Memory,
+	/// A write-ahead log is used instead of a rollback journal
+	#[default]
+	Wal,
+	/// There is no rollback journal
+	Off,
+}
+impl JournalMode {
+	/// Returns the value of the pragma
+	#[inline]
+	pub(crate) const fn as_pragma(&self) -> &'static str {
+		match self {
+			Self::Delete => "DELETE",
+			Self::Truncate => "TRUNCATE",
+			Self::Persist => "PERSIST",
+			Self::Memory => "MEMORY",
+			Self::Wal => "WAL",
+			Self::Off => "OFF",
+		}
+	}
+}
+
+/// Synchronization mode of the database
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum Synchronous {
+	/// The database does not wait for data to be written to disk
+	Off,
+	/// The database waits for data to be written to disk at the most critical moments
+	#[default]
+	Normal,
+	/// The database waits for data to be written to disk at every critical moment
+	Full,
+	/// Like [`Full`](Self::Full), and the directory is also synchronized after deleting a rollback journal
+	Extra,
+}
+impl Synchronous {
+	/// Returns the value of the pragma
+	#[inline]
+	pub(crate) const fn as_pragma(&self) -> &'static str {
+		match self {
+			Self::Off => "OFF",
+			Self::Normal => "NORMAL",
+			Self::Full => "FULL",
+			Self::Extra => "EXTRA",
+		}
+	}
+}
+
+/// Output format of date/time values
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum DateTimeFormat {
+	/// [ISO 8601](https://en.wikipedia.org/wiki/ISO_8601) string
+	#[default]
+	Iso8601,
+	/// Number of seconds since the [Unix epoch](https://en.wikipedia.org/wiki/Unix_time)
+	///
+	/// Dates are taken at midnight UTC, and times are counted from midnight.
+	Unix,
+	/// [RFC 3339](https://datatracker.ietf.org/doc/html/rfc3339) string
+	///
+	/// Dates and times are formatted as `full-date` and `partial-time` respectively.
+	Rfc3339,
+}
+
 /// Configuration of a single media plugin
 #[derive(Debug, Default, Clone, Deserialize)]
 pub(crate) struct MediaConfig {
 	/// Root directories containing the media files
 	#[serde(default)]
 	pub(crate) paths: Vec<PathBuf>,
+	/// Position of the library in the index
+	///
+	/// Libraries are listed by increasing position, then by display name.
+	#[serde(default)]
+	pub(crate) position: i32,
+	/// Compute the waveform of the media files?
+	///
+	/// This requires `ffmpeg` and noticeably slows down indexing.
+	#[serde(default)]
+	pub(crate) waveform: bool,
+	/// Store the raw output of the metadata extractor?
+	///
+	/// It is only available if the plugin exports it, and is stored compressed.
+	#[serde(default)]
+	pub(crate) raw_metadata: bool,
+	/// Handling of extracted metadata whose number of values does not match the declared fields
+	#[serde(default)]
+	pub(crate) field_mismatch: FieldMismatch,
+	/// Identifiers of the fields that must have a value for a media file to be indexed
+	#[serde(default)]
+	pub(crate) required_fields: Vec<String>,
+	/// Normalization of field values when grouping media
+	#[serde(default)]
+	pub(crate) normalize: NormalizeConfig,
+	/// Store the lowercased text of the media, to search them with the `q` parameter of the listings?
+	///
+	/// It is a lighter alternative to full-text search, matching substrings of the text fields.
+	#[serde(default)]
+	pub(crate) search: bool,
+	/// Options passed to the plugin on initialization
+	#[serde(default)]
+	pub(crate) options: HashMap<String, String>,
+}
+impl MediaConfig {
+	/// Removes the [`paths`](Self#structfield.paths) that point to the same directory as a previous one
+	///
+	/// Paths are compared in their canonical form, but the kept ones are left as written,
+	/// since media (and their user data) are stored under the paths found while walking them.
+	/// Paths that cannot be canonicalized (e.g. because they do not exist) are compared as they are.
+	fn dedup_paths(&mut self, name: &str) {
+		let mut seen = HashSet::new();
+		self.paths = mem::take(&mut self.paths)
+			.into_iter()
+			.filter_map(|path| {
+				let canonical = path.canonicalize().unwrap_or_else(|_err| path.clone());
+				if seen.insert(canonical.clone()) {
+					Some(path)
+				} else {
+					log::warn!(
+						"Ignoring {} in library {name}, as it is a duplicate of {}",
+						path.display(),
+						canonical.display()
+					);
+					None
+				}
+			})
+			.collect();
+	}
+}
+
+/// Configuration of the normalization of field values when grouping media
+///
+/// Normalized values are lowercased, their whitespace is collapsed,
+/// and a leading article is moved to the end (e.g. `The Beatles` becomes `beatles, the`),
+/// so that variants of the same value fall in the same group.
+#[derive(Debug, Clone, Deserialize)]
+pub(crate) struct NormalizeConfig {
+	/// Identifiers of the text fields to normalize
+	#[serde(default)]
+	pub(crate) fields: Vec<String>,
+	/// Articles to move to the end of the values
+	#[serde(default = "NormalizeConfig::default_articles")]
+	pub(crate) articles: Vec<String>,
+}
+impl NormalizeConfig {
+	/// Default value for [`articles`](Self#structfield.articles)
+	#[inline]
+	fn default_articles() -> Vec<String> {
+		vec!["the".to_owned(), "a".to_owned(), "an".to_owned()]
+	}
+
+	/// Computes the normalized key of the given value
+	pub(crate) fn key(&self, value: &str) -> String {
+		let value = value
+			.split_whitespace()
+			.collect::<Vec<_>>()
+			.join(" ")
+			.to_lowercase();
+		for article in &self.articles {
+			let article = article.to_lowercase();
+			if let Some(rest) = value
+				.strip_prefix(&article)
+				.and_then(|rest| rest.strip_prefix(' '))
+			{
+				return format!("{rest}, {article}");
+			}
+		}
+		value
+	}
+}
+impl Default for NormalizeConfig {
+	#[inline]
+	fn default() -> Self {
+		Self {
+			fields: Default::default(),
+			articles: Self::default_articles(),
+		}
+	}
+}
+
+/// Handling of extracted metadata whose number of values does not match the declared fields
+///
+/// Such a mismatch is a bug in the plugin.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum FieldMismatch {
+	/// The media is skipped
+	#[default]
+	Skip,
+	/// Missing values are set to null and extra values are dropped
+	Pad,
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn leading_article_is_moved_to_the_end() {
+		let normalize = NormalizeConfig::default();
+		assert_eq!(normalize.key("The Beatles"), "beatles, the");
+		assert_eq!(normalize.key("The Beatles"), normalize.key("Beatles, The"));
+		assert_eq!(normalize.key("An Pierlé"), normalize.key("an pierlé"));
+		assert_eq!(normalize.key("Theatre"), "theatre");
+	}
+
+	#[test]
+	fn whitespace_is_collapsed() {
+		let normalize = NormalizeConfig::default();
+		assert_eq!(
+			normalize.key("  The \t Rolling\n Stones "),
+			"rolling stones, the"
+		);
+		assert_eq!(
+			normalize.key("Rolling  Stones,   The"),
+			normalize.key("The Rolling Stones")
+		);
+	}
+
+	/// Parses a configuration with the given database options
+	fn database_config(options: &[(&str, &str)]) -> Result<DatabaseConfig, ConfigError> {
+		options
+			.iter()
+			.try_fold(config::Config::builder(), |builder, (key, value)| {
+				builder.set_override(format!("database.{key}"), *value)
+			})?
+			.build()?
+			.try_deserialize::<Config>()
+			.map(|config| config.database)
+	}
+
+	#[test]
+	fn journal_modes_are_parsed() {
+		for (value, journal_mode) in [
+			("delete", JournalMode::Delete),
+			("truncate", JournalMode::Truncate),
+			("persist", JournalMode::Persist),
+			("memory", JournalMode::Memory),
+			("wal", JournalMode::Wal),
+			("off", JournalMode::Off),
+		] {
+			let config = database_config(&[("journal_mode", value)]).unwrap();
+			assert_eq!(config.journal_mode, journal_mode, "{value}");
+		}
+	}
+
+	#[test]
+	fn synchronous_modes_are_parsed() {
+		for (value, synchronous) in [
+			("off", Synchronous::Off),
+			("normal", Synchronous::Normal),
+			("full", Synchronous::Full),
+			("extra", Synchronous::Extra),
+		] {
+			let config = database_config(&[("synchronous", value)]).unwrap();
+			assert_eq!(config.synchronous, synchronous, "{value}");
+		}
+	}
+
+	#[test]
+	fn database_modes_default() {
+		let config = database_config(&[]).unwrap();
+		assert_eq!(config.journal_mode, JournalMode::Wal);
+		assert_eq!(config.synchronous, Synchronous::Normal);
+	}
+
+	#[test]
+	fn invalid_database_modes_are_rejected() {
+		assert!(database_config(&[("journal_mode", "WAL2")]).is_err());
+		assert!(database_config(&[("synchronous", "sometimes")]).is_err());
+	}
 }
@@ -1,32 +1,227 @@
 //! Provides functions to interact with the server's database
 
-use crate::EXE_NAME;
+use crate::{
+	config::{Config, DatabaseConfig},
+	EXE_NAME,
+};
 use axum::{
 	extract::{FromRef, FromRequestParts},
 	http::{request::Parts, StatusCode},
 };
 use r2d2::{Pool, PooledConnection};
 use r2d2_sqlite::SqliteConnectionManager;
-use rusqlite::{config::DbConfig, OpenFlags};
+use rusqlite::{config::DbConfig, Connection, OpenFlags, OptionalExtension};
 use scheduled_thread_pool::ScheduledThreadPool;
-use std::{error::Error, ffi::c_int, sync::Arc};
+use std::{
+	error::Error,
+	ffi::c_int,
+	fs::OpenOptions,
+	io::{self, Read, Write},
+	path::PathBuf,
+	sync::{Arc, Once, OnceLock},
+	time::Duration,
+};
+
+/// Migration of the database schema
+#[derive(Debug, Clone, Copy)]
+struct Migration {
+	/// SQL statements applying the migration
+	sql: &'static str,
+	/// Must the tables of all plugins be rebuilt afterwards?
+	rebuild_plugins: bool,
+}
+
+/// Migrations of the database schema, in order
+///
+/// The `user_version` of the database counts the applied migrations,
+/// and [`init`] applies the missing ones in a single transaction.
+/// Applied migrations must not change: later changes of the tables go in new migrations.
+///
+/// This is the only place where the user data tables (e.g. `media_user_data`) are altered.
+/// Unlike the tables of plugins, they are never dropped, so that user data survives plugin upgrades.
+const MIGRATIONS: &[Migration] = &[
+	// NOTE: Databases created before migrations were introduced may have some of these tables already
+	Migration {
+		sql: "
+			CREATE TABLE IF NOT EXISTS plugins (
+				name TEXT NOT NULL,
+				kind TEXT NOT NULL,
+				version TEXT NOT NULL,
+
+				PRIMARY KEY (name, kind) ON CONFLICT REPLACE
+			) STRICT, WITHOUT ROWID;
+
+			CREATE TABLE IF NOT EXISTS libraries (
+				name TEXT NOT NULL PRIMARY KEY ON CONFLICT REPLACE,
+				display_name TEXT
+			) STRICT, WITHOUT ROWID;
+
+			CREATE TABLE IF NOT EXISTS media_generations (
+				library TEXT NOT NULL PRIMARY KEY,
+				generation INTEGER NOT NULL
+			) STRICT, WITHOUT ROWID;
+
+			CREATE TABLE IF NOT EXISTS media_user_data (
+				library TEXT NOT NULL,
+				path TEXT NOT NULL,
+				rating INTEGER CHECK (rating BETWEEN 1 AND 5),
+				favorite INTEGER NOT NULL DEFAULT FALSE,
+
+				PRIMARY KEY (library, path) ON CONFLICT REPLACE
+			) STRICT, WITHOUT ROWID;
+
+			CREATE TABLE IF NOT EXISTS media_waveforms (
+				library TEXT NOT NULL,
+				path TEXT NOT NULL,
+				peaks BLOB NOT NULL,
+
+				PRIMARY KEY (library, path) ON CONFLICT REPLACE
+			) STRICT, WITHOUT ROWID;
+
+			CREATE TABLE IF NOT EXISTS media_group_keys (
+				library TEXT NOT NULL,
+				path TEXT NOT NULL,
+				field TEXT NOT NULL,
+				value TEXT NOT NULL,
+				key TEXT NOT NULL,
+
+				PRIMARY KEY (library, path, field, value) ON CONFLICT REPLACE
+			) STRICT, WITHOUT ROWID;
+
+			CREATE TABLE IF NOT EXISTS media_failures (
+				library TEXT NOT NULL,
+				path TEXT NOT NULL,
+				mtime TEXT NOT NULL,
+				failures INTEGER NOT NULL,
+
+				PRIMARY KEY (library, path) ON CONFLICT REPLACE
+			) STRICT, WITHOUT ROWID;
+
+			CREATE TABLE IF NOT EXISTS media_raw_metadata (
+				library TEXT NOT NULL,
+				path TEXT NOT NULL,
+				raw_metadata BLOB NOT NULL,
+
+				PRIMARY KEY (library, path) ON CONFLICT REPLACE
+			) STRICT, WITHOUT ROWID;
+		",
+		rebuild_plugins: true,
+	},
+	// NOTE: The tables of loaded plugins are filled in by `PluginStore::update_database`
+	Migration {
+		sql: "ALTER TABLE plugins ADD COLUMN table_name TEXT;",
+		rebuild_plugins: false,
+	},
+	Migration {
+		sql: "
+			CREATE TABLE media_search (
+				library TEXT NOT NULL,
+				path TEXT NOT NULL,
+				text TEXT NOT NULL,
+
+				PRIMARY KEY (library, path) ON CONFLICT REPLACE
+			) STRICT, WITHOUT ROWID;
+		",
+		rebuild_plugins: false,
+	},
+];
+
+/// Duration above which SQL statements are logged as slow
+static SLOW_QUERY_THRESHOLD: OnceLock<Duration> = OnceLock::new();
+
+/// Returns the path of the database file
+#[inline]
+pub(crate) fn path() -> PathBuf {
+	std::env::var_os("AEPA_DB")
+		.unwrap_or_else(|| format!("{EXE_NAME}.sqlite").into())
+		.into()
+}
+
+/// Checks that there is enough free space on the filesystem of the database
+///
+/// # Errors
+/// This function returns a [`SQLITE_FULL`](rusqlite::ffi::SQLITE_FULL) error
+/// if there is less than `min_free_space` bytes available.
+/// If the free space cannot be read, a warning is logged and the check passes.
+pub(crate) fn check_free_space(min_free_space: u64) -> rusqlite::Result<()> {
+	if min_free_space == 0 {
+		return Ok(());
+	}
+	match fs2::available_space(path()) {
+		Ok(available) if available < min_free_space => Err(rusqlite::Error::SqliteFailure(
+			rusqlite::ffi::Error::new(rusqlite::ffi::SQLITE_FULL),
+			Some(format!(
+				"only {available} bytes are free on the filesystem of the database, less than the required {min_free_space}"
+			)),
+		)),
+		Ok(_) => Ok(()),
+		Err(err) => {
+			log::warn!("Could not read the free space on the filesystem of the database: {err}");
+			Ok(())
+		}
+	}
+}
 
 /// Initializes the pool of connections to the database
-pub(crate) fn init() -> Result<Pool<SqliteConnectionManager>, Box<dyn Error>> {
+#[inline]
+pub(crate) fn init(
+	config: &DatabaseConfig,
+) -> Result<Pool<SqliteConnectionManager>, Box<dyn Error>> {
+	open(path(), config)
+}
+
+/// Initializes the pool of connections to the given database file
+///
+/// [`init`] opens the [configured file](path).
+pub(crate) fn open(
+	file: PathBuf,
+	config: &DatabaseConfig,
+) -> Result<Pool<SqliteConnectionManager>, Box<dyn Error>> {
 	/// Callback for [`rusqlite::trace::config_log`]
 	fn db_config_log(code: c_int, msg: &str) {
 		log::debug!(target: "database", "({code}) {msg}");
 	}
-	#[allow(unsafe_code)]
-	// SAFETY: `db_config_log` does not call any SQLite function and is thread-safe.
-	unsafe {
-		rusqlite::trace::config_log(Some(db_config_log))?;
-	}
+	/// Set once the log callback is configured
+	///
+	/// The database library can only be configured before the first database is opened.
+	static CONFIG_LOG: Once = Once::new();
+	let mut res = Ok(());
+	CONFIG_LOG.call_once(|| {
+		#[allow(unsafe_code)]
+		// SAFETY: `db_config_log` does not call any SQLite function and is thread-safe.
+		unsafe {
+			res = rusqlite::trace::config_log(Some(db_config_log));
+		}
+	});
+	res?;
 
-	let file = std::env::var_os("AEPA_DB").unwrap_or_else(|| format!("{EXE_NAME}.sqlite").into());
-	let flags = OpenFlags::SQLITE_OPEN_READ_WRITE
-		| OpenFlags::SQLITE_OPEN_CREATE
-		| OpenFlags::SQLITE_OPEN_NO_MUTEX;
+	let DatabaseConfig {
+		journal_mode,
+		synchronous,
+		slow_query_threshold,
+		read_only,
+	} = *config;
+	if let Some(threshold) = slow_query_threshold {
+		SLOW_QUERY_THRESHOLD.get_or_init(|| Duration::from_millis(threshold));
+	}
+	let flags = if read_only {
+		log::warn!(
+			"The database is opened read-only: media are not indexed and changes are disabled"
+		);
+		OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX
+	} else {
+		// NOTE: SQLite only reports a generic error when it cannot write
+		if let Err(err) = OpenOptions::new().write(true).create(true).open(&file) {
+			return Err(format!(
+				"could not open the database {} for writing ({err}); if it is on a read-only filesystem, set `database.read_only` to serve it without indexing",
+				file.display(),
+			)
+			.into());
+		}
+		OpenFlags::SQLITE_OPEN_READ_WRITE
+			| OpenFlags::SQLITE_OPEN_CREATE
+			| OpenFlags::SQLITE_OPEN_NO_MUTEX
+	};
 	let db_pool = Pool::builder()
 		.thread_pool(Arc::new(
 			ScheduledThreadPool::builder()
@@ -37,19 +232,41 @@ pub(crate) fn init() -> Result<Pool<SqliteConnectionManager>, Box<dyn Error>> {
 		.build(
 			SqliteConnectionManager::file(file)
 				.with_flags(flags)
-				.with_init(|conn| {
+				.with_init(move |conn| {
 					/// Callback for [`Connection::trace`]
 					fn db_trace(msg: &str) {
 						log::trace!(target: "sql", "{msg}");
 					}
 					conn.trace(Some(db_trace));
 
+					/// Callback for [`Connection::profile`]
+					fn db_profile(msg: &str, duration: Duration) {
+						if SLOW_QUERY_THRESHOLD
+							.get()
+							.map_or(false, |threshold| duration > *threshold)
+						{
+							log::warn!(target: "sql", "Slow statement ({duration:?}): {msg}");
+						}
+					}
+					if SLOW_QUERY_THRESHOLD.get().is_some() {
+						conn.profile(Some(db_profile));
+					}
+
 					conn.set_db_config(DbConfig::SQLITE_DBCONFIG_ENABLE_FKEY, true)?;
 					conn.pragma_update(None, "trusted_schema", false)?;
-					conn.pragma_update_and_check(None, "journal_mode", "WAL", |row| {
-						row.get::<_, Box<str>>(0)
-					})?;
-					conn.pragma_update(None, "synchronous", "NORMAL")?;
+					if read_only {
+						conn.pragma_update(None, "query_only", true)?;
+
+						log::debug!("Opened a read-only connection to the database");
+						return Ok(());
+					}
+					conn.pragma_update_and_check(
+						None,
+						"journal_mode",
+						journal_mode.as_pragma(),
+						|row| row.get::<_, Box<str>>(0),
+					)?;
+					conn.pragma_update(None, "synchronous", synchronous.as_pragma())?;
 					conn.pragma_update(None, "auto_vacuum", "FULL")?;
 					conn.pragma_update(None, "application_id", i32::from_be_bytes(*b"AEPA"))?;
 
@@ -58,25 +275,93 @@ pub(crate) fn init() -> Result<Pool<SqliteConnectionManager>, Box<dyn Error>> {
 				}),
 		)?;
 
+	if read_only {
+		return Ok(db_pool);
+	}
 	let mut conn = db_pool.get()?;
 	let transaction = conn.transaction()?;
-	transaction.execute_batch(
-		"
-			CREATE TABLE IF NOT EXISTS plugins (
-				name TEXT NOT NULL,
-				kind TEXT NOT NULL,
-				version TEXT NOT NULL,
-
-				PRIMARY KEY (name, kind) ON CONFLICT REPLACE
-			) STRICT, WITHOUT ROWID;
-		"
-		.trim(),
-	)?;
+	let applied =
+		transaction.pragma_query_value(None, "user_version", |row| row.get::<_, usize>(0))?;
+	if applied > MIGRATIONS.len() {
+		return Err(format!(
+			"The database schema is at version {applied}, but this server only knows up to version {}",
+			MIGRATIONS.len()
+		)
+		.into());
+	}
+	for (version, migration) in MIGRATIONS.iter().enumerate().skip(applied) {
+		log::info!("Migrating the database schema to version {}", version + 1);
+		transaction.execute_batch(migration.sql)?;
+		if migration.rebuild_plugins {
+			transaction.execute("DELETE FROM plugins", ())?;
+		}
+	}
+	transaction.pragma_update(None, "user_version", MIGRATIONS.len())?;
 	transaction.commit()?;
 
 	Ok(db_pool)
 }
 
+/// Bumps the generation of the media of a library
+///
+/// It must be called every time the media of the library change,
+/// so that cached listings are invalidated.
+pub(crate) fn bump_generation(conn: &Connection, library: &str) -> rusqlite::Result<()> {
+	conn.execute(
+		"INSERT INTO media_generations(library, generation) VALUES (?, 1) ON CONFLICT (library) DO UPDATE SET generation = generation + 1",
+		[library],
+	)
+	.map(|_| ())
+}
+
+/// Returns the generation of the media of a library
+pub(crate) fn generation(conn: &Connection, library: &str) -> rusqlite::Result<i64> {
+	conn.query_row(
+		"SELECT generation FROM media_generations WHERE library = ?",
+		[library],
+		|row| row.get(0),
+	)
+	.optional()
+	.map(Option::unwrap_or_default)
+}
+
+/// Compresses data before storing it in the database
+pub(crate) fn compress(data: &[u8]) -> io::Result<Vec<u8>> {
+	let mut writer = brotli::CompressorWriter::new(Vec::new(), 4096, 9, 22);
+	writer.write_all(data)?;
+	Ok(writer.into_inner())
+}
+
+/// Decompresses data compressed by [`compress`]
+pub(crate) fn decompress(data: &[u8]) -> io::Result<Vec<u8>> {
+	let mut buf = Vec::new();
+	brotli::Decompressor::new(data, 4096).read_to_end(&mut buf)?;
+	Ok(buf)
+}
+
+/// [Axum extractor](axum::extract) that rejects requests if the database is [read-only](DatabaseConfig#structfield.read_only)
+///
+/// It guards the endpoints that write to the database.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Writable;
+#[axum::async_trait]
+impl<S> FromRequestParts<S> for Writable
+where
+	Config: FromRef<S>,
+	S: Send + Sync,
+{
+	type Rejection = (StatusCode, &'static str);
+
+	#[inline]
+	async fn from_request_parts(_parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+		if Config::from_ref(state).database.read_only {
+			Err((StatusCode::SERVICE_UNAVAILABLE, "The database is read-only"))
+		} else {
+			Ok(Self)
+		}
+	}
+}
+
 /// [Axum extractor](axum::extract) for a database connection
 #[repr(transparent)]
 pub(crate) struct DbConn(pub(crate) PooledConnection<SqliteConnectionManager>);
@@ -99,3 +384,51 @@ where
 			})
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn databases_without_migrations_are_migrated() {
+		let dir = tempfile::tempdir().unwrap();
+		let file = dir.path().join("db.sqlite");
+		// NOTE: The log callback can only be configured before SQLite opens its first database
+		open(dir.path().join("other.sqlite"), &DatabaseConfig::default()).unwrap();
+		{
+			let conn = Connection::open(&file).unwrap();
+			conn.execute_batch(
+				"
+					CREATE TABLE plugins (
+						name TEXT NOT NULL,
+						kind TEXT NOT NULL,
+						version TEXT NOT NULL,
+
+						PRIMARY KEY (name, kind) ON CONFLICT REPLACE
+					) STRICT, WITHOUT ROWID;
+					INSERT INTO plugins VALUES ('stub', 'media', '0.1.0');
+				",
+			)
+			.unwrap();
+		}
+
+		let db_pool = open(file, &DatabaseConfig::default()).unwrap();
+		let conn = db_pool.get().unwrap();
+		let version = conn
+			.pragma_query_value(None, "user_version", |row| row.get::<_, usize>(0))
+			.unwrap();
+		assert_eq!(version, MIGRATIONS.len());
+		let plugins = conn
+			.query_row("SELECT count(*) FROM plugins", (), |row| {
+				row.get::<_, i64>(0)
+			})
+			.unwrap();
+		assert_eq!(plugins, 0, "the tables of plugins should be rebuilt");
+		for table in ["libraries", "media_user_data", "media_search"] {
+			conn.query_row(&format!("SELECT count(*) FROM {table}"), (), |_| Ok(()))
+				.unwrap_or_else(|err| panic!("{table} should exist: {err}"));
+		}
+		conn.prepare("SELECT table_name FROM plugins")
+			.expect("plugins should have a table_name column");
+	}
+}
@@ -3,26 +3,30 @@
 mod api;
 mod assets;
 
-use crate::AppState;
+use crate::{config::Config, AppState};
 use axum::{
-	extract::ConnectInfo,
-	http,
+	extract::{ConnectInfo, FromRef, FromRequestParts, State},
+	http::{self, header, request::Parts, HeaderMap, HeaderValue, StatusCode},
 	middleware::{self, Next},
-	response::Response,
+	response::{Html, IntoResponse, Response},
 	Router,
 };
-use client::leptos;
+use client::{
+	leptos::{self, LeptosOptions},
+	reqwest::Url,
+	BasePath,
+};
 use hyper::body::HttpBody;
 use leptos_axum::LeptosRoutes;
 use std::{
 	fmt::{self, Display, Formatter},
 	net::SocketAddr,
-	time::Duration,
+	time::{Duration, SystemTime},
 };
 use tower::ServiceBuilder;
 use tower_http::{
 	classify::{ServerErrorsAsFailures, SharedClassifier},
-	compression::{CompressionLayer, DefaultPredicate, Predicate},
+	compression::{predicate::NotForContentType, CompressionLayer, Predicate},
 	normalize_path::NormalizePathLayer,
 	trace::{DefaultMakeSpan, OnFailure, OnRequest, OnResponse, TraceLayer},
 };
@@ -31,35 +35,282 @@ use tracing::Span;
 /// Constructs a new configured [`Router`]
 pub(super) fn new_router(state: &AppState) -> Router<AppState> {
 	let request_client = state.request_client.clone();
+	let base_path = BasePath(state.config.base_path.clone());
+	let app = {
+		let base_path = base_path.clone();
+		move || {
+			leptos::provide_context(base_path.clone());
+			client::App()
+		}
+	};
 
 	Router::new()
-		.nest("/api", api::new_router())
-		.nest(
-			&format!("/{}", state.leptos_options.site_pkg_dir),
-			assets::new_router(),
-		)
 		.leptos_routes_with_context(
 			state,
-			leptos_axum::generate_route_list(client::App),
+			leptos_axum::generate_route_list(app.clone()),
 			move || {
 				leptos::provide_context(request_client.clone());
+				leptos::provide_context(base_path.clone());
 			},
-			client::App,
+			app,
+		)
+		.route_layer(middleware::from_fn_with_state(
+			(state.leptos_options.clone(), state.config.base_path.clone()),
+			ssr_fallback,
+		))
+		.nest(
+			&format!("/{}", state.leptos_options.site_pkg_dir),
+			assets::new_router(),
 		)
+		.layer(middleware::from_fn_with_state(
+			state.config.cross_origin_isolation,
+			cross_origin_isolation,
+		))
+		.nest(&format!("{}api", state.config.base_path), api::new_router())
 		.layer(
 			// NOTE: Requests pass through layers top down (↓)
 			ServiceBuilder::new()
 				.layer(NormalizePathLayer::trim_trailing_slash())
 				.layer(CustomTrace::new_layer())
-				.layer(
-					CompressionLayer::new()
-						.compress_when(DefaultPredicate::new().and(ProfilePredicate)),
-				)
+				.layer(middleware::from_fn(encoded_entity_tags))
+				.layer(CompressionLayer::new().compress_when(
+					compression_predicate(state.config.compression_threshold).and(ProfilePredicate),
+				))
 				.layer(middleware::from_fn(req_to_res_extensions)),
 			// NOTE: Responses pass through layers bottom up (↑)
 		)
 }
 
+/// Public URL of the app
+#[derive(Debug, Clone)]
+pub(crate) struct PublicUrl {
+	/// Configured [`public_url`](Config#structfield.public_url)
+	pub(crate) url: Option<Url>,
+	/// Configured [`base_path`](Config#structfield.base_path)
+	pub(crate) base_path: String,
+}
+impl PublicUrl {
+	/// Returns a link to the given path, relative to the root of the app
+	///
+	/// The link is absolute from the [`base_path`](Self#structfield.base_path)
+	/// if no public URL is configured.
+	pub(crate) fn link(&self, path: &str) -> String {
+		let path = path.trim_start_matches('/');
+		self.url
+			.as_ref()
+			.and_then(|url| url.join(path).ok())
+			.map_or_else(|| format!("{}{path}", self.base_path), String::from)
+	}
+}
+
+/// [Axum extractor](axum::extract) that guards administration endpoints
+///
+/// The request must carry the configured [`admin_token`](Config#structfield.admin_token)
+/// as a bearer token in its `Authorization` header.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Admin;
+#[axum::async_trait]
+impl<S> FromRequestParts<S> for Admin
+where
+	Config: FromRef<S>,
+	S: Send + Sync,
+{
+	type Rejection = (
+		StatusCode,
+		[(header::HeaderName, &'static str); 1],
+		&'static str,
+	);
+
+	async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+		let config = Config::from_ref(state);
+		let Some(expected) = config.admin_token.as_deref() else {
+			return Err((
+				StatusCode::NOT_FOUND,
+				[(header::CACHE_CONTROL, "no-store")],
+				"Administration is disabled",
+			));
+		};
+		let token = parts
+			.headers
+			.get(header::AUTHORIZATION)
+			.and_then(|value| value.to_str().ok())
+			.and_then(|value| value.strip_prefix("Bearer "));
+		// NOTE: The comparison runs in constant time to not leak the token
+		match token {
+			Some(token)
+				if token.len() == expected.len()
+					&& token
+						.bytes()
+						.zip(expected.bytes())
+						.fold(0, |acc, (a, b)| acc | (a ^ b))
+						== 0 =>
+			{
+				Ok(Self)
+			}
+			_ => Err((
+				StatusCode::UNAUTHORIZED,
+				[(header::WWW_AUTHENTICATE, "Bearer")],
+				"Invalid administration token",
+			)),
+		}
+	}
+}
+
+/// [Middleware](axum::middleware) that serves a minimal HTML shell when the server-side rendering fails
+///
+/// The shell only loads the client, which then renders the page and fetches the data itself;
+/// it carries the configured [`base_path`](Config#structfield.base_path) like the rendered pages do.
+async fn ssr_fallback<B: Send + 'static>(
+	State((leptos_options, base_path)): State<(LeptosOptions, String)>,
+	request: http::Request<B>,
+	next: Next<B>,
+) -> Response {
+	let uri = request.uri().clone();
+	match tokio::spawn(next.run(request)).await {
+		Ok(response) if !response.status().is_server_error() => return response,
+		Ok(response) => {
+			log::error!(
+				"Server-side rendering of {uri} failed with {}",
+				response.status()
+			);
+		}
+		Err(err) => {
+			log::error!("Server-side rendering of {uri} panicked: {err}");
+		}
+	}
+
+	let pkg = format!(
+		"/{}/{}",
+		leptos_options.site_pkg_dir, leptos_options.output_name
+	);
+	Html(format!(
+		r#"<!DOCTYPE html>
+<html>
+	<head>
+		<meta charset="utf-8">
+		<meta name="viewport" content="width=device-width, initial-scale=1">
+		<meta name="base-path" content="{base_path}">
+		<link rel="modulepreload" href="{pkg}.js">
+		<link rel="preload" href="{pkg}_bg.wasm" as="fetch" type="application/wasm" crossorigin="">
+		<script type="module">import init, {{ hydrate }} from '{pkg}.js'; init('{pkg}_bg.wasm').then(hydrate);</script>
+	</head>
+	<body></body>
+</html>"#
+	))
+	.into_response()
+}
+
+/// [Middleware](axum::middleware) that enables cross-origin isolation, if configured
+///
+/// See [`cross_origin_isolation`](Config#structfield.cross_origin_isolation).
+async fn cross_origin_isolation<B>(
+	State(enabled): State<bool>,
+	request: http::Request<B>,
+	next: Next<B>,
+) -> Response {
+	let mut response = next.run(request).await;
+	if enabled {
+		let headers = response.headers_mut();
+		headers.insert(
+			header::HeaderName::from_static("cross-origin-opener-policy"),
+			header::HeaderValue::from_static("same-origin"),
+		);
+		headers.insert(
+			header::HeaderName::from_static("cross-origin-embedder-policy"),
+			header::HeaderValue::from_static("require-corp"),
+		);
+	}
+	response
+}
+
+/// Returns the [compression predicate](Predicate) of the responses
+///
+/// Images, audio and video files, and ZIP archives are already compressed.
+fn compression_predicate(threshold: u16) -> impl Predicate {
+	ContentLengthAbove(threshold)
+		.and(NotForContentType::GRPC)
+		.and(NotForContentType::IMAGES)
+		.and(NotForContentType::const_new("audio/"))
+		.and(NotForContentType::const_new("video/"))
+		.and(NotForContentType::const_new("application/zip"))
+}
+
+/// Content codings that may be applied by the [`CompressionLayer`]
+const CONTENT_CODINGS: [&str; 4] = ["br", "deflate", "gzip", "zstd"];
+
+/// [Middleware](axum::middleware) that gives a distinct entity tag to each content coding of a response
+///
+/// The coding is appended to the entity tag of compressed responses,
+/// and stripped from the tags of the `If-None-Match` header before the request is handled.
+/// It is appended back to the tag of a `304 Not Modified` response,
+/// which the [`CompressionLayer`] never encodes.
+async fn encoded_entity_tags<B>(mut request: http::Request<B>, next: Next<B>) -> Response {
+	let mut coding = None;
+	let if_none_match = request
+		.headers()
+		.get_all(header::IF_NONE_MATCH)
+		.iter()
+		.filter_map(|value| value.to_str().ok())
+		.flat_map(|value| value.split(','))
+		.map(|tag| {
+			let tag = tag.trim();
+			tag.strip_suffix('"')
+				.and_then(|tag| {
+					CONTENT_CODINGS.into_iter().find_map(|candidate| {
+						let tag = tag.strip_suffix(candidate)?.strip_suffix('-')?;
+						coding = Some(candidate);
+						Some(format!("{tag}\""))
+					})
+				})
+				.unwrap_or_else(|| tag.to_owned())
+		})
+		.collect::<Vec<_>>();
+	if coding.is_some() {
+		if let Ok(value) = HeaderValue::try_from(if_none_match.join(", ")) {
+			request.headers_mut().insert(header::IF_NONE_MATCH, value);
+		}
+	}
+
+	let mut response = next.run(request).await;
+	let coding = match response.headers().get(header::CONTENT_ENCODING) {
+		Some(value) => value.to_str().ok().map(ToOwned::to_owned),
+		None if response.status() == StatusCode::NOT_MODIFIED => coding.map(ToOwned::to_owned),
+		None => None,
+	};
+	if let Some(coding) = coding {
+		let etag = response
+			.headers()
+			.get(header::ETAG)
+			.and_then(|etag| etag.to_str().ok())
+			.and_then(|etag| etag.strip_suffix('"'))
+			.and_then(|etag| HeaderValue::try_from(format!("{etag}-{coding}\"")).ok());
+		if let Some(etag) = etag {
+			response.headers_mut().insert(header::ETAG, etag);
+		}
+	}
+	response
+}
+
+/// Returns the headers describing a file served whole
+///
+/// They are the same for `GET` and `HEAD` requests, whose response body is dropped.
+/// The entity tag is derived from the size and the modification time of the file.
+fn file_headers(len: u64, modified: SystemTime) -> HeaderMap {
+	let modified = modified
+		.duration_since(SystemTime::UNIX_EPOCH)
+		.unwrap_or_default()
+		.as_nanos();
+
+	let mut headers = HeaderMap::new();
+	headers.insert(header::CONTENT_LENGTH, HeaderValue::from(len));
+	// NOTE: Ranges are not supported
+	headers.insert(header::ACCEPT_RANGES, HeaderValue::from_static("none"));
+	if let Ok(etag) = HeaderValue::try_from(format!("\"{len:x}-{modified:x}\"")) {
+		headers.insert(header::ETAG, etag);
+	}
+	headers
+}
+
 /// [Middleware](axum::middleware) that copies some [`Request`] extensions to the [`Response`](response::Response)
 ///
 /// # Copied extensions
@@ -155,6 +406,26 @@ impl Display for FmtDuration {
 	}
 }
 
+/// [Compression predicate](Predicate) that compresses responses whose length reaches a threshold
+///
+/// Unlike [`SizeAbove`](tower_http::compression::predicate::SizeAbove),
+/// the `Content-Length` header is read before the size of the body,
+/// so that `HEAD` responses, whose body is empty, are compressed like the `GET` ones.
+/// Responses of unknown length are compressed.
+#[derive(Debug, Clone, Copy)]
+struct ContentLengthAbove(u16);
+impl Predicate for ContentLengthAbove {
+	fn should_compress<B: HttpBody>(&self, response: &http::Response<B>) -> bool {
+		response
+			.headers()
+			.get(header::CONTENT_LENGTH)
+			.and_then(|len| len.to_str().ok())
+			.and_then(|len| len.parse().ok())
+			.or_else(|| response.body().size_hint().exact())
+			.map_or(true, |len| len >= u64::from(self.0))
+	}
+}
+
 /// [Compression predicate](Predicate) according to the compilation profile
 #[derive(Debug, Default, Clone, Copy)]
 struct ProfilePredicate;
@@ -164,3 +435,179 @@ impl Predicate for ProfilePredicate {
 		!cfg!(debug_assertions)
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::plugins::stub::{self, Library};
+	use axum::{body::Body, http::Method, routing};
+	use std::sync::Arc;
+	use tower::ServiceExt;
+
+	/// Constructs a router of the API around the given library, which compresses its responses
+	///
+	/// Unlike the one of [`new_router`], it does so in debug builds.
+	pub(super) fn router(library: &Library) -> Router {
+		Router::new()
+			.nest("/api", api::new_router())
+			.layer(
+				ServiceBuilder::new()
+					.layer(middleware::from_fn(encoded_entity_tags))
+					.layer(CompressionLayer::new().compress_when(compression_predicate(
+						library.config.compression_threshold,
+					))),
+			)
+			.with_state(AppState::stub(
+				library.config.clone(),
+				library.db_pool.clone(),
+				Arc::clone(&library.plugins),
+			))
+	}
+
+	/// Sends a request to the router, with the given headers
+	pub(super) async fn request(
+		router: &Router,
+		method: Method,
+		uri: &str,
+		headers: &[(header::HeaderName, &str)],
+	) -> Response {
+		let mut request = http::Request::builder().method(method).uri(uri);
+		for (name, value) in headers {
+			request = request.header(name, *value);
+		}
+		router
+			.clone()
+			.oneshot(request.body(Body::empty()).unwrap())
+			.await
+			.unwrap()
+	}
+
+	#[tokio::test]
+	async fn head_and_get_have_the_same_headers() {
+		let library = Library::new(stub::extract_title, |_| {});
+		library.write("text.txt", "text ".repeat(512));
+		library.write("song.mp3", "song ".repeat(512));
+		library.index();
+		let text = format!(
+			"/api/libraries/{}/{}/file",
+			Library::NAME,
+			library.id("text")
+		);
+		let song = format!(
+			"/api/libraries/{}/{}/file",
+			Library::NAME,
+			library.id("song")
+		);
+		let router = router(&library);
+		let br = [(header::ACCEPT_ENCODING, "br")];
+
+		for uri in [&text, &song] {
+			let get = request(&router, Method::GET, uri, &br).await;
+			let head = request(&router, Method::HEAD, uri, &br).await;
+			assert_eq!(get.status(), StatusCode::OK);
+			assert_eq!(head.status(), StatusCode::OK);
+			assert_eq!(head.headers(), get.headers(), "{uri}");
+		}
+
+		let compressed = request(&router, Method::GET, &text, &br).await;
+		let identity = request(&router, Method::GET, &text, &[]).await;
+		assert_eq!(compressed.headers()[header::CONTENT_ENCODING], "br");
+		assert!(!compressed.headers().contains_key(header::CONTENT_LENGTH));
+		assert!(!identity.headers().contains_key(header::CONTENT_ENCODING));
+		assert_eq!(identity.headers()[header::CONTENT_LENGTH], "2560");
+		assert_ne!(
+			compressed.headers()[header::ETAG],
+			identity.headers()[header::ETAG]
+		);
+
+		let song = request(&router, Method::GET, &song, &br).await;
+		assert!(!song.headers().contains_key(header::CONTENT_ENCODING));
+		assert_eq!(song.headers()[header::CONTENT_LENGTH], "2560");
+	}
+
+	#[tokio::test]
+	async fn compressed_entity_tags_are_matched() {
+		let library = Library::new(stub::extract_title, |config| {
+			config.compression_threshold = 0;
+		});
+		library.write("text.txt", "text");
+		library.index();
+		let router = router(&library);
+		let uri = format!("/api/libraries/{}", Library::NAME);
+		let br = (header::ACCEPT_ENCODING, "br");
+
+		let response = request(&router, Method::GET, &uri, &[br.clone()]).await;
+		assert_eq!(response.status(), StatusCode::OK);
+		assert_eq!(response.headers()[header::CONTENT_ENCODING], "br");
+		let etag = response.headers()[header::ETAG]
+			.to_str()
+			.unwrap()
+			.to_owned();
+		assert!(etag.ends_with("-br\""), "{etag}");
+
+		let response = request(
+			&router,
+			Method::GET,
+			&uri,
+			&[br, (header::IF_NONE_MATCH, &etag)],
+		)
+		.await;
+		assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+		assert_eq!(response.headers()[header::ETAG], etag.as_str());
+	}
+
+	#[tokio::test]
+	async fn small_responses_are_not_compressed() {
+		let library = Library::new(stub::extract_title, |config| {
+			config.compression_threshold = 1024;
+		});
+		library.write("small.txt", "t".repeat(1023));
+		library.write("large.txt", "t".repeat(1024));
+		library.index();
+		let router = router(&library);
+		let br = [(header::ACCEPT_ENCODING, "br")];
+		let uri = |title| {
+			format!(
+				"/api/libraries/{}/{}/file",
+				Library::NAME,
+				library.id(title)
+			)
+		};
+
+		let small = request(&router, Method::GET, &uri("small"), &br).await;
+		assert_eq!(small.status(), StatusCode::OK);
+		assert!(!small.headers().contains_key(header::CONTENT_ENCODING));
+		assert_eq!(small.headers()[header::CONTENT_LENGTH], "1023");
+
+		let large = request(&router, Method::GET, &uri("large"), &br).await;
+		assert_eq!(large.status(), StatusCode::OK);
+		assert_eq!(large.headers()[header::CONTENT_ENCODING], "br");
+	}
+
+	#[tokio::test]
+	async fn ssr_fallback_carries_the_base_path() {
+		let router = Router::new()
+			.route(
+				"/aepa/",
+				routing::get(|| async { StatusCode::INTERNAL_SERVER_ERROR }),
+			)
+			.route_layer(middleware::from_fn_with_state(
+				(
+					LeptosOptions::builder()
+						.output_name(env!("ASSET_PREFIX").to_owned())
+						.build(),
+					"/aepa/".to_owned(),
+				),
+				ssr_fallback,
+			));
+
+		let response = request(&router, Method::GET, "/aepa/", &[]).await;
+		assert_eq!(response.status(), StatusCode::OK);
+		let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+		let body = std::str::from_utf8(&body).unwrap();
+		assert!(
+			body.contains(r#"<meta name="base-path" content="/aepa/">"#),
+			"{body}"
+		);
+	}
+}
@@ -1,78 +1,2306 @@
 //! Provides routes for the API
 
-use crate::{db::DbConn, plugins::PluginStore, AppState};
+mod openapi;
+mod zip;
+
+use crate::{
+	config::{Config, DateTimeFormat},
+	db::{self, DbConn, Writable},
+	http::{Admin, PublicUrl},
+	plugins::{MediaPlugin, PluginStore, Progress},
+	AppState, LogLine,
+};
 use axum::{
-	extract::{Path, State},
-	Json, Router,
+	extract::{FromRef, FromRequestParts, Path, Query, State},
+	http::{header, request::Parts, HeaderMap, Method},
+	response::{IntoResponse, Response},
+	routing, Json, Router,
 };
-use axum_extra::routing::Resource;
+use axum_extra::{body::AsyncReadBody, routing::Resource};
+use globset::Glob;
 use hyper::StatusCode;
+use log::LevelFilter;
+use pluglib::media::{MetadataField, MetadataFieldType};
+use r2d2::Pool;
+use r2d2_sqlite::SqliteConnectionManager;
+use rusqlite::{Connection, OptionalExtension, Row, ToSql};
+use serde::{Deserialize, Serialize};
 use serde_json::Value;
-use std::{collections::HashMap, sync::Arc};
+use std::{
+	collections::{hash_map::DefaultHasher, HashMap, HashSet},
+	hash::{Hash, Hasher},
+	sync::{mpsc, Arc},
+};
+use time::{
+	format_description::{
+		well_known::{Iso8601, Rfc3339},
+		FormatItem,
+	},
+	macros::format_description,
+	Date, OffsetDateTime, Time,
+};
+use tokio::{
+	io::AsyncReadExt,
+	sync::{
+		broadcast::{self, error::RecvError},
+		OwnedMutexGuard,
+	},
+};
+use zip::ZipWriter;
+
+/// Library listed by [`libraries_index`]
+#[derive(Debug, Serialize)]
+struct Library {
+	/// Name of the library
+	name: String,
+	/// Display name of the library
+	display_name: String,
+	/// URL of the icon provided by the plugin
+	icon: Option<String>,
+	/// Description provided by the plugin
+	description: Option<String>,
+}
 
 /// `GET /api/libraries`
-#[inline]
+///
+/// Custom display names override the ones provided by the plugins.
+/// Libraries are listed by their configured [position](crate::config::MediaConfig#structfield.position),
+/// then by display name, so that the list does not change between calls.
+/// If no media plugin is loaded, a `503 Service Unavailable` response explains why there is no library.
 #[axum::debug_handler(state = AppState)]
-async fn libraries_index(State(plugins): State<Arc<PluginStore>>) -> Json<HashMap<String, String>> {
-	Json(
-		plugins
-			.media
-			.iter()
-			.map(|(name, plugin)| (name.clone(), plugin.media.name.to_str().to_owned()))
-			.collect(),
+async fn libraries_index(
+	State(plugins): State<Arc<PluginStore>>,
+	State(config): State<Config>,
+	DbConn(conn): DbConn,
+) -> Result<Json<Vec<Library>>, (StatusCode, String)> {
+	if plugins.media.is_empty() {
+		return Err((
+			StatusCode::SERVICE_UNAVAILABLE,
+			"No media plugin is loaded, so there is no library; the server logs tell where plugins are searched".to_owned(),
+		));
+	}
+	let map_err = |err: rusqlite::Error| (StatusCode::INTERNAL_SERVER_ERROR, err.to_string());
+
+	let mut display_names = {
+		let mut stmt = conn
+			.prepare_cached(
+				"SELECT name, display_name FROM libraries WHERE display_name IS NOT NULL",
+			)
+			.map_err(map_err)?;
+		let rows = stmt
+			.query_map((), |row| {
+				Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+			})
+			.map_err(map_err)?;
+		rows.collect::<Result<HashMap<_, _>, _>>()
+			.map_err(map_err)?
+	};
+
+	let mut libraries = plugins
+		.media
+		.iter()
+		.map(|(name, plugin)| {
+			let display_name = display_names
+				.remove(name)
+				.unwrap_or_else(|| plugin.media.name.to_str().to_owned());
+			Library {
+				name: name.clone(),
+				display_name,
+				icon: plugin
+					.media
+					.icon
+					.map(|icon| icon.to_str().to_owned())
+					.into(),
+				description: plugin
+					.media
+					.description
+					.map(|description| description.to_str().to_owned())
+					.into(),
+			}
+		})
+		.collect::<Vec<_>>();
+	libraries.sort_by_cached_key(|library| {
+		(
+			config
+				.media
+				.get(&library.name)
+				.map_or(0, |config| config.position),
+			library.display_name.to_lowercase(),
+			library.name.clone(),
+		)
+	});
+	Ok(Json(libraries))
+}
+
+/// Request body of [`libraries_update`]
+#[derive(Debug, Deserialize)]
+struct LibrariesUpdate {
+	/// Custom display name of the library
+	///
+	/// If `null` or empty, the name provided by the plugin is restored.
+	name: Option<String>,
+}
+
+/// `PATCH /api/libraries/:name`
+#[axum::debug_handler(state = AppState)]
+async fn libraries_update(
+	_: Writable,
+	State(plugins): State<Arc<PluginStore>>,
+	DbConn(conn): DbConn,
+	Path(name): Path<String>,
+	Json(update): Json<LibrariesUpdate>,
+) -> Result<Json<String>, (StatusCode, String)> {
+	let plugin = get_library(&plugins, &name)?;
+	let display_name = update
+		.name
+		.map(|display_name| display_name.trim().to_owned())
+		.filter(|display_name| !display_name.is_empty());
+
+	conn.execute(
+		"INSERT INTO libraries(name, display_name) VALUES (?, ?)",
+		(&name, &display_name),
 	)
+	.map_err(|err| (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()))?;
+
+	Ok(Json(
+		display_name.unwrap_or_else(|| plugin.media.name.to_str().to_owned()),
+	))
+}
+
+/// Returns the media plugin of the requested library
+fn get_library<'p>(
+	plugins: &'p PluginStore,
+	name: &str,
+) -> Result<&'p MediaPlugin, (StatusCode, String)> {
+	plugins.media.get(name).ok_or_else(|| {
+		let removed = plugins.removed.iter().find(|plugin| plugin.name == name);
+		if removed.is_some_and(|plugin| plugin.disabled) {
+			(
+				StatusCode::NOT_FOUND,
+				"The plugin of the requested library is disabled".to_owned(),
+			)
+		} else if removed.is_some() {
+			(
+				StatusCode::NOT_FOUND,
+				"The plugin of the requested library is not loaded anymore".to_owned(),
+			)
+		} else {
+			(
+				StatusCode::NOT_FOUND,
+				"The requested library does not exist".to_owned(),
+			)
+		}
+	})
+}
+
+/// Checks if the values of the given field are normalized when grouping media
+fn is_normalized(config: &Config, name: &str, field: &MetadataField) -> bool {
+	field.r#type == MetadataFieldType::Text
+		&& config.media.get(name).map_or(false, |config| {
+			config
+				.normalize
+				.fields
+				.iter()
+				.any(|ident| *ident == *field.ident)
+		})
+}
+
+/// Returns a function that maps database errors of the requested library to responses
+///
+/// If the table of the library is missing, it is recreated and a `503 Service Unavailable` response is returned.
+fn map_library_err<'a>(
+	plugins: &'a PluginStore,
+	db_pool: &'a Pool<SqliteConnectionManager>,
+	name: &'a str,
+) -> impl Fn(rusqlite::Error) -> (StatusCode, String) + Copy + 'a {
+	move |err| match err {
+		rusqlite::Error::SqliteFailure(_, Some(ref msg)) if msg.starts_with("no such table") => {
+			log::warn!("The table of library {name} is missing, recreating it");
+			if let Err(err) = plugins.rebuild_media_table(name, db_pool) {
+				log::error!("Could not recreate the table of library {name}: {err}");
+			}
+			(
+				StatusCode::SERVICE_UNAVAILABLE,
+				"The requested library is not yet initialized".to_owned(),
+			)
+		}
+		err => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()),
+	}
+}
+
+/// User data attached to a media
+///
+/// It is stored apart from the plugins' tables, so that it survives reindexes and plugin upgrades.
+#[derive(Debug, Default, Clone, Copy, Serialize, Deserialize)]
+struct MediaUserData {
+	/// Rating of the media, from 1 to 5
+	rating: Option<u8>,
+	/// Is the media a favorite?
+	#[serde(default)]
+	favorite: bool,
+}
+
+/// Maximum number of media returned at once by listings
+const MAX_LISTING_LIMIT: u32 = 1000;
+
+/// Query parameters of the listings of media, as sent
+#[derive(Debug, Default, Deserialize)]
+struct ListingQuery {
+	/// See [`ListingParams.limit`](ListingParams#structfield.limit)
+	limit: Option<u32>,
+	/// See [`ListingParams.offset`](ListingParams#structfield.offset)
+	#[serde(default)]
+	offset: u32,
+	/// See [`ListingParams.sort`](ListingParams#structfield.sort)
+	sort: Option<String>,
+	/// See [`ListingParams.order`](ListingParams#structfield.order)
+	#[serde(default)]
+	order: SortOrder,
+	/// See [`ListingParams.nulls`](ListingParams#structfield.nulls)
+	#[serde(default)]
+	nulls: NullsOrder,
+	/// See [`ListingParams.datetime`](ListingParams#structfield.datetime)
+	datetime: Option<DateTimeFormat>,
+	/// See [`ListingParams.raw_metadata`](ListingParams#structfield.raw_metadata)
+	#[serde(default)]
+	raw_metadata: bool,
+	/// See [`ListingParams.q`](ListingParams#structfield.q)
+	q: Option<String>,
+}
+
+/// Options of the listings of media, read from the query parameters
+///
+/// They are validated against the schema of the requested library,
+/// and invalid ones are rejected with a `400 Bad Request` response.
+#[derive(Debug)]
+struct ListingParams {
+	/// Name of the library
+	library: String,
+	/// Lowercased text that the media must contain
+	///
+	/// It is only accepted for [searchable](crate::config::MediaConfig#structfield.search) libraries.
+	q: Option<String>,
+	/// Maximum number of media to return, capped to [`MAX_LISTING_LIMIT`]
+	limit: Option<u32>,
+	/// Number of media to skip
+	offset: u32,
+	/// Identifier of the field to sort by
+	///
+	/// Media sharing the same value are sorted by path, so that the order is stable.
+	sort: Option<String>,
+	/// Direction of the sort
+	order: SortOrder,
+	/// Placement of media without a value when sorting
+	nulls: NullsOrder,
+	/// Output format of date/time values
+	///
+	/// Defaults to [`Config.datetime_format`](Config#structfield.datetime_format).
+	datetime: DateTimeFormat,
+	/// Include the raw metadata of the media, parsed, in their `raw_metadata` field?
+	///
+	/// It is `null` if the library does not [store it](crate::config::MediaConfig#structfield.raw_metadata).
+	raw_metadata: bool,
+}
+impl ListingParams {
+	/// Returns the SQL clause that filters, orders and paginates the media, with its parameters
+	///
+	/// Without a field to sort by, the media are ordered by `default_order`.
+	fn clause(&self, default_order: &str) -> (String, Vec<Box<dyn ToSql>>) {
+		let order = self.sort.as_deref().map_or_else(
+			|| default_order.to_owned(),
+			|sort| {
+				format!(
+					"{sort} {} {}, path",
+					self.order.to_sql(),
+					self.nulls.to_sql()
+				)
+			},
+		);
+		let mut params = Vec::<Box<dyn ToSql>>::new();
+		let filter = if let Some(ref q) = self.q {
+			let pattern = q
+				.replace('\\', "\\\\")
+				.replace('%', "\\%")
+				.replace('_', "\\_");
+			params.push(Box::new(self.library.clone()));
+			params.push(Box::new(format!("%{pattern}%")));
+			"WHERE path IN (SELECT path FROM media_search WHERE library = ? AND text LIKE ? ESCAPE '\\')"
+		} else {
+			""
+		};
+		params.push(Box::new(self.limit.map_or(-1, i64::from)));
+		params.push(Box::new(i64::from(self.offset)));
+		(
+			format!("{filter} ORDER BY {order} LIMIT ? OFFSET ?"),
+			params,
+		)
+	}
+
+	/// Returns a tag identifying these options, to be part of an entity tag
+	///
+	/// The searched text is hashed, since it may contain characters that cannot be part of a tag.
+	fn tag(&self) -> String {
+		format!(
+			"{:?}-{}-{}-{}-{:?}-{:?}-{}-{}",
+			self.datetime,
+			self.q.as_ref().map_or_else(String::new, |q| {
+				let mut hasher = DefaultHasher::new();
+				q.hash(&mut hasher);
+				format!("{:x}", hasher.finish())
+			}),
+			if self.raw_metadata { "raw" } else { "" },
+			self.sort.as_deref().unwrap_or_default(),
+			self.order,
+			self.nulls,
+			self.limit
+				.map_or_else(String::new, |limit| limit.to_string()),
+			self.offset,
+		)
+	}
+}
+#[axum::async_trait]
+impl<S> FromRequestParts<S> for ListingParams
+where
+	Arc<PluginStore>: FromRef<S>,
+	Config: FromRef<S>,
+	S: Send + Sync,
+{
+	type Rejection = (StatusCode, String);
+
+	async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+		let Query(query) = Query::<ListingQuery>::from_request_parts(parts, state)
+			.await
+			.map_err(|err| (StatusCode::BAD_REQUEST, err.body_text()))?;
+		let Path(path) = Path::<HashMap<String, String>>::from_request_parts(parts, state)
+			.await
+			.map_err(|err| (err.status(), err.body_text()))?;
+		let plugins = Arc::<PluginStore>::from_ref(state);
+		let config = Config::from_ref(state);
+		let library = path.get("libraries_id").cloned().unwrap_or_default();
+		let plugin = get_library(&plugins, &library)?;
+
+		let q = query
+			.q
+			.map(|q| q.trim().to_lowercase())
+			.filter(|q| !q.is_empty());
+		if q.is_some()
+			&& !config
+				.media
+				.get(&library)
+				.map_or(false, |config| config.search)
+		{
+			return Err((
+				StatusCode::BAD_REQUEST,
+				"The library is not searchable".to_owned(),
+			));
+		}
+
+		if let Some(ref sort) = query.sort {
+			if !["path", "mtime", "size"]
+				.into_iter()
+				.chain(
+					plugin
+						.media
+						.fields
+						.iter()
+						.filter(|field| !field.is_list && field.r#type != MetadataFieldType::Blob)
+						.map(|field| field.ident.to_str()),
+				)
+				.any(|ident| ident == sort)
+			{
+				return Err((
+					StatusCode::BAD_REQUEST,
+					format!("The library cannot be sorted by {sort:?}"),
+				));
+			}
+		}
+
+		Ok(Self {
+			library,
+			q,
+			limit: query.limit.map(|limit| limit.min(MAX_LISTING_LIMIT)),
+			offset: query.offset,
+			sort: query.sort,
+			order: query.order,
+			nulls: query.nulls,
+			datetime: query.datetime.unwrap_or(config.datetime_format),
+			raw_metadata: query.raw_metadata,
+		})
+	}
+}
+
+/// Direction of a sort
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum SortOrder {
+	/// Ascending order
+	#[default]
+	Asc,
+	/// Descending order
+	Desc,
+}
+impl SortOrder {
+	/// Returns the SQL keyword of this order
+	#[inline]
+	const fn to_sql(self) -> &'static str {
+		match self {
+			Self::Asc => "ASC",
+			Self::Desc => "DESC",
+		}
+	}
+}
+
+/// Placement of `NULL` values in a sort
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum NullsOrder {
+	/// `NULL` values come first, whatever the direction of the sort
+	First,
+	/// `NULL` values come last, whatever the direction of the sort
+	#[default]
+	Last,
+}
+impl NullsOrder {
+	/// Returns the SQL clause of this placement
+	#[inline]
+	const fn to_sql(self) -> &'static str {
+		match self {
+			Self::First => "NULLS FIRST",
+			Self::Last => "NULLS LAST",
+		}
+	}
+}
+
+/// Kind of a date/time column
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum DateTimeColumn {
+	/// Date and time with offset
+	DateTime,
+	/// Date only
+	Date,
+	/// Time only
+	Time,
+}
+impl DateTimeColumn {
+	/// Reads the value of the column and formats it
+	///
+	/// If a stored date or time cannot be parsed, it is returned as is.
+	fn read(self, row: &Row<'_>, col: &str, format: DateTimeFormat) -> rusqlite::Result<Value> {
+		/// RFC 3339 `full-date`
+		const FULL_DATE: &[FormatItem<'_>] = format_description!("[year]-[month]-[day]");
+		/// RFC 3339 `partial-time`
+		const PARTIAL_TIME: &[FormatItem<'_>] = format_description!("[hour]:[minute]:[second]");
+
+		let formatted = match self {
+			Self::DateTime => {
+				let Some(datetime) = row.get::<_, Option<OffsetDateTime>>(col)? else {
+					return Ok(Value::Null);
+				};
+				match format {
+					DateTimeFormat::Iso8601 => datetime.format(&Iso8601::DEFAULT).map(Value::from),
+					DateTimeFormat::Unix => Ok(datetime.unix_timestamp().into()),
+					DateTimeFormat::Rfc3339 => datetime.format(&Rfc3339).map(Value::from),
+				}
+			}
+			Self::Date | Self::Time => {
+				let Some(value) = row.get::<_, Option<String>>(col)? else {
+					return Ok(Value::Null);
+				};
+				let formatted = if self == Self::Date {
+					Date::parse(&value, &Iso8601::DEFAULT).map(|date| match format {
+						DateTimeFormat::Iso8601 => date.format(&Iso8601::DEFAULT).map(Value::from),
+						DateTimeFormat::Unix => {
+							Ok(date.midnight().assume_utc().unix_timestamp().into())
+						}
+						DateTimeFormat::Rfc3339 => date.format(FULL_DATE).map(Value::from),
+					})
+				} else {
+					Time::parse(&value, &Iso8601::DEFAULT).map(|time| match format {
+						DateTimeFormat::Iso8601 => time.format(&Iso8601::DEFAULT).map(Value::from),
+						DateTimeFormat::Unix => Ok((time - Time::MIDNIGHT).whole_seconds().into()),
+						DateTimeFormat::Rfc3339 => time.format(PARTIAL_TIME).map(Value::from),
+					})
+				};
+				match formatted {
+					Ok(formatted) => formatted,
+					Err(_err) => return Ok(Value::String(value)),
+				}
+			}
+		};
+		formatted.map_err(|err| rusqlite::Error::ToSqlConversionFailure(err.into()))
+	}
 }
 
 /// `GET /api/libraries/:name`
+///
+/// The listing is tagged with the generation of the library, which changes every time its media do.
 #[axum::debug_handler(state = AppState)]
 async fn libraries_show(
 	State(plugins): State<Arc<PluginStore>>,
+	State(db_pool): State<Pool<SqliteConnectionManager>>,
+	State(public_url): State<PublicUrl>,
 	DbConn(conn): DbConn,
 	Path(name): Path<String>,
+	params: ListingParams,
+	headers: HeaderMap,
+) -> Result<Response, (StatusCode, String)> {
+	let plugin = get_library(&plugins, &name)?;
+	let map_err = map_library_err(&plugins, &db_pool, &name);
+	let (clause, clause_params) = params.clause("id");
+
+	let etag = format!(
+		"\"{}-{}\"",
+		db::generation(&conn, &name).map_err(map_err)?,
+		params.tag(),
+	);
+	if is_not_modified(&headers, &etag) {
+		return Ok((StatusCode::NOT_MODIFIED, [(header::ETAG, etag)]).into_response());
+	}
+	let media = read_media(
+		&conn,
+		plugin,
+		&name,
+		&public_url,
+		params.datetime,
+		params.raw_metadata,
+		&clause,
+		clause_params,
+	)
+	.map_err(map_err)?;
+	Ok(([(header::ETAG, etag)], Json(media)).into_response())
+}
+
+/// Checks if the `If-None-Match` header of a request matches an entity tag
+fn is_not_modified(headers: &HeaderMap, etag: &str) -> bool {
+	headers
+		.get_all(header::IF_NONE_MATCH)
+		.iter()
+		.filter_map(|value| value.to_str().ok())
+		.flat_map(|value| value.split(','))
+		.any(|value| value.trim() == etag || value.trim() == "*")
+}
+
+/// Default number of media returned by [`libraries_recent`]
+const DEFAULT_RECENT_LIMIT: u32 = 50;
+
+/// `GET /api/libraries/:name/recent`
+///
+/// Lists the media of a library from the most recently modified file, unless another sort is requested.
+#[axum::debug_handler(state = AppState)]
+async fn libraries_recent(
+	State(plugins): State<Arc<PluginStore>>,
+	State(db_pool): State<Pool<SqliteConnectionManager>>,
+	State(public_url): State<PublicUrl>,
+	DbConn(conn): DbConn,
+	Path(name): Path<String>,
+	mut params: ListingParams,
 ) -> Result<Json<Vec<HashMap<String, Value>>>, (StatusCode, String)> {
-	let plugin = plugins.media.get(&name).ok_or_else(|| {
+	let plugin = get_library(&plugins, &name)?;
+	let map_err = map_library_err(&plugins, &db_pool, &name);
+	params.limit.get_or_insert(DEFAULT_RECENT_LIMIT);
+	let (clause, clause_params) = params.clause("mtime DESC, id DESC");
+
+	read_media(
+		&conn,
+		plugin,
+		&name,
+		&public_url,
+		params.datetime,
+		params.raw_metadata,
+		&clause,
+		clause_params,
+	)
+	.map(Json)
+	.map_err(map_err)
+}
+
+/// Reads the media of a library as JSON objects
+///
+/// `clause` is appended to the `SELECT` statement (e.g. to order or paginate the rows),
+/// and `clause_params` are bound to its parameters.
+/// The user data of each media is joined to it.
+/// If `raw_metadata` is set, the parsed raw metadata of each media is added to it.
+#[allow(clippy::too_many_arguments)]
+fn read_media(
+	conn: &Connection,
+	plugin: &MediaPlugin,
+	name: &str,
+	public_url: &PublicUrl,
+	datetime_format: DateTimeFormat,
+	raw_metadata: bool,
+	clause: &str,
+	clause_params: Vec<Box<dyn ToSql>>,
+) -> rusqlite::Result<Vec<HashMap<String, Value>>> {
+	let datetime_cols = plugin
+		.media
+		.fields
+		.iter()
+		.filter(|field| !field.is_list)
+		.filter_map(|field| match field.r#type {
+			MetadataFieldType::Date => Some((field.ident.to_str(), DateTimeColumn::Date)),
+			MetadataFieldType::Time => Some((field.ident.to_str(), DateTimeColumn::Time)),
+			_ => None,
+		})
+		.chain([("mtime", DateTimeColumn::DateTime)])
+		.collect::<HashMap<_, _>>();
+	let blob_cols = plugin
+		.media
+		.fields
+		.iter()
+		.filter(|field| !field.is_list && field.r#type == MetadataFieldType::Blob)
+		.map(|field| field.ident.to_str())
+		.collect::<HashSet<_>>();
+	let duration_cols = plugin
+		.media
+		.fields
+		.iter()
+		.filter(|field| !field.is_list && field.r#type == MetadataFieldType::Duration)
+		.map(|field| field.ident.to_str())
+		.collect::<HashSet<_>>();
+
+	let mut raw_metadata_stmt = raw_metadata
+		.then(|| {
+			conn.prepare_cached(
+				"SELECT raw_metadata FROM media_raw_metadata WHERE library = ? AND path = ?",
+			)
+		})
+		.transpose()?;
+
+	// NOTE: The columns of the user data cannot clash with fields, whose identifiers have no `.`
+	let mut stmt = conn.prepare_cached(&format!(
+		r#"SELECT {table}.*, "user.rating", "user.favorite" FROM {table} LEFT JOIN (SELECT path AS "user.path", rating AS "user.rating", favorite AS "user.favorite" FROM media_user_data WHERE library = ?) ON "user.path" = path {clause}"#,
+		table = plugin.table_ident()
+	))?;
+	let mut cols = stmt
+		.column_names()
+		.into_iter()
+		.map(|s| s.to_owned())
+		.collect::<Vec<_>>();
+	// NOTE: The user data are the last two columns
+	let user_col = cols.len() - 2;
+	cols.truncate(user_col);
+	let params = std::iter::once(Box::new(name.to_owned()) as Box<dyn ToSql>).chain(clause_params);
+	let rows = stmt.query_map(rusqlite::params_from_iter(params), |row| {
+		let user_data = MediaUserData {
+			rating: row.get(user_col)?,
+			favorite: row
+				.get::<_, Option<bool>>(user_col + 1)?
+				.unwrap_or_default(),
+		};
+		cols.iter()
+			.map(|col| {
+				if blob_cols.contains(col.as_str()) {
+					// NOTE: Binary data is served by `media_blob`
+					let is_null = row.get_ref(col.as_str())?.as_blob_or_null()?.is_none();
+					let url = if is_null {
+						Value::Null
+					} else {
+						public_url
+							.link(&format!(
+								"/api/libraries/{name}/{}/{col}",
+								row.get::<_, i64>("id")?
+							))
+							.into()
+					};
+					return Ok((col.to_owned(), url));
+				}
+				if duration_cols.contains(col.as_str()) {
+					// NOTE: Durations are stored in milliseconds, but served in seconds
+					#[allow(clippy::cast_precision_loss)]
+					let seconds = row.get::<_, Option<i64>>(col.as_str())?
+						.map_or(Value::Null, |millis| (millis as f64 / 1000.0).into());
+					return Ok((col.to_owned(), seconds));
+				}
+				if let Some(kind) = datetime_cols.get(col.as_str()) {
+					return kind
+						.read(row, col, datetime_format)
+						.map(|val| (col.to_owned(), val));
+				}
+				row.get::<_, Value>(col.as_str())
+					.or_else(|err| match err {
+						rusqlite::Error::FromSqlConversionFailure(..) => {
+							row.get::<_, String>(col.as_str()).map(Value::from)
+						}
+						_ => Err(err),
+					})
+					.map(|val| (col.to_owned(), val))
+			})
+			.collect::<Result<HashMap<String, Value>, _>>()
+			.and_then(|mut media| {
+				let path = match media.get("path") {
+					Some(Value::String(path)) => Some(path.clone()),
+					_ => None,
+				};
+				if let Some(ref mut raw_metadata_stmt) = raw_metadata_stmt {
+					let data = match path {
+						Some(ref path) => raw_metadata_stmt
+							.query_row((name, path), |row| row.get::<_, Vec<u8>>(0))
+							.optional()?,
+						None => None,
+					};
+					// NOTE: Raw metadata that cannot be read is left out, like missing one
+					let value = data
+						.and_then(|data| db::decompress(&data).ok())
+						.and_then(|data| serde_json::from_slice(&data).ok())
+						.unwrap_or_default();
+					media.insert("raw_metadata".to_owned(), value);
+				}
+				media.insert(
+					"user".to_owned(),
+					serde_json::to_value(user_data).unwrap_or_else(|_err| unreachable!()),
+				);
+				Ok(media)
+			})
+	})?;
+	rows.collect()
+}
+
+/// Requested media, read from the path
+///
+/// A `404 Not Found` response is returned if the library does not exist,
+/// and a `400 Bad Request` response if the identifier of the media is not an integer.
+/// Whether the media exists is checked by [`get_media_path`].
+#[derive(Debug, Clone)]
+struct MediaId {
+	/// Name of the library
+	library: String,
+	/// Identifier of the media
+	id: i64,
+}
+#[axum::async_trait]
+impl<S> FromRequestParts<S> for MediaId
+where
+	Arc<PluginStore>: FromRef<S>,
+	S: Send + Sync,
+{
+	type Rejection = (StatusCode, String);
+
+	async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+		let Path(path) = Path::<HashMap<String, String>>::from_request_parts(parts, state)
+			.await
+			.map_err(|err| (err.status(), err.body_text()))?;
+		let plugins = Arc::<PluginStore>::from_ref(state);
+		let library = path.get("libraries_id").cloned().unwrap_or_default();
+		get_library(&plugins, &library)?;
+
+		let id = path
+			.get("media_id")
+			.and_then(|id| id.parse().ok())
+			.ok_or_else(|| {
+				(
+					StatusCode::BAD_REQUEST,
+					"The identifier of the media must be an integer".to_owned(),
+				)
+			})?;
+		Ok(Self { library, id })
+	}
+}
+
+/// Returns the path of the requested media
+///
+/// A `404 Not Found` response is returned if the media does not exist.
+fn get_media_path(
+	conn: &Connection,
+	plugin: &MediaPlugin,
+	id: i64,
+	map_err: impl Fn(rusqlite::Error) -> (StatusCode, String),
+) -> Result<String, (StatusCode, String)> {
+	conn.query_row(
+		&format!(
+			"SELECT path FROM {table} WHERE id = ?",
+			table = plugin.table_ident()
+		),
+		[id],
+		|row| row.get::<_, String>(0),
+	)
+	.optional()
+	.map_err(map_err)?
+	.ok_or_else(|| {
 		(
 			StatusCode::NOT_FOUND,
-			"The requested library does not exist".to_owned(),
+			"The requested media does not exist".to_owned(),
+		)
+	})
+}
+
+/// `PUT /api/libraries/:name/:id/rating`
+#[axum::debug_handler(state = AppState)]
+async fn media_rating_update(
+	_: Writable,
+	State(plugins): State<Arc<PluginStore>>,
+	State(db_pool): State<Pool<SqliteConnectionManager>>,
+	DbConn(conn): DbConn,
+	MediaId { library: name, id }: MediaId,
+	Json(user_data): Json<MediaUserData>,
+) -> Result<Json<MediaUserData>, (StatusCode, String)> {
+	let plugin = get_library(&plugins, &name)?;
+	if user_data
+		.rating
+		.is_some_and(|rating| !(1..=5).contains(&rating))
+	{
+		return Err((
+			StatusCode::UNPROCESSABLE_ENTITY,
+			"The rating must be between 1 and 5".to_owned(),
+		));
+	}
+	let map_err = map_library_err(&plugins, &db_pool, &name);
+
+	let path = get_media_path(&conn, plugin, id, map_err)?;
+	conn.execute(
+		"INSERT INTO media_user_data(library, path, rating, favorite) VALUES (:library, :path, :rating, :favorite)",
+		rusqlite::named_params! {
+			":library": name,
+			":path": path,
+			":rating": user_data.rating,
+			":favorite": user_data.favorite,
+		},
+	)
+	.map_err(map_err)?;
+	db::bump_generation(&conn, &name).map_err(map_err)?;
+
+	Ok(Json(user_data))
+}
+
+/// `GET /api/libraries/:name/:id/waveform`
+#[axum::debug_handler(state = AppState)]
+async fn media_waveform(
+	State(plugins): State<Arc<PluginStore>>,
+	State(db_pool): State<Pool<SqliteConnectionManager>>,
+	DbConn(conn): DbConn,
+	MediaId { library: name, id }: MediaId,
+) -> Result<Json<Vec<u8>>, (StatusCode, String)> {
+	let plugin = get_library(&plugins, &name)?;
+	let map_err = map_library_err(&plugins, &db_pool, &name);
+
+	let path = get_media_path(&conn, plugin, id, map_err)?;
+	conn.query_row(
+		"SELECT peaks FROM media_waveforms WHERE library = ? AND path = ?",
+		(&name, &path),
+		|row| row.get::<_, Vec<u8>>(0),
+	)
+	.optional()
+	.map_err(map_err)?
+	.map(Json)
+	.ok_or_else(|| {
+		(
+			StatusCode::NOT_FOUND,
+			"The requested media has no waveform".to_owned(),
+		)
+	})
+}
+
+/// `GET /api/libraries/:name/:id/raw_metadata`
+///
+/// Returns the raw output of the metadata extractor.
+#[axum::debug_handler(state = AppState)]
+async fn media_raw_metadata(
+	State(plugins): State<Arc<PluginStore>>,
+	State(db_pool): State<Pool<SqliteConnectionManager>>,
+	DbConn(conn): DbConn,
+	MediaId { library: name, id }: MediaId,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+	let plugin = get_library(&plugins, &name)?;
+	let map_err = map_library_err(&plugins, &db_pool, &name);
+
+	let path = get_media_path(&conn, plugin, id, map_err)?;
+	let data = conn
+		.query_row(
+			"SELECT raw_metadata FROM media_raw_metadata WHERE library = ? AND path = ?",
+			(&name, &path),
+			|row| row.get::<_, Vec<u8>>(0),
+		)
+		.optional()
+		.map_err(map_err)?
+		.ok_or_else(|| {
+			(
+				StatusCode::NOT_FOUND,
+				"The requested media has no raw metadata".to_owned(),
+			)
+		})?;
+	let data = db::decompress(&data)
+		.map_err(|err| (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()))?;
+	Ok(([(header::CONTENT_TYPE, "application/json")], data))
+}
+
+/// `GET /api/libraries/:name/:id/file`
+///
+/// Returns the media file.
+/// `HEAD` requests get the same headers without opening the file.
+#[axum::debug_handler(state = AppState)]
+async fn media_file(
+	State(plugins): State<Arc<PluginStore>>,
+	State(db_pool): State<Pool<SqliteConnectionManager>>,
+	DbConn(conn): DbConn,
+	method: Method,
+	MediaId { library: name, id }: MediaId,
+) -> Result<Response, (StatusCode, String)> {
+	let plugin = get_library(&plugins, &name)?;
+	let map_err = map_library_err(&plugins, &db_pool, &name);
+
+	let path = get_media_path(&conn, plugin, id, map_err)?;
+	drop(conn);
+
+	let source = Arc::clone(&plugins.source);
+	let file_path = path.clone();
+	let (info, file) = tokio::task::spawn_blocking(move || {
+		let file_path = std::path::Path::new(&file_path);
+		let info = source.metadata(file_path)?;
+		let file = if method == Method::HEAD {
+			None
+		} else {
+			Some(source.open(file_path)?)
+		};
+		Ok::<_, std::io::Error>((info, file))
+	})
+	.await
+	.map_err(|err| (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()))?
+	.map_err(|err| {
+		(
+			match err.kind() {
+				std::io::ErrorKind::NotFound => StatusCode::NOT_FOUND,
+				_ => StatusCode::INTERNAL_SERVER_ERROR,
+			},
+			err.to_string(),
 		)
 	})?;
-	let map_err = |err: rusqlite::Error| (StatusCode::INTERNAL_SERVER_ERROR, err.to_string());
+	let mime = std::path::Path::new(&path)
+		.file_name()
+		.and_then(|file_name| file_name.to_str())
+		.and_then(mime_db::lookup)
+		.unwrap_or("application/octet-stream");
+	let headers = (
+		[(header::CONTENT_TYPE, mime)],
+		super::file_headers(info.len, info.modified),
+	);
+	Ok(match file {
+		Some(file) => (headers, AsyncReadBody::new(file)).into_response(),
+		None => headers.into_response(),
+	})
+}
+
+/// `GET /api/libraries/:name/:id/:field`
+///
+/// Returns the binary data stored in a field.
+#[axum::debug_handler(state = AppState)]
+async fn media_blob(
+	State(plugins): State<Arc<PluginStore>>,
+	State(db_pool): State<Pool<SqliteConnectionManager>>,
+	DbConn(conn): DbConn,
+	MediaId { library: name, id }: MediaId,
+	Path((_, _, field)): Path<(String, String, String)>,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+	let plugin = get_library(&plugins, &name)?;
+	if !plugin.media.fields.iter().any(|media_field| {
+		!media_field.is_list
+			&& media_field.r#type == MetadataFieldType::Blob
+			&& media_field.ident.to_str() == field
+	}) {
+		return Err((
+			StatusCode::NOT_FOUND,
+			"The requested field does not exist".to_owned(),
+		));
+	}
+	let map_err = map_library_err(&plugins, &db_pool, &name);
+
+	let data = conn
+		.query_row(
+			&format!(
+				"SELECT {field} FROM {table} WHERE id = ?",
+				table = plugin.table_ident()
+			),
+			[id],
+			|row| row.get::<_, Option<Vec<u8>>>(0),
+		)
+		.optional()
+		.map_err(map_err)?
+		.ok_or_else(|| {
+			(
+				StatusCode::NOT_FOUND,
+				"The requested media does not exist".to_owned(),
+			)
+		})?
+		.ok_or_else(|| {
+			(
+				StatusCode::NOT_FOUND,
+				"The requested media has no such data".to_owned(),
+			)
+		})?;
+	Ok(([(header::CONTENT_TYPE, "application/octet-stream")], data))
+}
+
+/// Query parameters of [`libraries_group`]
+#[derive(Debug, Deserialize)]
+struct LibrariesGroupQuery {
+	/// Identifier of the field to group by
+	by: String,
+	/// Identifier of a binary field to pick a representative of each group from
+	cover: Option<String>,
+}
 
+/// Group of media sharing the same value
+#[derive(Debug, Serialize)]
+struct MediaGroup {
+	/// Value shared by the media of the group
+	value: Value,
+	/// Number of media in the group
+	count: i64,
+	/// URL of the representative binary data of the group
+	#[serde(skip_serializing_if = "Option::is_none")]
+	cover: Option<String>,
+}
+
+/// `GET /api/libraries/:name/group`
+///
+/// Groups the media of a library by the distinct values of a field.
+/// Lists are unnested, so that a media belongs to the group of each of its values.
+/// Text fields configured to be normalized are grouped by their normalized key,
+/// and each group shows one of its original values.
+#[axum::debug_handler(state = AppState)]
+async fn libraries_group(
+	State(plugins): State<Arc<PluginStore>>,
+	State(db_pool): State<Pool<SqliteConnectionManager>>,
+	State(public_url): State<PublicUrl>,
+	State(config): State<Config>,
+	DbConn(conn): DbConn,
+	Path(name): Path<String>,
+	Query(query): Query<LibrariesGroupQuery>,
+) -> Result<Json<Vec<MediaGroup>>, (StatusCode, String)> {
+	let plugin = get_library(&plugins, &name)?;
+	let field = plugin
+		.media
+		.fields
+		.iter()
+		.find(|field| field.ident.to_str() == query.by)
+		.ok_or_else(|| {
+			(
+				StatusCode::BAD_REQUEST,
+				format!("The library has no field {:?}", query.by),
+			)
+		})?;
+	let cover = query
+		.cover
+		.as_deref()
+		.map(|cover| {
+			plugin
+				.media
+				.fields
+				.iter()
+				.find(|field| {
+					!field.is_list
+						&& field.r#type == MetadataFieldType::Blob
+						&& field.ident.to_str() == cover
+				})
+				.map(|field| field.ident.to_str())
+				.ok_or_else(|| {
+					(
+						StatusCode::BAD_REQUEST,
+						format!("The library has no binary field {cover:?}"),
+					)
+				})
+		})
+		.transpose()?;
+	let map_err = map_library_err(&plugins, &db_pool, &name);
+
+	let table = plugin.table_ident();
+	let (value, key, count, source, params) = if is_normalized(&config, &name, field) {
+		(
+			"min(keys.value)".to_owned(),
+			"keys.key".to_owned(),
+			format!("count(DISTINCT {table}.id)"),
+			format!(
+				"{table} JOIN media_group_keys AS keys ON keys.path = {table}.path AND keys.library = ? AND keys.field = ?"
+			),
+			vec![name.as_str(), field.ident.to_str()],
+		)
+	} else if field.is_list {
+		(
+			"each.value".to_owned(),
+			"each.value".to_owned(),
+			"count(*)".to_owned(),
+			format!(
+				"{table}, json_each({table}.{field}) AS each",
+				field = field.ident
+			),
+			Vec::new(),
+		)
+	} else {
+		let value = format!("{table}.{}", field.ident);
+		(
+			value.clone(),
+			value,
+			"count(*)".to_owned(),
+			table.clone(),
+			Vec::new(),
+		)
+	};
+	let cover_id = cover.map_or_else(
+		|| "NULL".to_owned(),
+		|cover| format!("min(CASE WHEN {table}.{cover} IS NOT NULL THEN {table}.id END)"),
+	);
 	let mut stmt = conn
 		.prepare_cached(&format!(
-			"SELECT * FROM {table}",
-			table = plugin.table_ident()
+			"SELECT {value} AS value, {count} AS count, {cover_id} AS cover_id FROM {source} GROUP BY {key} ORDER BY {key}"
 		))
 		.map_err(map_err)?;
-	let cols = stmt
-		.column_names()
-		.into_iter()
-		.map(|s| s.to_owned())
-		.collect::<Vec<_>>();
 	let rows = stmt
-		.query_map((), |row| {
-			cols.iter()
-				.map(|col| {
-					row.get::<_, Value>(col.as_str())
-						.or_else(|err| match err {
-							rusqlite::Error::FromSqlConversionFailure(..) => {
-								row.get::<_, String>(col.as_str()).map(Value::from)
-							}
-							_ => Err(err),
-						})
-						.map(|val| (col.to_owned(), val))
-				})
-				.collect::<Result<HashMap<String, Value>, _>>()
+		.query_map(rusqlite::params_from_iter(params), |row| {
+			Ok(MediaGroup {
+				value: row.get("value").or_else(|err| match err {
+					rusqlite::Error::FromSqlConversionFailure(..) => {
+						row.get::<_, String>("value").map(Value::from)
+					}
+					_ => Err(err),
+				})?,
+				count: row.get("count")?,
+				cover: cover.and_then(|cover| {
+					row.get::<_, Option<i64>>("cover_id")
+						.ok()
+						.flatten()
+						.map(|id| public_url.link(&format!("/api/libraries/{name}/{id}/{cover}")))
+				}),
+			})
 		})
 		.map_err(map_err)?;
 	rows.collect::<Result<_, _>>().map(Json).map_err(map_err)
 }
 
+/// Query parameters of [`libraries_cover`]
+#[derive(Debug, Deserialize)]
+struct LibrariesCoverQuery {
+	/// Identifier of the field the group is made by
+	by: String,
+	/// Value shared by the media of the group, as listed by [`libraries_group`]
+	value: String,
+	/// Identifier of the binary field to return
+	///
+	/// Defaults to the first binary field of the media.
+	cover: Option<String>,
+}
+
+/// `GET /api/libraries/:name/cover`
+///
+/// Returns the binary data of the first media of a group that has some,
+/// so that a group can be illustrated without listing its media.
+/// Groups are matched like [`libraries_group`] makes them.
+/// The data is tagged with the generation of the library, which changes every time its media do.
+#[axum::debug_handler(state = AppState)]
+async fn libraries_cover(
+	State(plugins): State<Arc<PluginStore>>,
+	State(db_pool): State<Pool<SqliteConnectionManager>>,
+	State(config): State<Config>,
+	DbConn(conn): DbConn,
+	Path(name): Path<String>,
+	Query(query): Query<LibrariesCoverQuery>,
+	headers: HeaderMap,
+) -> Result<Response, (StatusCode, String)> {
+	let plugin = get_library(&plugins, &name)?;
+	let field = plugin
+		.media
+		.fields
+		.iter()
+		.find(|field| field.r#type != MetadataFieldType::Blob && field.ident.to_str() == query.by)
+		.ok_or_else(|| {
+			(
+				StatusCode::BAD_REQUEST,
+				format!("The library has no field {:?}", query.by),
+			)
+		})?;
+	let cover = plugin
+		.media
+		.fields
+		.iter()
+		.find(|field| {
+			!field.is_list
+				&& field.r#type == MetadataFieldType::Blob
+				&& query
+					.cover
+					.as_deref()
+					.map_or(true, |cover| field.ident.to_str() == cover)
+		})
+		.ok_or_else(|| {
+			(
+				StatusCode::BAD_REQUEST,
+				query.cover.as_ref().map_or_else(
+					|| "The library has no binary field".to_owned(),
+					|cover| format!("The library has no binary field {cover:?}"),
+				),
+			)
+		})?;
+	let map_err = map_library_err(&plugins, &db_pool, &name);
+
+	let etag = format!(
+		"\"{}-{}-{:x}\"",
+		db::generation(&conn, &name).map_err(map_err)?,
+		cover.ident,
+		{
+			let mut hasher = DefaultHasher::new();
+			(&query.by, &query.value).hash(&mut hasher);
+			hasher.finish()
+		},
+	);
+	if is_not_modified(&headers, &etag) {
+		return Ok((StatusCode::NOT_MODIFIED, [(header::ETAG, etag)]).into_response());
+	}
+
+	let table = plugin.table_ident();
+	let (filter, params) = if is_normalized(&config, &name, field) {
+		(
+			"path IN (SELECT path FROM media_group_keys WHERE library = ? AND field = ? AND key = ?)"
+				.to_owned(),
+			vec![
+				name.clone(),
+				field.ident.to_str().to_owned(),
+				config
+					.media
+					.get(&name)
+					.map(|config| config.normalize.key(&query.value))
+					.unwrap_or_default(),
+			],
+		)
+	} else if field.is_list {
+		(
+			format!(
+				"EXISTS (SELECT 1 FROM json_each({table}.{field}) WHERE value = ?)",
+				field = field.ident
+			),
+			vec![query.value],
+		)
+	} else {
+		(format!("{} = ?", field.ident), vec![query.value])
+	};
+	let data = conn
+		.query_row(
+			&format!(
+				"SELECT {cover} FROM {table} WHERE {cover} IS NOT NULL AND {filter} ORDER BY id LIMIT 1",
+				cover = cover.ident,
+			),
+			rusqlite::params_from_iter(params),
+			|row| row.get::<_, Vec<u8>>(0),
+		)
+		.optional()
+		.map_err(map_err)?
+		.ok_or_else(|| {
+			(
+				StatusCode::NOT_FOUND,
+				"The requested group has no such data".to_owned(),
+			)
+		})?;
+	Ok((
+		[
+			(header::CONTENT_TYPE, "application/octet-stream".to_owned()),
+			(header::ETAG, etag),
+		],
+		data,
+	)
+		.into_response())
+}
+
+/// Statistics of a library
+#[derive(Debug, Serialize)]
+struct LibraryStats {
+	/// Number of media
+	count: i64,
+	/// Total size (in bytes) of the media files
+	size: i64,
+	/// Total duration (in seconds) of the media
+	///
+	/// It is only computed if the plugin declares a numeric or duration `duration` field.
+	duration: Option<f64>,
+}
+
+/// `GET /api/libraries/:name/stats`
+#[axum::debug_handler(state = AppState)]
+async fn libraries_stats(
+	State(plugins): State<Arc<PluginStore>>,
+	State(db_pool): State<Pool<SqliteConnectionManager>>,
+	DbConn(conn): DbConn,
+	Path(name): Path<String>,
+) -> Result<Json<LibraryStats>, (StatusCode, String)> {
+	let plugin = get_library(&plugins, &name)?;
+	let map_err = map_library_err(&plugins, &db_pool, &name);
+
+	let duration = plugin
+		.media
+		.fields
+		.iter()
+		.find(|field| !field.is_list && field.ident.to_str() == "duration")
+		.and_then(|field| match field.r#type {
+			MetadataFieldType::Integer | MetadataFieldType::Real => Some("total(duration)"),
+			// NOTE: Durations are stored in milliseconds
+			MetadataFieldType::Duration => Some("total(duration) / 1000.0"),
+			_ => None,
+		});
+	conn.query_row(
+		&format!(
+			"SELECT count(*) AS count, sum(size) AS size, {duration} AS duration FROM {table}",
+			duration = duration.unwrap_or("NULL"),
+			table = plugin.table_ident(),
+		),
+		(),
+		|row| {
+			Ok(LibraryStats {
+				count: row.get("count")?,
+				size: row.get::<_, Option<i64>>("size")?.unwrap_or_default(),
+				duration: row.get("duration")?,
+			})
+		},
+	)
+	.map(Json)
+	.map_err(map_err)
+}
+
+/// Maximum number of facets returned at once
+const MAX_FACETS: u32 = 1000;
+
+/// Query parameters of [`libraries_facets`]
+#[derive(Debug, Deserialize)]
+struct LibrariesFacetsQuery {
+	/// Identifier of the field to list the values of
+	field: String,
+	/// Maximum number of values to return, capped to [`MAX_FACETS`]
+	#[serde(default = "LibrariesFacetsQuery::default_limit")]
+	limit: u32,
+	/// Number of values to skip
+	#[serde(default)]
+	offset: u32,
+}
+impl LibrariesFacetsQuery {
+	/// Default value for [`limit`](Self#structfield.limit)
+	#[inline]
+	const fn default_limit() -> u32 {
+		100
+	}
+}
+
+/// Distinct value of a field
+#[derive(Debug, Serialize)]
+struct Facet {
+	/// Value of the field
+	value: Value,
+	/// Number of media having this value
+	count: i64,
+}
+
+/// `GET /api/libraries/:name/facets`
+///
+/// Values of list fields are counted separately, and media without a value are not counted.
+/// The most common values come first.
+#[axum::debug_handler(state = AppState)]
+async fn libraries_facets(
+	State(plugins): State<Arc<PluginStore>>,
+	State(db_pool): State<Pool<SqliteConnectionManager>>,
+	DbConn(conn): DbConn,
+	Path(name): Path<String>,
+	Query(query): Query<LibrariesFacetsQuery>,
+) -> Result<Json<Vec<Facet>>, (StatusCode, String)> {
+	let plugin = get_library(&plugins, &name)?;
+	let field = plugin
+		.media
+		.fields
+		.iter()
+		.find(|field| {
+			field.r#type != MetadataFieldType::Blob && field.ident.to_str() == query.field
+		})
+		.ok_or_else(|| {
+			(
+				StatusCode::BAD_REQUEST,
+				format!("The library has no facets for field {:?}", query.field),
+			)
+		})?;
+	let map_err = map_library_err(&plugins, &db_pool, &name);
+
+	let table = plugin.table_ident();
+	let (value, source) = if field.is_list {
+		(
+			"each.value".to_owned(),
+			format!(
+				"{table}, json_each({table}.{field}) AS each",
+				field = field.ident
+			),
+		)
+	} else {
+		(format!("{table}.{}", field.ident), table.clone())
+	};
+	let mut stmt = conn
+		.prepare_cached(&format!(
+			"SELECT {value} AS value, count(DISTINCT {table}.id) AS count FROM {source} WHERE {value} IS NOT NULL GROUP BY {value} ORDER BY count DESC, {value} LIMIT ? OFFSET ?"
+		))
+		.map_err(map_err)?;
+	let rows = stmt
+		.query_map([query.limit.min(MAX_FACETS), query.offset], |row| {
+			Ok(Facet {
+				value: row.get("value").or_else(|err| match err {
+					rusqlite::Error::FromSqlConversionFailure(..) => {
+						row.get::<_, String>("value").map(Value::from)
+					}
+					_ => Err(err),
+				})?,
+				count: row.get("count")?,
+			})
+		})
+		.map_err(map_err)?;
+	rows.collect::<Result<_, _>>().map(Json).map_err(map_err)
+}
+
+/// Format of a playlist
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum PlaylistFormat {
+	/// [M3U](https://en.wikipedia.org/wiki/M3U) file
+	#[default]
+	M3u,
+	/// JSON array of URLs
+	Json,
+}
+
+/// Query parameters of [`libraries_playlist`]
+#[derive(Debug, Deserialize)]
+struct LibrariesPlaylistQuery {
+	/// Identifier of the field the media are grouped by
+	by: String,
+	/// Value of the group
+	value: String,
+	/// Format of the playlist
+	#[serde(default)]
+	format: PlaylistFormat,
+}
+
+/// `GET /api/libraries/:name/playlist`
+///
+/// Returns a playlist of the files of the media of a [group](libraries_group).
+/// They are ordered by track number if the plugin has an integer `track` field, then by path.
+/// The entries of M3U playlists are titled by the text `title` field if the plugin has one,
+/// falling back to the names of the files.
+#[axum::debug_handler(state = AppState)]
+async fn libraries_playlist(
+	State(plugins): State<Arc<PluginStore>>,
+	State(db_pool): State<Pool<SqliteConnectionManager>>,
+	State(public_url): State<PublicUrl>,
+	State(config): State<Config>,
+	DbConn(conn): DbConn,
+	Path(name): Path<String>,
+	Query(query): Query<LibrariesPlaylistQuery>,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+	let plugin = get_library(&plugins, &name)?;
+	let field = plugin
+		.media
+		.fields
+		.iter()
+		.find(|field| field.ident.to_str() == query.by)
+		.ok_or_else(|| {
+			(
+				StatusCode::BAD_REQUEST,
+				format!("The library has no field {:?}", query.by),
+			)
+		})?;
+	let map_err = map_library_err(&plugins, &db_pool, &name);
+
+	let table = plugin.table_ident();
+	let (filter, params) = if is_normalized(&config, &name, field) {
+		(
+			format!("EXISTS (SELECT 1 FROM media_group_keys AS keys WHERE keys.path = {table}.path AND keys.library = ? AND keys.field = ? AND keys.key = ?)"),
+			vec![
+				name.clone(),
+				field.ident.to_str().to_owned(),
+				config.media[&name].normalize.key(&query.value),
+			],
+		)
+	} else if field.is_list {
+		(
+			format!(
+				"EXISTS (SELECT 1 FROM json_each({table}.{field}) AS each WHERE each.value = ?)",
+				field = field.ident
+			),
+			vec![query.value],
+		)
+	} else {
+		(format!("{table}.{} = ?", field.ident), vec![query.value])
+	};
+	let has_field =
+		|ident: &str, r#type: MetadataFieldType| {
+			plugin.media.fields.iter().any(|field| {
+				!field.is_list && field.r#type == r#type && field.ident.to_str() == ident
+			})
+		};
+	let order = if has_field("track", MetadataFieldType::Integer) {
+		"track IS NULL, track, path"
+	} else {
+		"path"
+	};
+	let title = if has_field("title", MetadataFieldType::Text) {
+		"title"
+	} else {
+		"NULL"
+	};
+	let mut stmt = conn
+		.prepare_cached(&format!(
+			"SELECT id, path, {title} AS title FROM {table} WHERE {filter} ORDER BY {order}"
+		))
+		.map_err(map_err)?;
+	let rows = stmt
+		.query_map(rusqlite::params_from_iter(params), |row| {
+			Ok((
+				row.get::<_, i64>("id")?,
+				row.get::<_, String>("path")?,
+				row.get::<_, Option<String>>("title")?,
+			))
+		})
+		.map_err(map_err)?;
+	let tracks = rows
+		.map(|row| {
+			row.map(|(id, path, title)| {
+				(
+					title.unwrap_or_else(|| {
+						std::path::Path::new(&path)
+							.file_stem()
+							.map(|stem| stem.to_string_lossy().into_owned())
+							.unwrap_or(path)
+					}),
+					public_url.link(&format!("/api/libraries/{name}/{id}/file")),
+				)
+			})
+		})
+		.collect::<Result<Vec<_>, _>>()
+		.map_err(map_err)?;
+
+	Ok(match query.format {
+		PlaylistFormat::M3u => {
+			let playlist = std::iter::once("#EXTM3U\n".to_owned())
+				.chain(
+					tracks
+						.into_iter()
+						.map(|(title, url)| format!("#EXTINF:-1,{title}\n{url}\n")),
+				)
+				.collect::<String>();
+			([(header::CONTENT_TYPE, "audio/x-mpegurl")], playlist).into_response()
+		}
+		PlaylistFormat::Json => Json(
+			tracks
+				.into_iter()
+				.map(|(_title, url)| url)
+				.collect::<Vec<_>>(),
+		)
+		.into_response(),
+	})
+}
+
+/// Media file quarantined after repeated failed extractions
+#[derive(Debug, Serialize)]
+struct QuarantinedMedia {
+	/// Path of the file
+	path: String,
+	/// Number of consecutive failed extractions
+	failures: u32,
+}
+
+/// `GET /api/libraries/:name/quarantine`
+///
+/// Returns the files that are not probed anymore until they are modified.
+#[axum::debug_handler(state = AppState)]
+async fn libraries_quarantine(
+	State(plugins): State<Arc<PluginStore>>,
+	State(config): State<Config>,
+	DbConn(conn): DbConn,
+	Path(name): Path<String>,
+) -> Result<Json<Vec<QuarantinedMedia>>, (StatusCode, String)> {
+	get_library(&plugins, &name)?;
+	let map_err = |err: rusqlite::Error| (StatusCode::INTERNAL_SERVER_ERROR, err.to_string());
+
+	let quarantine_after = config.indexing.quarantine_after;
+	if quarantine_after == 0 {
+		return Ok(Json(Vec::new()));
+	}
+	let mut stmt = conn
+		.prepare_cached(
+			"SELECT path, failures FROM media_failures WHERE library = ? AND failures >= ? ORDER BY path",
+		)
+		.map_err(map_err)?;
+	let rows = stmt
+		.query_map((&name, quarantine_after), |row| {
+			Ok(QuarantinedMedia {
+				path: row.get(0)?,
+				failures: row.get(1)?,
+			})
+		})
+		.map_err(map_err)?;
+	rows.collect::<Result<Vec<_>, _>>()
+		.map(Json)
+		.map_err(map_err)
+}
+
+/// Request body of [`libraries_download`]
+#[derive(Debug, Deserialize)]
+struct LibrariesDownload {
+	/// Identifiers of the media to download
+	ids: Vec<i64>,
+}
+
+/// Size (in bytes) of the chunks of files streamed in archives
+const DOWNLOAD_CHUNK_SIZE: usize = 64 * 1024;
+
+/// `POST /api/libraries/:name/download`
+///
+/// Streams a ZIP archive of the files of the given media.
+/// Entries are named after the files, and a number is appended to the names already taken.
+#[axum::debug_handler(state = AppState)]
+async fn libraries_download(
+	_: Admin,
+	State(plugins): State<Arc<PluginStore>>,
+	State(config): State<Config>,
+	State(db_pool): State<Pool<SqliteConnectionManager>>,
+	DbConn(conn): DbConn,
+	Path(name): Path<String>,
+	Json(mut download): Json<LibrariesDownload>,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+	let plugin = get_library(&plugins, &name)?;
+	let map_err = map_library_err(&plugins, &db_pool, &name);
+
+	let mut seen = HashSet::new();
+	download.ids.retain(|id| seen.insert(*id));
+	if download.ids.is_empty() {
+		return Err((StatusCode::BAD_REQUEST, "No media is requested".to_owned()));
+	}
+	let max_files = config.download.max_files;
+	if download.ids.len() > usize::from(max_files) {
+		return Err((
+			StatusCode::PAYLOAD_TOO_LARGE,
+			format!("At most {max_files} files can be downloaded at once"),
+		));
+	}
+
+	let mut files = Vec::with_capacity(download.ids.len());
+	let mut total_size = 0_u64;
+	{
+		let mut stmt = conn
+			.prepare_cached(&format!(
+				"SELECT path, mtime, size FROM {table} WHERE id = ?",
+				table = plugin.table_ident()
+			))
+			.map_err(map_err)?;
+		for id in download.ids {
+			let (path, mtime, size) = stmt
+				.query_row([id], |row| {
+					Ok((
+						row.get::<_, String>(0)?,
+						row.get::<_, OffsetDateTime>(1)?,
+						row.get::<_, u64>(2)?,
+					))
+				})
+				.optional()
+				.map_err(map_err)?
+				.ok_or_else(|| {
+					(
+						StatusCode::NOT_FOUND,
+						format!("The media {id} does not exist"),
+					)
+				})?;
+			total_size = total_size.saturating_add(size);
+			files.push((path, mtime));
+		}
+	}
+	drop(conn);
+	let max_size = config.download.max_size;
+	if total_size > max_size {
+		return Err((
+			StatusCode::PAYLOAD_TOO_LARGE,
+			format!("At most {max_size} bytes can be downloaded at once"),
+		));
+	}
+	let entry_names = entry_names(files.iter().map(|(path, _)| path.as_str()));
+
+	let source = Arc::clone(&plugins.source);
+	let (mut body_tx, body) = axum::body::Body::channel();
+	let library = name.clone();
+	tokio::spawn(async move {
+		let mut zip = ZipWriter::default();
+		let res = async {
+			// NOTE: The client hung up if data cannot be sent
+			let hung_up = |_err| std::io::Error::from(std::io::ErrorKind::BrokenPipe);
+			for ((path, mtime), entry_name) in files.into_iter().zip(entry_names) {
+				let source = Arc::clone(&source);
+				let mut file =
+					tokio::task::spawn_blocking(move || source.open(std::path::Path::new(&path)))
+						.await??;
+				body_tx
+					.send_data(zip.start_entry(&entry_name, mtime)?.into())
+					.await
+					.map_err(hung_up)?;
+				loop {
+					let mut chunk = vec![0; DOWNLOAD_CHUNK_SIZE];
+					let len = file.read(&mut chunk).await?;
+					if len == 0 {
+						break;
+					}
+					chunk.truncate(len);
+					zip.write(&chunk)?;
+					body_tx.send_data(chunk.into()).await.map_err(hung_up)?;
+				}
+				body_tx
+					.send_data(zip.finish_entry()?.into())
+					.await
+					.map_err(hung_up)?;
+			}
+			body_tx
+				.send_data(zip.finish()?.into())
+				.await
+				.map_err(hung_up)
+		}
+		.await;
+		match res {
+			Ok(()) => {}
+			Err(ref err) if err.kind() == std::io::ErrorKind::BrokenPipe => {}
+			Err(err) => {
+				log::error!("Could not stream the archive of library {library}: {err}");
+				body_tx.abort();
+			}
+		}
+	});
+
+	let file_name = name
+		.chars()
+		.filter(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_'))
+		.collect::<String>();
+	Ok((
+		[
+			(header::CONTENT_TYPE, "application/zip".to_owned()),
+			(
+				header::CONTENT_DISPOSITION,
+				format!("attachment; filename=\"{file_name}.zip\""),
+			),
+		],
+		axum::body::boxed(body),
+	))
+}
+
+/// Returns the names of the entries of an archive of the given files
+///
+/// The names are the base names of the files, so that the archive cannot write outside of its directory.
+/// A number is appended to the names already taken, which are compared case-insensitively
+/// for the sake of case-insensitive filesystems.
+fn entry_names<'p>(paths: impl IntoIterator<Item = &'p str>) -> Vec<String> {
+	let mut taken = HashSet::new();
+	paths
+		.into_iter()
+		.map(|path| {
+			// NOTE: Backslashes are path separators for some extractors
+			let file_name = std::path::Path::new(path).file_name().map_or_else(
+				|| "file".to_owned(),
+				|s| s.to_string_lossy().replace('\\', "_"),
+			);
+			let (stem, extension) = match file_name.rsplit_once('.') {
+				Some((stem, extension)) if !stem.is_empty() => (stem, format!(".{extension}")),
+				_ => (file_name.as_str(), String::new()),
+			};
+			let mut entry_name = file_name.clone();
+			let mut number = 1;
+			while !taken.insert(entry_name.to_lowercase()) {
+				entry_name = format!("{stem} ({number}){extension}");
+				number += 1;
+			}
+			entry_name
+		})
+		.collect()
+}
+
+/// Query parameters of [`libraries_reindex`]
+#[derive(Debug, Deserialize)]
+struct LibrariesReindex {
+	/// Glob that the paths of the media files to reindex must match
+	r#match: String,
+}
+
+/// `POST /api/libraries/:name/reindex`
+///
+/// Extracts the metadata of the media files whose path matches a glob again, even if they did not change or are quarantined.
+/// The other media are left as they are.
+/// The [progress events](crate::plugins::Progress) are streamed as newline-delimited JSON.
+#[axum::debug_handler(state = AppState)]
+async fn libraries_reindex(
+	_: Admin,
+	_: Writable,
+	State(plugins): State<Arc<PluginStore>>,
+	State(config): State<Config>,
+	State(db_pool): State<Pool<SqliteConnectionManager>>,
+	Path(name): Path<String>,
+	Query(query): Query<LibrariesReindex>,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+	get_library(&plugins, &name)?;
+	let only = Glob::new(&query.r#match)
+		.map_err(|err| (StatusCode::BAD_REQUEST, err.to_string()))?
+		.compile_matcher();
+	let indexing = Arc::clone(&plugins.indexing)
+		.try_lock_owned()
+		.map_err(|_err| {
+			(
+				StatusCode::CONFLICT,
+				"Media are already being indexed".to_owned(),
+			)
+		})?;
+
+	Ok(stream_progress(indexing, move |progress| {
+		plugins
+			.reindex_media(&name, &db_pool, config.media.get(&name), &only, progress)
+			.map_err(|err| log::error!("Could not reindex library {name}: {err}"))
+	}))
+}
+
+/// `GET /api/plugins/:name/schema.sql`
+///
+/// Returns the SQL statements that create the database table of a media plugin.
+#[axum::debug_handler(state = AppState)]
+async fn plugins_schema(
+	_: Admin,
+	State(plugins): State<Arc<PluginStore>>,
+	Path(name): Path<String>,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+	let plugin = plugins.media.get(&name).ok_or_else(|| {
+		(
+			StatusCode::NOT_FOUND,
+			"The requested plugin does not exist".to_owned(),
+		)
+	})?;
+
+	Ok((
+		[(header::CONTENT_TYPE, "application/sql; charset=utf-8")],
+		plugin.schema(),
+	))
+}
+
+/// `POST /api/plugins/:name/rebuild`
+///
+/// Drops and recreates the database table of a media plugin, then loads its media again.
+/// The [progress events](crate::plugins::Progress) are streamed as newline-delimited JSON.
+#[axum::debug_handler(state = AppState)]
+async fn plugins_rebuild(
+	_: Admin,
+	_: Writable,
+	State(plugins): State<Arc<PluginStore>>,
+	State(config): State<Config>,
+	State(db_pool): State<Pool<SqliteConnectionManager>>,
+	Path(name): Path<String>,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+	if !plugins.media.contains_key(&name) {
+		return Err((
+			StatusCode::NOT_FOUND,
+			"The requested plugin does not exist".to_owned(),
+		));
+	}
+	let indexing = Arc::clone(&plugins.indexing)
+		.try_lock_owned()
+		.map_err(|_err| {
+			(
+				StatusCode::CONFLICT,
+				"Media are already being indexed".to_owned(),
+			)
+		})?;
+
+	Ok(stream_progress(indexing, move |progress| {
+		plugins
+			.rebuild_media(&name, &db_pool, config.media.get(&name), progress)
+			.map_err(|err| log::error!("Could not rebuild the table of plugin {name}: {err}"))
+	}))
+}
+
+/// Runs an indexing task on a blocking thread, while streaming its progress events
+///
+/// The [progress events](crate::plugins::Progress) are sent as newline-delimited JSON,
+/// and the response is aborted if the task fails.
+fn stream_progress(
+	indexing: OwnedMutexGuard<()>,
+	task: impl FnOnce(Progress<'_>) -> Result<(), ()> + Send + 'static,
+) -> impl IntoResponse {
+	let (mut body_tx, body) = axum::body::Body::channel();
+	let runtime = tokio::runtime::Handle::current();
+	tokio::task::spawn_blocking(move || {
+		let _indexing = indexing;
+		let (tx, rx) = mpsc::channel();
+		std::thread::scope(|scope| {
+			let forward = scope.spawn(move || {
+				for event in rx {
+					let Ok(mut line) = serde_json::to_vec(&event) else {
+						continue;
+					};
+					line.push(b'\n');
+					if runtime.block_on(body_tx.send_data(line.into())).is_err() {
+						// NOTE: The client hung up, but the task goes on
+						break;
+					}
+				}
+				body_tx
+			});
+			let res = task(Some(&tx));
+			drop(tx);
+			let body_tx = forward.join();
+			if res.is_err() {
+				if let Ok(body_tx) = body_tx {
+					body_tx.abort();
+				}
+			}
+		});
+	});
+
+	(
+		[(header::CONTENT_TYPE, "application/x-ndjson")],
+		axum::body::boxed(body),
+	)
+}
+
+/// Plugin found in the database but not loaded
+#[derive(Debug, Serialize)]
+struct RemovedPlugin {
+	/// Name of the plugin
+	name: String,
+	/// Kind of the plugin
+	kind: String,
+	/// Version of the plugin
+	version: String,
+	/// Database table of the plugin, if known
+	table: Option<String>,
+	/// Is the plugin disabled?
+	disabled: bool,
+	/// Was the data of the plugin dropped?
+	dropped: bool,
+}
+
+/// `GET /api/plugins/removed`
+///
+/// Returns the plugins found in the database at startup but not loaded.
+#[axum::debug_handler(state = AppState)]
+async fn plugins_removed(
+	_: Admin,
+	State(plugins): State<Arc<PluginStore>>,
+) -> Json<Vec<RemovedPlugin>> {
+	Json(
+		plugins
+			.removed
+			.iter()
+			.map(|plugin| RemovedPlugin {
+				name: plugin.name.clone(),
+				kind: plugin.kind.to_string(),
+				version: plugin.version.to_string(),
+				table: plugin.table.clone(),
+				disabled: plugin.disabled,
+				dropped: plugin.dropped,
+			})
+			.collect(),
+	)
+}
+
+/// Query parameters of [`admin_logs`]
+#[derive(Debug, Deserialize)]
+struct AdminLogsQuery {
+	/// Most verbose level of the streamed logs
+	///
+	/// Defaults to `info`.
+	level: Option<String>,
+}
+
+/// `GET /api/admin/logs`
+///
+/// Streams the log lines as [server-sent events](https://html.spec.whatwg.org/multipage/server-sent-events.html).
+/// If the client falls behind, the oldest lines are dropped and a `lagged` event tells how many.
+/// Clients over [`Config.max_log_subscribers`](Config#structfield.max_log_subscribers)
+/// are rejected with a `503 Service Unavailable` response.
+#[axum::debug_handler(state = AppState)]
+async fn admin_logs(
+	_: Admin,
+	State(logs): State<broadcast::Sender<LogLine>>,
+	State(config): State<Config>,
+	Query(query): Query<AdminLogsQuery>,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+	let level = query
+		.level
+		.as_deref()
+		.map_or(Ok(LevelFilter::Info), str::parse)
+		.map_err(|err| (StatusCode::BAD_REQUEST, format!("Invalid log level: {err}")))?;
+
+	// NOTE: Subscribing before counting ensures that concurrent clients cannot exceed the limit,
+	//       and the receiver is dropped (so the count decremented) when the client hangs up
+	let mut rx = logs.subscribe();
+	if logs.receiver_count() > config.max_log_subscribers {
+		return Err((
+			StatusCode::SERVICE_UNAVAILABLE,
+			"Too many clients are already streaming the logs".to_owned(),
+		));
+	}
+	let (mut body_tx, body) = axum::body::Body::channel();
+	tokio::spawn(async move {
+		loop {
+			let event = match rx.recv().await {
+				Ok(log) if log.level <= level => {
+					let mut event = "event: log\n".to_owned();
+					for line in log.line.lines() {
+						event.push_str("data: ");
+						event.push_str(line);
+						event.push('\n');
+					}
+					event.push('\n');
+					event
+				}
+				Ok(_) => continue,
+				Err(RecvError::Lagged(count)) => format!("event: lagged\ndata: {count}\n\n"),
+				Err(RecvError::Closed) => break,
+			};
+			if body_tx.send_data(event.into()).await.is_err() {
+				// NOTE: The client hung up
+				break;
+			}
+		}
+	});
+
+	Ok((
+		[
+			(header::CONTENT_TYPE, "text/event-stream"),
+			(header::CACHE_CONTROL, "no-cache"),
+		],
+		axum::body::boxed(body),
+	))
+}
+
+/// `GET /api/openapi.json`
+///
+/// Returns the [OpenAPI](https://spec.openapis.org/oas/v3.1.0) document of the API.
+#[axum::debug_handler(state = AppState)]
+async fn openapi(State(config): State<Config>) -> Json<Value> {
+	Json(openapi::document(&config.base_path))
+}
+
 /// Constructs a new configured [`Router`]
 pub(super) fn new_router() -> Router<AppState> {
 	let libraries = Resource::named("libraries")
 		.index(libraries_index)
-		.show(libraries_show);
+		.show(libraries_show)
+		.update(libraries_update);
+
+	Router::new()
+		.merge(libraries)
+		.route(
+			"/libraries/:libraries_id/group",
+			routing::get(libraries_group),
+		)
+		.route(
+			"/libraries/:libraries_id/cover",
+			routing::get(libraries_cover),
+		)
+		.route(
+			"/libraries/:libraries_id/stats",
+			routing::get(libraries_stats),
+		)
+		.route(
+			"/libraries/:libraries_id/facets",
+			routing::get(libraries_facets),
+		)
+		.route(
+			"/libraries/:libraries_id/playlist",
+			routing::get(libraries_playlist),
+		)
+		.route(
+			"/libraries/:libraries_id/recent",
+			routing::get(libraries_recent),
+		)
+		.route(
+			"/libraries/:libraries_id/quarantine",
+			routing::get(libraries_quarantine),
+		)
+		.route(
+			"/libraries/:libraries_id/download",
+			routing::post(libraries_download),
+		)
+		.route(
+			"/libraries/:libraries_id/reindex",
+			routing::post(libraries_reindex),
+		)
+		.route(
+			"/libraries/:libraries_id/:media_id/rating",
+			routing::put(media_rating_update),
+		)
+		.route(
+			"/libraries/:libraries_id/:media_id/waveform",
+			routing::get(media_waveform),
+		)
+		.route(
+			"/libraries/:libraries_id/:media_id/file",
+			routing::get(media_file),
+		)
+		.route(
+			"/libraries/:libraries_id/:media_id/raw_metadata",
+			routing::get(media_raw_metadata),
+		)
+		.route(
+			"/libraries/:libraries_id/:media_id/:field",
+			routing::get(media_blob),
+		)
+		.route("/plugins/removed", routing::get(plugins_removed))
+		.route("/plugins/:name/schema.sql", routing::get(plugins_schema))
+		.route("/plugins/:name/rebuild", routing::post(plugins_rebuild))
+		.route("/admin/logs", routing::get(admin_logs))
+		.route("/openapi.json", routing::get(openapi))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{
+		http::tests::{request, router},
+		plugins::stub::{self, Library},
+	};
+	use axum::{body::Body, http};
+	use tower::ServiceExt;
+
+	/// Reads the body of a response as JSON
+	async fn json(response: Response) -> Value {
+		let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+		serde_json::from_slice(&body).unwrap()
+	}
+
+	#[test]
+	fn entry_names_are_unique() {
+		assert_eq!(
+			entry_names([
+				"/music/a.mp3",
+				"/other/A.mp3",
+				"/music/b/a.mp3",
+				"/music/A (1).mp3",
+				"/music/README",
+				"/music/.hidden",
+			]),
+			[
+				"a.mp3",
+				"A (1).mp3",
+				"a (2).mp3",
+				"A (1) (1).mp3",
+				"README",
+				".hidden",
+			]
+		);
+	}
+
+	#[tokio::test]
+	async fn rating_survives_rebuild() {
+		let library = Library::new(stub::extract_title, |_| {});
+		library.write("song.txt", "song");
+		library.index();
+		let router = router(&library);
+
+		let response = router
+			.clone()
+			.oneshot(
+				http::Request::put(format!(
+					"/api/libraries/{}/{}/rating",
+					Library::NAME,
+					library.id("song")
+				))
+				.header(header::CONTENT_TYPE, "application/json")
+				.body(Body::from(r#"{"rating":4,"favorite":true}"#))
+				.unwrap(),
+			)
+			.await
+			.unwrap();
+		assert_eq!(response.status(), StatusCode::OK);
+
+		library
+			.plugins
+			.rebuild_media(
+				Library::NAME,
+				&library.db_pool,
+				library.config.media.get(Library::NAME),
+				None,
+			)
+			.unwrap();
+		let uri = format!("/api/libraries/{}", Library::NAME);
+		let response = request(&router, Method::GET, &uri, &[]).await;
+		assert_eq!(response.status(), StatusCode::OK);
+		let media = json(response).await;
+		assert_eq!(media[0]["title"], "song");
+		assert_eq!(
+			media[0]["user"],
+			serde_json::json!({ "rating": 4, "favorite": true })
+		);
+	}
+
+	#[tokio::test]
+	async fn listing_tag_changes_after_reindex() {
+		let library = Library::new(stub::extract_title, |_| {});
+		library.write("first.txt", "media");
+		library.index();
+		let router = router(&library);
+		let uri = format!("/api/libraries/{}", Library::NAME);
+
+		let response = request(&router, Method::GET, &uri, &[]).await;
+		assert_eq!(response.status(), StatusCode::OK);
+		let etag = response.headers()[header::ETAG]
+			.to_str()
+			.unwrap()
+			.to_owned();
+		let response = request(
+			&router,
+			Method::GET,
+			&uri,
+			&[(header::IF_NONE_MATCH, &etag)],
+		)
+		.await;
+		assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+		assert_eq!(response.headers()[header::ETAG], etag.as_str());
+
+		library.write("second.txt", "media");
+		library.index();
+		let response = request(
+			&router,
+			Method::GET,
+			&uri,
+			&[(header::IF_NONE_MATCH, &etag)],
+		)
+		.await;
+		assert_eq!(response.status(), StatusCode::OK);
+		assert_ne!(response.headers()[header::ETAG], etag.as_str());
+		assert_eq!(json(response).await.as_array().map(Vec::len), Some(2));
+	}
+
+	#[tokio::test]
+	async fn playlist_is_ordered_by_track() {
+		let library = Library::new(stub::extract_tags, |_| {});
+		library.write("a.txt", "title=Third\nalbum=Album\ntrack=3");
+		library.write("b.txt", "title=Second\nalbum=Album\ntrack=2");
+		library.write("c.txt", "title=First\nalbum=Album\ntrack=1");
+		library.write("d.txt", "title=Bonus\nalbum=Album");
+		library.write("e.txt", "title=Other\nalbum=Other album\ntrack=1");
+		library.index();
+		let router = router(&library);
+
+		let uri = format!(
+			"/api/libraries/{}/playlist?by=album&value=Album",
+			Library::NAME
+		);
+		let response = request(&router, Method::GET, &uri, &[]).await;
+		assert_eq!(response.status(), StatusCode::OK);
+		let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+		let expected = std::iter::once("#EXTM3U\n".to_owned())
+			.chain(["First", "Second", "Third", "Bonus"].map(|title| {
+				format!(
+					"#EXTINF:-1,{title}\n/api/libraries/{}/{}/file\n",
+					Library::NAME,
+					library.id(title)
+				)
+			}))
+			.collect::<String>();
+		assert_eq!(std::str::from_utf8(&body).unwrap(), expected);
+	}
+
+	#[tokio::test]
+	async fn stats_are_summed() {
+		let library = Library::new(stub::extract_tags, |_| {});
+		library.write("a.txt", "duration=1500");
+		library.write("b.txt", "duration=2250");
+		library.write("c.txt", "title=C");
+		library.index();
+		let router = router(&library);
 
-	Router::new().merge(libraries)
+		let uri = format!("/api/libraries/{}/stats", Library::NAME);
+		let response = request(&router, Method::GET, &uri, &[]).await;
+		assert_eq!(response.status(), StatusCode::OK);
+		assert_eq!(
+			json(response).await,
+			serde_json::json!({ "count": 3, "size": 13 + 13 + 7, "duration": 3.75 })
+		);
+	}
 }
@@ -0,0 +1,614 @@
+//! Provides the [OpenAPI](https://spec.openapis.org/oas/v3.1.0) document of the API
+//!
+//! The document is written by hand, so it must be updated along with the routes of [`api`](super).
+
+use serde_json::{json, Value};
+
+/// Returns a path parameter
+fn path_param(name: &str, description: &str, schema: Value) -> Value {
+	json!({
+		"name": name,
+		"in": "path",
+		"required": true,
+		"description": description,
+		"schema": schema,
+	})
+}
+
+/// Returns a query parameter
+fn query_param(name: &str, description: &str, required: bool, schema: Value) -> Value {
+	json!({
+		"name": name,
+		"in": "query",
+		"required": required,
+		"description": description,
+		"schema": schema,
+	})
+}
+
+/// Returns a response with the given content type and schema
+fn content(description: &str, content_type: &str, schema: Value) -> Value {
+	json!({
+		"description": description,
+		"content": { content_type: { "schema": schema } },
+	})
+}
+
+/// Returns a response without content
+fn status(description: &str) -> Value {
+	json!({ "description": description })
+}
+
+/// Builds the document for an app served under `base_path`
+pub(super) fn document(base_path: &str) -> Value {
+	let library = path_param("name", "Name of the library", json!({ "type": "string" }));
+	let media = path_param(
+		"id",
+		"Identifier of the media",
+		json!({ "type": "integer" }),
+	);
+	let plugin = path_param("name", "Name of the plugin", json!({ "type": "string" }));
+	let admin = json!([{ "admin": [] }]);
+	let binary = json!({ "type": "string", "format": "binary" });
+	let listing = |default_limit: Option<u32>| {
+		let mut limit = json!({ "type": "integer", "minimum": 0 });
+		if let Some(default_limit) = default_limit {
+			limit["default"] = default_limit.into();
+		}
+		vec![
+			library.clone(),
+			query_param(
+				"limit",
+				"Maximum number of media to return, capped to 1000",
+				false,
+				limit,
+			),
+			query_param(
+				"offset",
+				"Number of media to skip",
+				false,
+				json!({ "type": "integer", "minimum": 0, "default": 0 }),
+			),
+			query_param(
+				"sort",
+				"Identifier of the field to sort by; ties are sorted by path",
+				false,
+				json!({ "type": "string" }),
+			),
+			query_param(
+				"order",
+				"Direction of the sort",
+				false,
+				json!({ "enum": ["asc", "desc"], "default": "asc" }),
+			),
+			query_param(
+				"nulls",
+				"Placement of media without a value",
+				false,
+				json!({ "enum": ["first", "last"], "default": "last" }),
+			),
+			query_param(
+				"datetime",
+				"Output format of date/time values",
+				false,
+				json!({
+					"enum": ["iso8601", "unix", "rfc3339"],
+				}),
+			),
+			query_param(
+				"q",
+				"Text that the text fields of the media must contain, ignoring case; only for searchable libraries",
+				false,
+				json!({ "type": "string" }),
+			),
+			query_param(
+				"raw_metadata",
+				"Include the parsed raw metadata of the media, if the library stores it",
+				false,
+				json!({ "type": "boolean", "default": false }),
+			),
+		]
+	};
+
+	json!({
+		"openapi": "3.1.0",
+		"info": {
+			"title": "Aedron Patchouli",
+			"version": env!("CARGO_PKG_VERSION"),
+		},
+		"servers": [{ "url": format!("{base_path}api") }],
+		"components": {
+			"securitySchemes": {
+				"admin": { "type": "http", "scheme": "bearer" },
+			},
+			"schemas": {
+				"Library": {
+					"type": "object",
+					"properties": {
+						"name": { "type": "string" },
+						"display_name": { "type": "string" },
+						"icon": { "type": ["string", "null"], "description": "URL of the icon of the media type" },
+						"description": { "type": ["string", "null"], "description": "Description of the media type" },
+					},
+				},
+				"MediaUserData": {
+					"type": "object",
+					"properties": {
+						"rating": { "type": ["integer", "null"], "minimum": 1, "maximum": 5 },
+						"favorite": { "type": "boolean" },
+					},
+				},
+				"Media": {
+					"type": "object",
+					"description": "Fields of the media, as declared by the plugin; binary fields are URLs",
+					"properties": {
+						"id": { "type": "integer" },
+						"path": { "type": "string" },
+						"mtime": {},
+						"size": { "type": "integer", "description": "Size of the file, in bytes" },
+						"user": { "$ref": "#/components/schemas/MediaUserData" },
+						"raw_metadata": { "description": "Raw output of the metadata extractor, only if requested" },
+					},
+					"additionalProperties": true,
+				},
+				"MediaGroup": {
+					"type": "object",
+					"properties": {
+						"value": {},
+						"count": { "type": "integer" },
+						"cover": { "type": "string" },
+					},
+				},
+				"LibraryStats": {
+					"type": "object",
+					"properties": {
+						"count": { "type": "integer" },
+						"size": { "type": "integer", "description": "Total size of the files, in bytes" },
+						"duration": { "type": ["number", "null"], "description": "Total duration, in seconds, if the media have one" },
+					},
+				},
+				"Facet": {
+					"type": "object",
+					"properties": {
+						"value": {},
+						"count": { "type": "integer" },
+					},
+				},
+				"QuarantinedMedia": {
+					"type": "object",
+					"properties": {
+						"path": { "type": "string" },
+						"failures": { "type": "integer" },
+					},
+				},
+				"RemovedPlugin": {
+					"type": "object",
+					"properties": {
+						"name": { "type": "string" },
+						"kind": { "type": "string", "enum": ["media"] },
+						"version": { "type": "string" },
+						"table": { "type": ["string", "null"] },
+						"disabled": { "type": "boolean" },
+						"dropped": { "type": "boolean" },
+					},
+				},
+			},
+		},
+		"paths": {
+			"/libraries": {
+				"get": {
+					"summary": "Lists the libraries, by configured position then by display name",
+					"responses": {
+						"200": content("Libraries, with their display names", "application/json", json!({
+							"type": "array",
+							"items": { "$ref": "#/components/schemas/Library" },
+						})),
+						"503": status("No media plugin is loaded"),
+					},
+				},
+			},
+			"/libraries/{name}": {
+				"get": {
+					"summary": "Lists the media of a library",
+					"parameters": listing(None),
+					"responses": {
+						"200": content("Media of the library", "application/json", json!({
+							"type": "array",
+							"items": { "$ref": "#/components/schemas/Media" },
+						})),
+						"304": status("The listing did not change"),
+						"400": status("The query parameters are invalid, the field cannot be sorted by, or the library is not searchable"),
+						"404": status("The library does not exist"),
+					},
+				},
+				"patch": {
+					"summary": "Sets the display name of a library",
+					"parameters": [library],
+					"requestBody": {
+						"content": { "application/json": { "schema": {
+							"type": "object",
+							"properties": { "name": { "type": ["string", "null"] } },
+						} } },
+					},
+					"responses": {
+						"200": content("New display name", "application/json", json!({ "type": "string" })),
+						"404": status("The library does not exist"),
+					},
+				},
+			},
+			"/libraries/{name}/group": {
+				"get": {
+					"summary": "Groups the media of a library by the distinct values of a field",
+					"parameters": [
+						library,
+						query_param("by", "Identifier of the field to group by", true, json!({ "type": "string" })),
+						query_param("cover", "Identifier of a binary field to pick a representative from", false, json!({ "type": "string" })),
+					],
+					"responses": {
+						"200": content("Groups of media", "application/json", json!({
+							"type": "array",
+							"items": { "$ref": "#/components/schemas/MediaGroup" },
+						})),
+						"400": status("The field cannot be grouped by"),
+						"404": status("The library does not exist"),
+					},
+				},
+			},
+			"/libraries/{name}/cover": {
+				"get": {
+					"summary": "Returns the binary data of the first media of a group that has some",
+					"parameters": [
+						library,
+						query_param("by", "Identifier of the field the group is made by", true, json!({ "type": "string" })),
+						query_param("value", "Value shared by the media of the group", true, json!({ "type": "string" })),
+						query_param("cover", "Identifier of the binary field, defaulting to the first one", false, json!({ "type": "string" })),
+					],
+					"responses": {
+						"200": content("Binary data", "application/octet-stream", binary.clone()),
+						"304": status("The data did not change"),
+						"400": status("The field or the binary field does not exist"),
+						"404": status("The library does not exist, or no media of the group has data"),
+					},
+				},
+			},
+			"/libraries/{name}/stats": {
+				"get": {
+					"summary": "Returns the number, total size and total duration of the media of a library",
+					"parameters": [library],
+					"responses": {
+						"200": content("Statistics of the library", "application/json", json!({
+							"$ref": "#/components/schemas/LibraryStats",
+						})),
+						"404": status("The library does not exist"),
+					},
+				},
+			},
+			"/libraries/{name}/facets": {
+				"get": {
+					"summary": "Lists the distinct values of a field, from the most common",
+					"parameters": [
+						library,
+						query_param("field", "Identifier of the field", true, json!({ "type": "string" })),
+						query_param("limit", "Maximum number of values to return", false, json!({ "type": "integer", "minimum": 0, "maximum": 1000, "default": 100 })),
+						query_param("offset", "Number of values to skip", false, json!({ "type": "integer", "minimum": 0, "default": 0 })),
+					],
+					"responses": {
+						"200": content("Values of the field", "application/json", json!({
+							"type": "array",
+							"items": { "$ref": "#/components/schemas/Facet" },
+						})),
+						"400": status("The field has no facets"),
+						"404": status("The library does not exist"),
+					},
+				},
+			},
+			"/libraries/{name}/playlist": {
+				"get": {
+					"summary": "Returns a playlist of the files of the media of a group",
+					"parameters": [
+						library,
+						query_param("by", "Identifier of the field the media are grouped by", true, json!({ "type": "string" })),
+						query_param("value", "Value of the group", true, json!({ "type": "string" })),
+						query_param("format", "Format of the playlist", false, json!({ "enum": ["m3u", "json"] })),
+					],
+					"responses": {
+						"200": {
+							"description": "Playlist",
+							"content": {
+								"audio/x-mpegurl": { "schema": { "type": "string" } },
+								"application/json": { "schema": {
+									"type": "array",
+									"items": { "type": "string" },
+								} },
+							},
+						},
+						"400": status("The field cannot be grouped by"),
+						"404": status("The library does not exist"),
+					},
+				},
+			},
+			"/libraries/{name}/recent": {
+				"get": {
+					"summary": "Lists the media of a library from the most recently modified file, unless another sort is requested",
+					"parameters": listing(Some(50)),
+					"responses": {
+						"200": content("Media of the library", "application/json", json!({
+							"type": "array",
+							"items": { "$ref": "#/components/schemas/Media" },
+						})),
+						"400": status("The query parameters are invalid, the field cannot be sorted by, or the library is not searchable"),
+						"404": status("The library does not exist"),
+					},
+				},
+			},
+			"/libraries/{name}/quarantine": {
+				"get": {
+					"summary": "Lists the files that are not probed anymore until they are modified",
+					"parameters": [library],
+					"responses": {
+						"200": content("Quarantined files", "application/json", json!({
+							"type": "array",
+							"items": { "$ref": "#/components/schemas/QuarantinedMedia" },
+						})),
+						"404": status("The library does not exist"),
+					},
+				},
+			},
+			"/libraries/{name}/download": {
+				"post": {
+					"summary": "Streams a ZIP archive of the files of the given media",
+					"security": admin,
+					"parameters": [library],
+					"requestBody": {
+						"content": { "application/json": { "schema": {
+							"type": "object",
+							"properties": {
+								"ids": { "type": "array", "items": { "type": "integer" } },
+							},
+							"required": ["ids"],
+						} } },
+					},
+					"responses": {
+						"200": content("ZIP archive of the files, named after their base names", "application/zip", json!({
+							"type": "string",
+							"format": "binary",
+						})),
+						"400": status("No media is requested"),
+						"401": status("The administration token is missing or invalid"),
+						"404": status("The library or a media does not exist, or administration is disabled"),
+						"413": status("Too many files, or too large files, are requested"),
+					},
+				},
+			},
+			"/libraries/{name}/reindex": {
+				"post": {
+					"summary": "Extracts the metadata of the media files whose path matches a glob again",
+					"security": admin,
+					"parameters": [
+						library,
+						query_param("match", "Glob that the paths of the media files must match", true, json!({ "type": "string" })),
+					],
+					"responses": {
+						"200": content("Progress events, as newline-delimited JSON", "application/x-ndjson", json!({ "type": "string" })),
+						"400": status("The glob is missing or invalid"),
+						"401": status("The administration token is missing or invalid"),
+						"404": status("The library does not exist or administration is disabled"),
+						"409": status("Media are already being indexed"),
+					},
+				},
+			},
+			"/libraries/{name}/{id}/rating": {
+				"put": {
+					"summary": "Sets the user data of a media",
+					"parameters": [library, media],
+					"requestBody": {
+						"content": { "application/json": { "schema": {
+							"$ref": "#/components/schemas/MediaUserData",
+						} } },
+					},
+					"responses": {
+						"200": content("New user data", "application/json", json!({
+							"$ref": "#/components/schemas/MediaUserData",
+						})),
+						"400": status("The identifier of the media is not an integer"),
+						"404": status("The library or the media does not exist"),
+					},
+				},
+			},
+			"/libraries/{name}/{id}/waveform": {
+				"get": {
+					"summary": "Returns the waveform peaks of a media",
+					"parameters": [library, media],
+					"responses": {
+						"200": content("Peaks, scaled to 0..=255", "application/octet-stream", binary.clone()),
+						"400": status("The identifier of the media is not an integer"),
+						"404": status("The library, the media or its waveform does not exist"),
+					},
+				},
+			},
+			"/libraries/{name}/{id}/raw_metadata": {
+				"get": {
+					"summary": "Returns the full output of the metadata extractor for a media",
+					"parameters": [library, media],
+					"responses": {
+						"200": content("Raw metadata", "application/json", json!({})),
+						"400": status("The identifier of the media is not an integer"),
+						"404": status("The library, the media or its raw metadata does not exist"),
+					},
+				},
+			},
+			"/libraries/{name}/{id}/file": {
+				"get": {
+					"summary": "Returns the file of a media",
+					"parameters": [library, media],
+					"responses": {
+						"200": content("Content of the file", "application/octet-stream", binary.clone()),
+						"400": status("The identifier of the media is not an integer"),
+						"404": status("The library, the media or its file does not exist"),
+					},
+				},
+			},
+			"/libraries/{name}/{id}/{field}": {
+				"get": {
+					"summary": "Returns the binary data stored in a field of a media",
+					"parameters": [
+						library,
+						media,
+						path_param("field", "Identifier of a binary field", json!({ "type": "string" })),
+					],
+					"responses": {
+						"200": content("Binary data", "application/octet-stream", binary),
+						"400": status("The identifier of the media is not an integer"),
+						"404": status("The library, the media or the field does not exist"),
+					},
+				},
+			},
+			"/plugins/{name}/schema.sql": {
+				"get": {
+					"summary": "Returns the SQL statements that create the database table of a media plugin",
+					"security": admin,
+					"parameters": [plugin],
+					"responses": {
+						"200": content("SQL statements", "application/sql", json!({ "type": "string" })),
+						"401": status("The administration token is missing or invalid"),
+						"404": status("The plugin does not exist or administration is disabled"),
+					},
+				},
+			},
+			"/plugins/removed": {
+				"get": {
+					"summary": "Lists the plugins found in the database at startup but not loaded",
+					"security": admin,
+					"responses": {
+						"200": content("Removed plugins", "application/json", json!({
+							"type": "array",
+							"items": { "$ref": "#/components/schemas/RemovedPlugin" },
+						})),
+						"401": status("The administration token is missing or invalid"),
+						"404": status("Administration is disabled"),
+					},
+				},
+			},
+			"/plugins/{name}/rebuild": {
+				"post": {
+					"summary": "Drops and recreates the database table of a media plugin, then loads its media again",
+					"security": admin,
+					"parameters": [plugin],
+					"responses": {
+						"200": content("Progress events, as newline-delimited JSON", "application/x-ndjson", json!({ "type": "string" })),
+						"401": status("The administration token is missing or invalid"),
+						"404": status("The plugin does not exist or administration is disabled"),
+						"409": status("Media are already being indexed"),
+					},
+				},
+			},
+			"/admin/logs": {
+				"get": {
+					"summary": "Streams the log lines as server-sent events",
+					"security": admin,
+					"parameters": [
+						query_param("level", "Most verbose level of the streamed logs", false, json!({
+							"enum": ["off", "error", "warn", "info", "debug", "trace"],
+							"default": "info",
+						})),
+					],
+					"responses": {
+						"200": content("`log` events carrying a log line, and `lagged` events carrying the number of dropped lines", "text/event-stream", json!({ "type": "string" })),
+						"400": status("The log level is invalid"),
+						"401": status("The administration token is missing or invalid"),
+						"404": status("Administration is disabled"),
+						"503": status("Too many clients are already streaming the logs"),
+					},
+				},
+			},
+			"/openapi.json": {
+				"get": {
+					"summary": "Returns this document",
+					"responses": {
+						"200": content("OpenAPI document", "application/json", json!({ "type": "object" })),
+					},
+				},
+			},
+		},
+	})
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{
+		http::tests::request,
+		plugins::stub::{self, Library},
+		AppState,
+	};
+	use axum::http::{Method, StatusCode};
+	use std::{collections::BTreeSet, sync::Arc};
+
+	/// Replaces the parameters of a path, in the syntax of either the document or the router, by `{}`
+	fn normalize(path: &str) -> String {
+		path.split('/')
+			.map(|segment| {
+				if segment.starts_with(':') || segment.starts_with('{') {
+					"{}"
+				} else {
+					segment
+				}
+			})
+			.collect::<Vec<_>>()
+			.join("/")
+	}
+
+	#[test]
+	fn documented_paths_are_routed() {
+		// NOTE: The router does not list its routes, but its debug output contains their paths
+		let router = format!("{:?}", super::super::new_router());
+		let routed = router
+			.split('"')
+			.filter(|s| s.len() > 1 && s.starts_with('/') && !s.contains("__private__"))
+			.map(normalize)
+			.collect::<BTreeSet<_>>();
+		let documented = document("/")["paths"]
+			.as_object()
+			.unwrap()
+			.keys()
+			.map(|path| normalize(path))
+			.collect::<BTreeSet<_>>();
+		assert_eq!(documented, routed);
+	}
+
+	#[tokio::test]
+	async fn documented_methods_are_routed() {
+		let library = Library::new(stub::extract_title, |_| {});
+		library.write("song.mp3", "song");
+		library.index();
+		let router = super::super::new_router()
+			.fallback(|| async { StatusCode::IM_A_TEAPOT })
+			.with_state(AppState::stub(
+				library.config.clone(),
+				library.db_pool.clone(),
+				Arc::clone(&library.plugins),
+			));
+
+		let document = document("/");
+		for (path, item) in document["paths"].as_object().unwrap() {
+			let uri = path
+				.replace("{name}", Library::NAME)
+				.replace("{id}", &library.id("song").to_string())
+				.replace("{field}", "title");
+			for method in item.as_object().unwrap().keys() {
+				let method = Method::from_bytes(method.to_uppercase().as_bytes()).unwrap();
+				let status = request(&router, method.clone(), &uri, &[]).await.status();
+				assert_ne!(
+					status,
+					StatusCode::IM_A_TEAPOT,
+					"{method} {path} is not routed"
+				);
+				assert_ne!(
+					status,
+					StatusCode::METHOD_NOT_ALLOWED,
+					"{method} {path} is not routed"
+				);
+			}
+		}
+	}
+}
@@ -0,0 +1,320 @@
+//! Provides a streaming writer of ZIP archives
+//!
+//! Entries are stored without compression, since media files are already compressed.
+//! Their CRC-32 and sizes are written in data descriptors after their data,
+//! so that the archive can be streamed without reading the files twice.
+//!
+//! ZIP64 is not supported, so archives are limited to 4 GiB and 65535 entries.
+
+use crc32fast::Hasher;
+use std::io;
+use time::OffsetDateTime;
+
+/// Signature of a local file header
+const LOCAL_FILE_HEADER: u32 = 0x0403_4b50;
+/// Signature of a data descriptor
+const DATA_DESCRIPTOR: u32 = 0x0807_4b50;
+/// Signature of a central directory file header
+const CENTRAL_DIRECTORY_HEADER: u32 = 0x0201_4b50;
+/// Signature of the end of central directory record
+const END_OF_CENTRAL_DIRECTORY: u32 = 0x0605_4b50;
+/// Version of the format needed to extract the entries (2.0)
+const VERSION: u16 = 20;
+/// General purpose flags of the entries
+///
+/// Bit 3 tells that a data descriptor follows the data, and bit 11 that the name is encoded in UTF-8.
+const FLAGS: u16 = 1 << 3 | 1 << 11;
+
+/// Entry of a [`ZipWriter`] whose data is being written
+#[derive(Debug)]
+struct Entry {
+	/// Name of the entry
+	name: String,
+	/// Offset of the local file header of the entry
+	offset: u32,
+	/// Modification time, in MS-DOS format
+	time: u16,
+	/// Modification date, in MS-DOS format
+	date: u16,
+	/// CRC-32 of the data written so far
+	crc: Hasher,
+	/// Size of the data written so far
+	size: u32,
+}
+
+/// Streaming writer of a ZIP archive
+///
+/// It does not write anything itself, but returns the bytes of the archive's structures,
+/// so that the caller sends them along with the data of the entries.
+#[derive(Debug, Default)]
+pub(super) struct ZipWriter {
+	/// Number of bytes of the archive so far
+	offset: u32,
+	/// Entry whose data is being written
+	current: Option<Entry>,
+	/// Central directory file headers of the finished entries
+	central_directory: Vec<u8>,
+	/// Number of finished entries
+	count: u16,
+}
+impl ZipWriter {
+	/// Starts a new entry and returns its local file header
+	///
+	/// # Panics
+	/// This function panics if the previous entry was not finished.
+	pub(super) fn start_entry(&mut self, name: &str, mtime: OffsetDateTime) -> io::Result<Vec<u8>> {
+		assert!(self.current.is_none(), "the previous entry is not finished");
+
+		let name_len = u16::try_from(name.len()).map_err(|_err| {
+			io::Error::new(
+				io::ErrorKind::InvalidInput,
+				"the name of the entry is too long",
+			)
+		})?;
+		let (time, date) = dos_datetime(mtime);
+		let mut header = Vec::with_capacity(30 + name.len());
+		header.extend(LOCAL_FILE_HEADER.to_le_bytes());
+		header.extend(VERSION.to_le_bytes());
+		header.extend(FLAGS.to_le_bytes());
+		header.extend(0_u16.to_le_bytes()); // NOTE: Stored
+		header.extend(time.to_le_bytes());
+		header.extend(date.to_le_bytes());
+		// NOTE: The CRC-32 and sizes are in the data descriptor
+		header.extend([0; 12]);
+		header.extend(name_len.to_le_bytes());
+		header.extend(0_u16.to_le_bytes());
+		header.extend(name.as_bytes());
+
+		self.current = Some(Entry {
+			name: name.to_owned(),
+			offset: self.offset,
+			time,
+			date,
+			crc: Hasher::new(),
+			size: 0,
+		});
+		self.advance(header.len())?;
+		Ok(header)
+	}
+
+	/// Accounts for data of the current entry
+	///
+	/// # Panics
+	/// This function panics if no entry was started.
+	pub(super) fn write(&mut self, data: &[u8]) -> io::Result<()> {
+		let entry = self.current.as_mut().expect("no entry is started");
+		entry.crc.update(data);
+		entry.size = u32::try_from(data.len())
+			.ok()
+			.and_then(|len| entry.size.checked_add(len))
+			.ok_or_else(too_large)?;
+		self.advance(data.len())
+	}
+
+	/// Finishes the current entry and returns its data descriptor
+	///
+	/// # Panics
+	/// This function panics if no entry was started.
+	pub(super) fn finish_entry(&mut self) -> io::Result<Vec<u8>> {
+		let entry = self.current.take().expect("no entry is started");
+		let crc = entry.crc.finalize();
+
+		let mut descriptor = Vec::with_capacity(16);
+		descriptor.extend(DATA_DESCRIPTOR.to_le_bytes());
+		descriptor.extend(crc.to_le_bytes());
+		descriptor.extend(entry.size.to_le_bytes());
+		descriptor.extend(entry.size.to_le_bytes());
+
+		self.count = self.count.checked_add(1).ok_or_else(too_large)?;
+		let header = &mut self.central_directory;
+		header.extend(CENTRAL_DIRECTORY_HEADER.to_le_bytes());
+		header.extend(VERSION.to_le_bytes());
+		header.extend(VERSION.to_le_bytes());
+		header.extend(FLAGS.to_le_bytes());
+		header.extend(0_u16.to_le_bytes());
+		header.extend(entry.time.to_le_bytes());
+		header.extend(entry.date.to_le_bytes());
+		header.extend(crc.to_le_bytes());
+		header.extend(entry.size.to_le_bytes());
+		header.extend(entry.size.to_le_bytes());
+		// NOTE: The length was checked when the entry was started
+		#[allow(clippy::cast_possible_truncation)]
+		header.extend((entry.name.len() as u16).to_le_bytes());
+		// NOTE: Lengths of the extra field and comment, disk number, internal and external attributes
+		header.extend([0; 12]);
+		header.extend(entry.offset.to_le_bytes());
+		header.extend(entry.name.as_bytes());
+
+		self.advance(descriptor.len())?;
+		Ok(descriptor)
+	}
+
+	/// Finishes the archive and returns its central directory
+	///
+	/// # Panics
+	/// This function panics if the last entry was not finished.
+	pub(super) fn finish(mut self) -> io::Result<Vec<u8>> {
+		assert!(self.current.is_none(), "the last entry is not finished");
+
+		let offset = self.offset;
+		let size = self.central_directory.len();
+		self.advance(size)?;
+		#[allow(clippy::cast_possible_truncation)]
+		let size = size as u32;
+
+		let mut trailer = self.central_directory;
+		trailer.extend(END_OF_CENTRAL_DIRECTORY.to_le_bytes());
+		// NOTE: Number of this disk, and of the disk with the central directory
+		trailer.extend([0; 4]);
+		trailer.extend(self.count.to_le_bytes());
+		trailer.extend(self.count.to_le_bytes());
+		trailer.extend(size.to_le_bytes());
+		trailer.extend(offset.to_le_bytes());
+		trailer.extend(0_u16.to_le_bytes());
+		Ok(trailer)
+	}
+
+	/// Moves the offset of the archive forward
+	fn advance(&mut self, len: usize) -> io::Result<()> {
+		self.offset = u32::try_from(len)
+			.ok()
+			.and_then(|len| self.offset.checked_add(len))
+			.ok_or_else(too_large)?;
+		Ok(())
+	}
+}
+
+/// Returns the error of an archive exceeding the limits of the format without ZIP64
+#[inline]
+fn too_large() -> io::Error {
+	io::Error::new(
+		io::ErrorKind::InvalidInput,
+		"the archive is too large to be written without ZIP64",
+	)
+}
+
+/// Converts a date/time to the MS-DOS format, as `(time, date)`
+///
+/// Dates that cannot be represented are replaced by 1980-01-01.
+fn dos_datetime(datetime: OffsetDateTime) -> (u16, u16) {
+	let year = datetime.year();
+	if !(1980..=2107).contains(&year) {
+		return (0, 1 << 5 | 1);
+	}
+	#[allow(clippy::cast_sign_loss, clippy::cast_possible_truncation)]
+	let year = (year - 1980) as u16;
+	let time = (u16::from(datetime.hour()) << 11)
+		| (u16::from(datetime.minute()) << 5)
+		| (u16::from(datetime.second()) / 2);
+	let date =
+		(year << 9) | (u16::from(u8::from(datetime.month())) << 5) | u16::from(datetime.day());
+	(time, date)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/// Entry read back from an archive
+	#[derive(Debug, PartialEq, Eq)]
+	struct ReadEntry {
+		/// Name of the entry
+		name: String,
+		/// Data of the entry
+		data: Vec<u8>,
+	}
+
+	/// Reads a little-endian `u16` at the given offset
+	fn u16_at(archive: &[u8], offset: usize) -> u16 {
+		u16::from_le_bytes(archive[offset..offset + 2].try_into().unwrap())
+	}
+
+	/// Reads a little-endian `u32` at the given offset
+	fn u32_at(archive: &[u8], offset: usize) -> u32 {
+		u32::from_le_bytes(archive[offset..offset + 4].try_into().unwrap())
+	}
+
+	/// Reads the entries of an archive from its central directory,
+	/// checking them against their local file headers and data descriptors
+	fn read(archive: &[u8]) -> Vec<ReadEntry> {
+		let end = archive.len() - 22;
+		assert_eq!(u32_at(archive, end), END_OF_CENTRAL_DIRECTORY);
+		let count = u16_at(archive, end + 10);
+		assert_eq!(u16_at(archive, end + 8), count);
+		let size = u32_at(archive, end + 12) as usize;
+		let mut offset = u32_at(archive, end + 16) as usize;
+		assert_eq!(offset + size, end, "the central directory is misplaced");
+
+		(0..count)
+			.map(|_| {
+				assert_eq!(u32_at(archive, offset), CENTRAL_DIRECTORY_HEADER);
+				assert_eq!(u16_at(archive, offset + 8), FLAGS);
+				assert_eq!(u16_at(archive, offset + 10), 0, "the entry is compressed");
+				let crc = u32_at(archive, offset + 16);
+				let compressed_size = u32_at(archive, offset + 20) as usize;
+				let size = u32_at(archive, offset + 24) as usize;
+				assert_eq!(compressed_size, size);
+				let name_len = usize::from(u16_at(archive, offset + 28));
+				let local = u32_at(archive, offset + 42) as usize;
+				let name = &archive[offset + 46..offset + 46 + name_len];
+				offset += 46 + name_len;
+
+				assert_eq!(u32_at(archive, local), LOCAL_FILE_HEADER);
+				assert_eq!(u16_at(archive, local + 6), FLAGS);
+				assert_eq!(usize::from(u16_at(archive, local + 26)), name_len);
+				assert_eq!(&archive[local + 30..local + 30 + name_len], name);
+				let data_start = local + 30 + name_len + usize::from(u16_at(archive, local + 28));
+				let data = &archive[data_start..data_start + size];
+				let descriptor = data_start + size;
+				assert_eq!(u32_at(archive, descriptor), DATA_DESCRIPTOR);
+				assert_eq!(u32_at(archive, descriptor + 4), crc);
+				assert_eq!(u32_at(archive, descriptor + 8) as usize, size);
+				assert_eq!(u32_at(archive, descriptor + 12) as usize, size);
+				assert_eq!(crc32fast::hash(data), crc, "the CRC-32 does not match");
+
+				ReadEntry {
+					name: String::from_utf8(name.to_vec()).unwrap(),
+					data: data.to_vec(),
+				}
+			})
+			.collect()
+	}
+
+	#[test]
+	fn entries_are_read_back() {
+		let mtime = OffsetDateTime::now_utc();
+		let mut writer = ZipWriter::default();
+		let mut archive = Vec::new();
+		for (name, chunks) in [
+			("first.txt", &[&b"Hello, "[..], b"world!"][..]),
+			("second ♪.mp3", &[&[0xff, 0xfb, 0x90, 0x00][..]]),
+		] {
+			archive.extend(writer.start_entry(name, mtime).unwrap());
+			for chunk in chunks {
+				writer.write(chunk).unwrap();
+				archive.extend(*chunk);
+			}
+			archive.extend(writer.finish_entry().unwrap());
+		}
+		archive.extend(writer.finish().unwrap());
+
+		assert_eq!(
+			read(&archive),
+			[
+				ReadEntry {
+					name: "first.txt".to_owned(),
+					data: b"Hello, world!".to_vec(),
+				},
+				ReadEntry {
+					name: "second ♪.mp3".to_owned(),
+					data: vec![0xff, 0xfb, 0x90, 0x00],
+				},
+			]
+		);
+	}
+
+	#[test]
+	fn empty_archive() {
+		assert_eq!(read(&ZipWriter::default().finish().unwrap()), []);
+	}
+}
@@ -2,8 +2,9 @@
 
 use crate::AppState;
 use axum::{
+	body::{self, Empty},
 	extract::Path,
-	http::StatusCode,
+	http::{Method, StatusCode},
 	response::{IntoResponse, Response},
 	routing, Router,
 };
@@ -11,21 +12,18 @@ use axum_extra::{
 	body::AsyncReadBody,
 	response::{Css, JavaScript, Wasm},
 };
-use tokio::fs::File;
+use tokio::fs::{self, File};
 
 /// `GET /*`
 /// [Handler](axum::handler) that returns the requested file from `client/assets/`
+///
+/// `HEAD` requests get the same headers without opening the file.
 #[axum::debug_handler(state = AppState)]
-async fn get_asset(Path(path): Path<String>) -> Result<Response, (StatusCode, String)> {
-	let assets_dir = std::path::Path::new("client/assets");
-	let file = match File::open(assets_dir.join(&path)).await {
-		Ok(file) => Ok(file),
-		Err(ref err) if err.kind() == std::io::ErrorKind::NotFound => {
-			File::open(assets_dir.join("out").join(&path)).await
-		}
-		Err(err) => Err(err),
-	}
-	.map_err(|err| {
+async fn get_asset(
+	method: Method,
+	Path(path): Path<String>,
+) -> Result<Response, (StatusCode, String)> {
+	let map_err = |err: std::io::Error| {
 		(
 			match err.kind() {
 				std::io::ErrorKind::NotFound => StatusCode::NOT_FOUND,
@@ -33,14 +31,32 @@ async fn get_asset(Path(path): Path<String>) -> Result<Response, (StatusCode, St
 			},
 			err.to_string(),
 		)
-	})?;
+	};
+
+	let assets_dir = std::path::Path::new("client/assets");
+	let mut file_path = assets_dir.join(&path);
+	let metadata = match fs::metadata(&file_path).await {
+		Err(ref err) if err.kind() == std::io::ErrorKind::NotFound => {
+			file_path = assets_dir.join("out").join(&path);
+			fs::metadata(&file_path).await
+		}
+		res => res,
+	}
+	.map_err(map_err)?;
+	let headers = super::file_headers(metadata.len(), metadata.modified().map_err(map_err)?);
 
-	let body = AsyncReadBody::new(file);
+	let body = if method == Method::HEAD {
+		body::boxed(Empty::new())
+	} else {
+		body::boxed(AsyncReadBody::new(
+			File::open(&file_path).await.map_err(map_err)?,
+		))
+	};
 	Ok(match path.rsplit_once('.') {
-		Some((_, "js")) => JavaScript(body).into_response(),
-		Some((_, "wasm")) => Wasm(body).into_response(),
-		Some((_, "css")) => Css(body).into_response(),
-		_ => body.into_response(),
+		Some((_, "js")) => (headers, JavaScript(body)).into_response(),
+		Some((_, "wasm")) => (headers, Wasm(body)).into_response(),
+		Some((_, "css")) => (headers, Css(body)).into_response(),
+		_ => (headers, body).into_response(),
 	})
 }
 
@@ -70,13 +70,19 @@
 )]
 #![forbid(clippy::undocumented_unsafe_blocks)]
 
+mod check;
 mod config;
 mod db;
 mod http;
 mod plugins;
+mod source;
 mod tls;
+mod waveform;
 
-use crate::tls::{ConnectedTlsAcceptor, Identity};
+use crate::{
+	check::Severity,
+	tls::{ConnectedTlsAcceptor, Identity},
+};
 use axum::{extract::FromRef, Server};
 use client::{
 	leptos::LeptosOptions,
@@ -84,19 +90,24 @@ use client::{
 	RequestClient,
 };
 use colored::Colorize;
-use config::Config;
+use config::{Config, ListenerConfig};
+use http::PublicUrl;
 use hyper::server::conn::AddrIncoming;
-use plugins::PluginStore;
+use plugins::{IndexingEvent, PluginStore};
 use r2d2::Pool;
 use r2d2_sqlite::SqliteConnectionManager;
 use std::{
 	backtrace::{Backtrace, BacktraceStatus},
+	collections::BTreeMap,
+	env,
 	error::Error,
 	fmt::Display,
 	io,
 	net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
-	sync::Arc,
+	sync::{mpsc, Arc},
+	time::Duration,
 };
+use tokio::{net::TcpSocket, sync::broadcast};
 
 /// Name of the server executable
 const EXE_NAME: &str = env!("CARGO_BIN_NAME");
@@ -111,12 +122,13 @@ const LOG_HIGHLIGHT: &str = "_HIGHLIGHT";
 /// 2023-08-19T14:01:10Z INFO [aedron-patchouli] Hello, world!
 /// ```
 ///
-/// On `unix` targets, the logs will be output to the syslog as well.
+/// On `unix` targets, the logs will be output to the syslog as well, if it is available.
+/// They are also sent to `logs`, if it has subscribers.
 ///
 /// Also, the [panic hook](std::panic::set_hook) is set to output panic info through the logger.
-fn setup_logger() -> Result<(), fern::InitError> {
+fn setup_logger(logs: broadcast::Sender<LogLine>) -> Result<(), fern::InitError> {
 	use colored::Color;
-	use fern::{colors::ColoredLevelConfig, Dispatch, InitError};
+	use fern::{colors::ColoredLevelConfig, Dispatch};
 	use log::LevelFilter;
 	use time::{
 		format_description::well_known::{
@@ -188,24 +200,52 @@ fn setup_logger() -> Result<(), fern::InitError> {
 					));
 				})
 				.chain(std::io::stdout()),
+		)
+		.chain(
+			Dispatch::new()
+				.format(move |out, message, record| {
+					let Ok(timestamp) = OffsetDateTime::now_utc().format(&Iso8601::<TIME_FORMAT>)
+					else {
+						unreachable!()
+					};
+					out.finish(format_args!(
+						"{timestamp} {:5} [{}] {message}",
+						record.level(),
+						record.module_path().unwrap_or_default()
+					));
+				})
+				.chain(fern::Output::call(move |record| {
+					if logs.receiver_count() > 0 {
+						// NOTE: Sending only fails if all subscribers hung up in the meantime
+						let _res = logs.send(LogLine {
+							level: record.level(),
+							line: record.args().to_string().into(),
+						});
+					}
+				})),
 		);
 	#[cfg(unix)]
-	{
-		// If `unix`, output to syslog as well
+	// If `unix`, output to syslog as well, if it is available (e.g. not in most containers)
+	let syslog_err = {
 		let syslog_formatter = syslog::Formatter3164 {
 			facility: syslog::Facility::LOG_USER,
 			hostname: None,
 			process: EXE_NAME.to_owned(),
 			pid: 0,
 		};
-		logger = logger.chain(
-			Dispatch::new().chain(syslog::unix(syslog_formatter).map_err(|err| match err.0 {
-				syslog::ErrorKind::Io(err) => InitError::Io(err),
-				_ => unreachable!(),
-			})?),
-		);
-	}
+		match syslog::unix(syslog_formatter) {
+			Ok(syslog_logger) => {
+				logger = logger.chain(Dispatch::new().chain(syslog_logger));
+				None
+			}
+			Err(err) => Some(err),
+		}
+	};
 	logger.apply()?;
+	#[cfg(unix)]
+	if let Some(err) = syslog_err {
+		log::warn!("Logs are not sent to syslog: {err}");
+	}
 
 	// Make panics use the installed logger
 	std::panic::set_hook(Box::new(move |panic_info| {
@@ -241,6 +281,125 @@ fn setup_logger() -> Result<(), fern::InitError> {
 	Ok(())
 }
 
+/// Capacity of the channel broadcasting [`LogLine`]s
+///
+/// Subscribers that fall further behind miss the oldest lines.
+const LOG_CAPACITY: usize = 1024;
+
+/// Formatted log line, broadcast to the subscribers of the log stream
+#[derive(Debug, Clone)]
+struct LogLine {
+	/// Level of the log
+	level: log::Level,
+	/// Formatted line
+	line: Arc<str>,
+}
+
+/// Mode of operation of the executable, selected by command-line arguments
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+enum Mode {
+	/// Run the server
+	#[default]
+	Serve,
+	/// Index the media of the configured libraries once, then exit
+	IndexOnly,
+	/// Check the configuration and report its problems, then exit
+	CheckConfig,
+}
+impl Mode {
+	/// Parses the given command-line arguments (without the executable name)
+	fn from_args(args: impl IntoIterator<Item = String>) -> Result<Self, String> {
+		let mut mode = Self::default();
+		for arg in args {
+			mode = match arg.as_str() {
+				"--index-only" => Self::IndexOnly,
+				"--check-config" => Self::CheckConfig,
+				_ => {
+					return Err(format!(
+						"Unknown argument {arg:?}\nUsage: {EXE_NAME} [--index-only | --check-config]"
+					));
+				}
+			};
+		}
+		Ok(mode)
+	}
+}
+
+/// Indexes the media of the configured libraries and logs a summary
+///
+/// This is what the executable does in [`Mode::IndexOnly`].
+///
+/// # Errors
+/// This function returns an error if the media of some libraries could not be indexed,
+/// so that the executable exits with a failure status.
+fn index_only(config: &Config) -> Result<(), Box<dyn Error>> {
+	if config.database.read_only {
+		return Err("Media cannot be indexed into a read-only database".into());
+	}
+
+	let db_pool = db::init(&config.database)?;
+	let mut plugins = PluginStore::load_plugins(config)?;
+	plugins.update_database(&db_pool)?;
+
+	let (tx, rx) = mpsc::channel();
+	let res = plugins.load_media(&db_pool, &config.media, Some(&tx));
+	drop(tx);
+
+	// NOTE: Counts of seen, probed, inserted and removed media of each library
+	let mut summary = BTreeMap::<Box<str>, [usize; 4]>::new();
+	for event in rx {
+		let (library, index, count) = match event {
+			IndexingEvent::Seen { library, .. } => (library, 0, 1),
+			IndexingEvent::Probed { library, .. } => (library, 1, 1),
+			IndexingEvent::Inserted { library, .. } => (library, 2, 1),
+			IndexingEvent::Removed { library, count } => (library, 3, count),
+		};
+		summary.entry(library).or_default()[index] += count;
+	}
+	for (library, [seen, probed, inserted, removed]) in summary {
+		log::info!(
+			target: LOG_HIGHLIGHT,
+			"Library {library}: {seen} seen, {probed} probed, {inserted} inserted, {removed} removed"
+		);
+	}
+	res
+}
+
+/// Logs the problems of the configuration
+///
+/// This is what the executable does in [`Mode::CheckConfig`].
+///
+/// # Errors
+/// This function returns an error if some problems are fatal.
+fn check_config(config: &Config) -> Result<(), Box<dyn Error>> {
+	let problems = check::check_config(config);
+	let mut fatal = 0_usize;
+	for problem in &problems {
+		match problem.severity {
+			Severity::Warning => log::warn!("{problem}"),
+			Severity::Fatal => {
+				fatal += 1;
+				log::error!("{problem}");
+			}
+		}
+	}
+
+	if fatal > 0 {
+		Err(format!(
+			"The configuration has {fatal} fatal problem(s) and {} warning(s)",
+			problems.len() - fatal
+		)
+		.into())
+	} else {
+		log::info!(
+			target: LOG_HIGHLIGHT,
+			"The configuration is valid, with {} warning(s)",
+			problems.len()
+		);
+		Ok(())
+	}
+}
+
 /// Stores the server's state
 #[derive(Debug, Clone, FromRef)]
 struct AppState {
@@ -254,6 +413,40 @@ struct AppState {
 	plugins: Arc<PluginStore>,
 	/// HTTP client to load [`leptos::Resource`]
 	request_client: RequestClient,
+	/// Public URL of the app
+	public_url: PublicUrl,
+	/// Channel broadcasting the log lines
+	logs: broadcast::Sender<LogLine>,
+}
+
+#[cfg(test)]
+impl AppState {
+	/// Constructs a state around the given database and plugins, for tests
+	pub(crate) fn stub(
+		config: Config,
+		db_pool: Pool<SqliteConnectionManager>,
+		plugins: Arc<PluginStore>,
+	) -> Self {
+		let base_path = config.base_path.clone();
+		Self {
+			config,
+			leptos_options: LeptosOptions::builder()
+				.output_name(env!("ASSET_PREFIX").to_owned())
+				.build(),
+			db_pool,
+			plugins,
+			request_client: RequestClient::build(
+				ClientBuilder::new(),
+				Url::parse("https://localhost/").unwrap(),
+			)
+			.unwrap(),
+			public_url: PublicUrl {
+				url: None,
+				base_path,
+			},
+			logs: broadcast::channel(LOG_CAPACITY).0,
+		}
+	}
 }
 
 #[tokio::main]
@@ -261,10 +454,28 @@ async fn main() {
 	/// Inner [`main`] function used to [`Display`] the returned error
 	#[inline]
 	async fn _main() -> Result<(), Box<dyn Error>> {
-		setup_logger()?;
+		let (logs, _) = broadcast::channel(LOG_CAPACITY);
+		setup_logger(logs.clone())?;
 
+		#[cfg(feature = "dotenv")]
+		match dotenvy::dotenv() {
+			Ok(path) => {
+				log::info!("Loaded environment variables from {}", path.display());
+			}
+			Err(err) if err.not_found() => {}
+			Err(err) => {
+				log::warn!("Could not load the .env file: {err}");
+			}
+		}
+
+		let mode = Mode::from_args(env::args().skip(1))?;
 		let config = config::build_config()?;
 		log::trace!("{config:?}");
+		match mode {
+			Mode::Serve => {}
+			Mode::IndexOnly => return index_only(&config),
+			Mode::CheckConfig => return check_config(&config),
+		}
 		let addr = SocketAddr::new(config.addr, config.port);
 		let mut site_addr = addr;
 		if site_addr.ip().is_unspecified() {
@@ -277,6 +488,14 @@ async fn main() {
 
 		let identity = match Identity::read(&config.tls.key, &config.tls.certificate) {
 			Ok(identity) => {
+				if !identity.key_matches()? {
+					return Err(format!(
+						"The provided TLS key {} does not match the certificate {}; if the server generated them, delete both files so that a new identity is generated",
+						config.tls.key.display(),
+						config.tls.certificate.display()
+					)
+					.into());
+				}
 				log::info!("Cryptographic identity loaded from files");
 				identity
 			}
@@ -297,7 +516,10 @@ async fn main() {
 
 		let leptos_options = LeptosOptions::builder()
 			.output_name(env!("ASSET_PREFIX").to_owned())
-			.site_pkg_dir("assets")
+			.site_pkg_dir(format!(
+				"{}assets",
+				config.base_path.trim_start_matches('/')
+			))
 			.site_addr(site_addr)
 			.build();
 
@@ -306,29 +528,59 @@ async fn main() {
 			builder = builder.add_root_certificate(Certificate::from_der(&cert.0).unwrap());
 		}
 		builder = builder.https_only(true);
-		let Ok(base_url) = Url::parse(&format!("https://{}", leptos_options.site_addr)) else {
+		let Ok(base_url) = Url::parse(&format!(
+			"https://{}{}",
+			leptos_options.site_addr, config.base_path
+		)) else {
 			unreachable!()
 		};
-		let request_client = RequestClient::build(builder, base_url).unwrap();
+		// NOTE: The internal client always uses the loopback address, even behind a reverse proxy
+		let request_client = RequestClient::build(builder, base_url.clone()).unwrap();
 
-		let db_pool = db::init()?;
+		let public_url = config
+			.public_url
+			.as_deref()
+			.map(|url| {
+				let mut url = Url::parse(url)?;
+				if !url.path().ends_with('/') {
+					url.set_path(&format!("{}/", url.path()));
+				}
+				Ok::<_, Box<dyn Error>>(url)
+			})
+			.transpose()?;
 
-		let plugins = PluginStore::load_plugins();
-		plugins.update_database(&db_pool)?;
-		plugins.load_media(&db_pool, &config.media);
+		let db_pool = db::init(&config.database)?;
 
+		let mut plugins = PluginStore::load_plugins(&config)?;
+		if !config.database.read_only {
+			plugins.update_database(&db_pool)?;
+			if let Err(err) = plugins.load_media(&db_pool, &config.media, None) {
+				log::error!("{err}");
+			}
+		}
+
+		let listener_config = config.listener;
+		let base_path = config.base_path.clone();
 		let state = AppState {
 			config,
 			leptos_options,
 			db_pool,
 			plugins: Arc::new(plugins),
 			request_client,
+			public_url: PublicUrl {
+				url: public_url.clone(),
+				base_path,
+			},
+			logs,
 		};
 
 		log::info!(target: LOG_HIGHLIGHT, "Starting the server on {addr}");
-		log::info!("You may access the app at: https://{site_addr}/");
+		log::info!(
+			"You may access the app at: {}",
+			public_url.unwrap_or(base_url)
+		);
 		Server::builder(ConnectedTlsAcceptor::new(
-			AddrIncoming::bind(&addr)?,
+			bind(addr, &listener_config)?,
 			&identity,
 		)?)
 		.http2_only(true)
@@ -347,6 +599,22 @@ async fn main() {
 	}
 }
 
+/// Binds the TCP listener of the server
+fn bind(addr: SocketAddr, config: &ListenerConfig) -> io::Result<AddrIncoming> {
+	let socket = match addr {
+		SocketAddr::V4(_) => TcpSocket::new_v4()?,
+		SocketAddr::V6(_) => TcpSocket::new_v6()?,
+	};
+	#[cfg(unix)]
+	socket.set_reuseaddr(true)?;
+	socket.bind(addr)?;
+	let mut incoming = AddrIncoming::from_listener(socket.listen(config.backlog)?)
+		.map_err(|err| io::Error::new(io::ErrorKind::Other, err))?;
+	incoming.set_keepalive(config.keepalive.map(Duration::from_secs));
+	incoming.set_nodelay(config.nodelay);
+	Ok(incoming)
+}
+
 /// Returns a [`Future`](std::future::Future) that resolves when the ⌃C signal is caught
 ///
 /// Additionally, on `unix` targets, the SIGTERM signal is also awaited.
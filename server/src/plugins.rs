@@ -3,34 +3,130 @@
 
 mod media;
 
-use crate::{config::MediaConfig, EXE_NAME};
-use media::MediaPlugin;
-use pluglib::Version;
+use crate::{
+	config::{Config, MediaConfig, PluginsConfig, RemovedPlugins},
+	source::{LocalFs, MediaSource},
+	EXE_NAME,
+};
+use globset::GlobMatcher;
+use libloading::Library;
+#[cfg(test)]
+pub(crate) use media::stub;
+pub(crate) use media::{IndexingEvent, MediaPlugin, Progress};
+use pluglib::{
+	ffi::{FfiMap, FfiStr},
+	log::LogLevel,
+	PluginConfig, Version,
+};
 use r2d2::Pool;
 use r2d2_sqlite::SqliteConnectionManager;
-use rayon::prelude::*;
+use rayon::{prelude::*, ThreadPool, ThreadPoolBuilder};
 use rusqlite::{
 	types::{FromSql, FromSqlError, FromSqlResult, ToSqlOutput, ValueRef},
-	Row, ToSql,
+	Connection, Row, ToSql,
 };
 use std::{
+	cmp::Ordering,
 	collections::{HashMap, HashSet},
 	error::Error,
 	fmt::{self, Debug, Display, Formatter},
 	hash::{Hash, Hasher},
+	num::NonZeroUsize,
 	path::{Path, PathBuf},
 	str::FromStr,
+	sync::{Arc, Condvar, Mutex},
+	thread,
+	time::Duration,
 };
 
+/// [`LogCallback`](pluglib::log::LogCallback) passed to plugins
+///
+/// The records are logged with a `plugin::<crate name>` target.
+extern "C" fn forward_plugin_log(target: FfiStr<'_>, level: LogLevel, message: FfiStr<'_>) {
+	let level = match level {
+		LogLevel::Error => log::Level::Error,
+		LogLevel::Warn => log::Level::Warn,
+		LogLevel::Info => log::Level::Info,
+		LogLevel::Debug => log::Level::Debug,
+		LogLevel::Trace => log::Level::Trace,
+	};
+	log::log!(target: &format!("plugin::{target}"), level, "{message}");
+}
+
+/// Loads a dynamic library, retrying after transient errors
+///
+/// The delay between retries is doubled each time.
+fn load_library(path: &Path, config: &PluginsConfig) -> Result<Library, libloading::Error> {
+	let mut delay = Duration::from_millis(config.load_retry_delay);
+	let mut retries = 0;
+	loop {
+		// SAFETY: Upheld by the plugin
+		match unsafe { Library::new(path) } {
+			Err(err) if retries < config.load_retries && is_transient_load_error(&err) => {
+				log::debug!(
+					"Could not load {}, retrying in {delay:?}: {err}",
+					path.display()
+				);
+				thread::sleep(delay);
+				delay = delay.saturating_mul(2);
+				retries += 1;
+			}
+			res => return res,
+		}
+	}
+}
+
+/// Returns whether a loading error may be caused by the library being written at the same time
+///
+/// Other errors (e.g. the file is not a valid dynamic library) are permanent.
+fn is_transient_load_error(err: &libloading::Error) -> bool {
+	/// Messages of the transient errors, as reported by the system loaders
+	const TRANSIENT_MESSAGES: &[&str] = &[
+		"text file busy",
+		"file too short",
+		"truncated",
+		"being used by another process",
+	];
+
+	let msg = err.to_string().to_lowercase();
+	TRANSIENT_MESSAGES.iter().any(|pat| msg.contains(pat))
+}
+
 /// Stores all plugins
-#[derive(Debug, Default)]
+#[derive(Debug)]
 pub(crate) struct PluginStore {
 	/// Stores media plugins
 	pub(crate) media: HashMap<String, MediaPlugin>,
+	/// Limits the number of metadata extractions running at the same time, across all plugins
+	extractions: Limiter,
+	/// Limits the number of media plugins indexing their media at the same time
+	scans: Limiter,
+	/// Thread pool running the indexing of media
+	threads: ThreadPool,
+	/// Minimum free space (in bytes) on the filesystem of the database to write indexed media
+	min_free_space: u64,
+	/// Storage that media files are read from
+	pub(crate) source: Arc<dyn MediaSource>,
+	/// Maximum time to read the metadata of a media file
+	stat_timeout: Option<Duration>,
+	/// Number of consecutive failed extractions after which a file is quarantined
+	quarantine_after: u32,
+	/// Number of indexed media between two commits to the database
+	commit_interval: usize,
+	/// Held while media are being indexed, so that indexings do not run concurrently
+	pub(crate) indexing: Arc<tokio::sync::Mutex<()>>,
+	/// Handling of the plugins found in the database but not loaded
+	removed_handling: RemovedPlugins,
+	/// Names of the plugins not to load, whose data is always kept
+	disabled: HashSet<String>,
+	/// Plugins found in the database but not loaded
+	///
+	/// It is filled by [`update_database`](Self::update_database).
+	pub(crate) removed: Vec<RemovedPlugin>,
 }
 impl PluginStore {
 	/// Returns the directories to search plugins in
-	fn get_plugin_dirs() -> Vec<PathBuf> {
+	pub(crate) fn get_plugin_dirs() -> Vec<PathBuf> {
 		let mut dirs = Vec::new();
 
 		/// Name of the plugins directory
@@ -60,15 +156,52 @@ impl PluginStore {
 		dirs
 	}
 
+	/// Constructs a new store without plugins
+	///
+	/// # Errors
+	/// This function returns an error if the thread pool of indexing cannot be created.
+	pub(crate) fn new(config: &Config) -> Result<Self, Box<dyn Error>> {
+		Ok(Self {
+			media: HashMap::new(),
+			extractions: Limiter::new(config.indexing.max_extractions()),
+			scans: Limiter::new(
+				config
+					.indexing
+					.max_scans
+					.map_or(usize::MAX, NonZeroUsize::get),
+			),
+			threads: ThreadPoolBuilder::new()
+				.num_threads(config.indexing.threads.map_or(0, NonZeroUsize::get))
+				.thread_name(|i| format!("indexing-{i}"))
+				.build()?,
+			min_free_space: config.indexing.min_free_space,
+			source: Arc::new(LocalFs),
+			stat_timeout: config.indexing.stat_timeout.map(Duration::from_millis),
+			quarantine_after: config.indexing.quarantine_after,
+			commit_interval: config.indexing.commit_interval,
+			indexing: Arc::default(),
+			removed_handling: config.plugins.removed,
+			disabled: config.plugins.disabled.clone(),
+			removed: Vec::new(),
+		})
+	}
+
 	/// Finds and loads all plugins
-	pub(super) fn load_plugins() -> Self {
-		let mut this = Self::default();
+	///
+	/// # Errors
+	/// This function returns an error if the thread pool of indexing cannot be created,
+	/// or if no media plugin was loaded
+	/// while [`plugins.required`](crate::config::PluginsConfig#structfield.required) is set.
+	pub(super) fn load_plugins(config: &Config) -> Result<Self, Box<dyn Error>> {
+		let max_extractions = config.indexing.max_extractions();
+		let mut this = Self::new(config)?;
 
 		log::debug!("Media plugin library {}", pluglib::media::PLUGLIB_VERSION);
 
-		Self::get_plugin_dirs()
-			.into_iter()
-			.filter_map(|dir| match std::fs::read_dir(&dir) {
+		let plugin_dirs = Self::get_plugin_dirs();
+		plugin_dirs
+			.iter()
+			.filter_map(|dir| match std::fs::read_dir(dir) {
 				Ok(dir) => Some(dir),
 				Err(ref err) if err.kind() == std::io::ErrorKind::NotFound => None,
 				Err(err) => {
@@ -86,11 +219,38 @@ impl PluginStore {
 				};
 				#[allow(clippy::single_match)]
 				match path.extension().and_then(|s| s.to_str()) {
-					Some("media") => match MediaPlugin::try_from(path.as_path()) {
+					Some("media") if this.disabled.contains(&name) => {
+						log::info!("Skipped media plugin {name}, which is disabled");
+					}
+					Some("media") => match MediaPlugin::load(&path, &config.plugins) {
 						Ok(plugin) => {
 							log::info!("Loaded {plugin}");
+							let options = config
+								.media
+								.get(&name)
+								.map_or_else(
+									|| Ok(FfiMap::default()),
+									|media_config| FfiMap::try_from(media_config.options.clone()),
+								)
+								.unwrap_or_else(|err| {
+									log::warn!(
+										"Ignoring the options of media plugin {name}: {err}"
+									);
+									FfiMap::default()
+								});
+							plugin.init(&PluginConfig {
+								max_extractions,
+								options,
+							});
 							this.media.insert(name, plugin);
 						}
+						Err(
+							err @ (PluginLoadError::IncompatibleLibVersions { .. }
+							| PluginLoadError::AbiMismatch { .. }
+							| PluginLoadError::MissingAbiFingerprint { .. }),
+						) => {
+							log::warn!("Could not load media plugin {name}: {err}");
+						}
 						Err(err) => {
 							log::debug!("Could not load media plugin {name}: {err}");
 						}
@@ -99,12 +259,29 @@ impl PluginStore {
 				}
 			});
 
-		this
+		if this.media.is_empty() {
+			let plugin_dirs = plugin_dirs
+				.iter()
+				.map(|dir| dir.display().to_string())
+				.collect::<Vec<_>>()
+				.join(", ");
+			if config.plugins.required {
+				return Err(format!("No media plugin was loaded from {plugin_dirs}").into());
+			}
+			log::warn!(
+				"No media plugin was loaded, so there is no library to serve; plugins are searched in {plugin_dirs}"
+			);
+		}
+		Ok(this)
 	}
 
 	/// Updates the database with the loaded plugins
+	///
+	/// The plugins of the database that are not loaded are handled
+	/// according to [`plugins.removed`](crate::config::PluginsConfig#structfield.removed),
+	/// except for the [disabled](crate::config::PluginsConfig#structfield.disabled) ones whose data is kept.
 	pub(super) fn update_database(
-		&self,
+		&mut self,
 		db_pool: &Pool<SqliteConnectionManager>,
 	) -> Result<(), Box<dyn Error>> {
 		let plugins = {
@@ -132,38 +309,276 @@ impl PluginStore {
 				!db_plugin.version.is_compatible(&old_plugin.version)
 			} else {
 				true
-			};
+			} || db_pool
+				.get()
+				.map_err(Box::<dyn Error>::from)
+				.and_then(|conn| plugin.is_table_outdated(&conn).map_err(From::from))
+				.unwrap_or_else(|err| {
+					log::error!("Could not read the schema of {plugin}: {err}");
+					false
+				});
 			if update_schema {
 				if let Err(err) = plugin.update_database(db_pool, db_plugin) {
 					log::error!("Could not insert {plugin} into the database: {err}");
 				}
+			} else if let Err(err) =
+				db_pool
+					.get()
+					.map_err(Box::<dyn Error>::from)
+					.and_then(|conn| {
+						conn.execute_batch(&plugin.indexes())?;
+						conn.execute(
+						"UPDATE plugins SET table_name = :table_name WHERE name = :name AND kind = :kind",
+						rusqlite::named_params! {
+							":table_name": db_plugin.table,
+							":name": db_plugin.name,
+							":kind": db_plugin.kind,
+						},
+					)?;
+						Ok(())
+					}) {
+				log::error!("Could not update {plugin} in the database: {err}");
 			}
 		});
 
+		self.removed = plugins
+			.into_iter()
+			.filter(|db_plugin| match db_plugin.kind {
+				PluginKind::Media => !self.media.contains_key(&db_plugin.name),
+			})
+			.map(|db_plugin| {
+				let disabled = self.disabled.contains(&db_plugin.name);
+				let dropped = match self.removed_handling {
+					_ if disabled => {
+						log::debug!("{db_plugin} is disabled, its data is left in the database");
+						false
+					}
+					RemovedPlugins::Leave => {
+						log::debug!("{db_plugin} is not loaded, its data is left in the database");
+						false
+					}
+					RemovedPlugins::Warn => {
+						log::warn!("{db_plugin} is not loaded, its data is left in the database");
+						false
+					}
+					RemovedPlugins::Drop => match db_pool
+						.get()
+						.map_err(Box::<dyn Error>::from)
+						.and_then(|mut conn| db_plugin.drop_data(&mut conn).map_err(From::from))
+					{
+						Ok(()) => {
+							log::info!("{db_plugin} is not loaded, its data was dropped");
+							true
+						}
+						Err(err) => {
+							log::error!("Could not drop the data of {db_plugin}: {err}");
+							false
+						}
+					},
+				};
+				RemovedPlugin {
+					name: db_plugin.name,
+					kind: db_plugin.kind,
+					version: db_plugin.version,
+					table: db_plugin.table,
+					disabled,
+					dropped,
+				}
+			})
+			.collect();
+
 		Ok(())
 	}
 
+	/// Drops and recreates the database table of a media plugin
+	///
+	/// The table will be empty until the media are loaded again.
+	pub(crate) fn rebuild_media_table(
+		&self,
+		name: &str,
+		db_pool: &Pool<SqliteConnectionManager>,
+	) -> Result<(), Box<dyn Error>> {
+		let plugin = self
+			.media
+			.get(name)
+			.ok_or_else(|| format!("no media plugin is named {name:?}"))?;
+		plugin.update_database(db_pool, plugin.into())
+	}
+
+	/// Drops and recreates the database table of a media plugin, then loads its media again
+	///
+	/// The caller must hold the [`indexing`](Self#structfield.indexing) lock.
+	/// If a progress channel is given, [`IndexingEvent`](media::IndexingEvent)s are sent to it.
+	pub(crate) fn rebuild_media(
+		&self,
+		name: &str,
+		db_pool: &Pool<SqliteConnectionManager>,
+		config: Option<&MediaConfig>,
+		progress: Progress<'_>,
+	) -> Result<(), Box<dyn Error>> {
+		self.rebuild_media_table(name, db_pool)?;
+		let (Some(plugin), Some(config)) = (self.media.get(name), config) else {
+			return Ok(());
+		};
+		let conn = db_pool.get()?;
+		plugin
+			.load_media(conn, config, self.indexing_context(), None, progress)
+			.map_err(From::from)
+	}
+
+	/// Loads the media of a media plugin again, extracting only the files matching a glob
+	///
+	/// The matching files are extracted even if they did not change, and the other ones are left as they are.
+	/// The caller must hold the [`indexing`](Self#structfield.indexing) lock.
+	/// If a progress channel is given, [`IndexingEvent`](media::IndexingEvent)s are sent to it.
+	pub(crate) fn reindex_media(
+		&self,
+		name: &str,
+		db_pool: &Pool<SqliteConnectionManager>,
+		config: Option<&MediaConfig>,
+		only: &GlobMatcher,
+		progress: Progress<'_>,
+	) -> Result<(), Box<dyn Error>> {
+		let plugin = self
+			.media
+			.get(name)
+			.ok_or_else(|| format!("no media plugin is named {name:?}"))?;
+		let Some(config) = config else {
+			return Ok(());
+		};
+		let conn = db_pool.get()?;
+		plugin
+			.load_media(conn, config, self.indexing_context(), Some(only), progress)
+			.map_err(From::from)
+	}
+
 	/// Loads all media files
-	#[inline]
+	///
+	/// If a progress channel is given, [`IndexingEvent`](media::IndexingEvent)s are sent to it.
+	///
+	/// # Errors
+	/// This function returns an error naming the libraries whose media could not be loaded,
+	/// or if media are already being indexed.
 	pub(super) fn load_media(
 		&self,
 		db_pool: &Pool<SqliteConnectionManager>,
 		config: &HashMap<String, MediaConfig>,
-	) {
-		self.media
+		progress: Progress<'_>,
+	) -> Result<(), Box<dyn Error>> {
+		let Ok(_indexing) = self.indexing.try_lock() else {
+			return Err("Media are already being indexed".into());
+		};
+
+		// NOTE: The plugins are not iterated on the indexing threads,
+		//       since they block while their media are extracted on them
+		let mut failed = self
+			.media
 			.par_iter()
-			.filter_map(|(name, plugin)| config.get(name).map(|config| (plugin, config)))
-			.for_each(|(plugin, config)| {
-				let conn = loop {
-					if let Some(conn) = db_pool.try_get() {
-						break conn;
+			.filter_map(|(name, plugin)| config.get(name).map(|config| (name, plugin, config)))
+			.filter_map(|(name, plugin, config)| {
+				let _permit = self.scans.acquire();
+				let conn = match db_pool.get() {
+					Ok(conn) => conn,
+					Err(err) => {
+						log::error!("Could not get a database connection for {plugin}: {err}");
+						return Some(name.as_str());
 					}
-					std::thread::yield_now();
 				};
-				if let Err(err) = plugin.load_media(conn, config) {
+				if let Err(err) =
+					plugin.load_media(conn, config, self.indexing_context(), None, progress)
+				{
 					log::error!("Could not commit media of {plugin}: {err}");
+					return Some(name.as_str());
 				}
-			});
+				None
+			})
+			.collect::<Vec<_>>();
+		if failed.is_empty() {
+			Ok(())
+		} else {
+			failed.sort_unstable();
+			Err(format!(
+				"Could not index the media of the libraries {}",
+				failed.join(", ")
+			)
+			.into())
+		}
+	}
+
+	/// Returns the resources shared by the indexing of all media plugins
+	#[inline]
+	fn indexing_context(&self) -> IndexingContext<'_> {
+		IndexingContext {
+			extractions: &self.extractions,
+			threads: &self.threads,
+			min_free_space: self.min_free_space,
+			source: &self.source,
+			stat_timeout: self.stat_timeout,
+			quarantine_after: self.quarantine_after,
+			commit_interval: self.commit_interval,
+		}
+	}
+}
+
+/// Resources shared by the indexing of all media plugins
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct IndexingContext<'i> {
+	/// Limits the number of metadata extractions running at the same time
+	pub(crate) extractions: &'i Limiter,
+	/// Thread pool walking the directories and extracting the metadata of media
+	pub(crate) threads: &'i ThreadPool,
+	/// Minimum free space (in bytes) on the filesystem of the database to write indexed media
+	pub(crate) min_free_space: u64,
+	/// Storage that media files are read from
+	pub(crate) source: &'i Arc<dyn MediaSource>,
+	/// Maximum time to read the metadata of a media file
+	pub(crate) stat_timeout: Option<Duration>,
+	/// Number of consecutive failed extractions after which a file is quarantined
+	pub(crate) quarantine_after: u32,
+	/// Number of indexed media between two commits to the database, or `0` to commit once
+	pub(crate) commit_interval: usize,
+}
+
+/// Counting semaphore that limits the number of operations running at the same time
+#[derive(Debug)]
+pub(crate) struct Limiter {
+	/// Number of available permits
+	permits: Mutex<usize>,
+	/// Notified when a permit is released
+	released: Condvar,
+}
+impl Limiter {
+	/// Constructs a new instance with the given number of permits
+	#[inline]
+	pub(crate) const fn new(permits: usize) -> Self {
+		Self {
+			permits: Mutex::new(permits),
+			released: Condvar::new(),
+		}
+	}
+
+	/// Blocks the current thread until a permit is available
+	///
+	/// The permit is released when the returned guard is dropped.
+	pub(crate) fn acquire(&self) -> LimiterPermit<'_> {
+		let mut permits = self
+			.released
+			.wait_while(self.permits.lock().unwrap(), |permits| *permits == 0)
+			.unwrap();
+		*permits -= 1;
+		LimiterPermit(self)
+	}
+}
+
+/// Guard of a [`Limiter`] permit
+#[derive(Debug)]
+#[must_use = "the permit is released when dropped"]
+pub(crate) struct LimiterPermit<'l>(&'l Limiter);
+impl<'l> Drop for LimiterPermit<'l> {
+	#[inline]
+	fn drop(&mut self) {
+		*self.0.permits.lock().unwrap() += 1;
+		self.0.released.notify_one();
 	}
 }
 
@@ -211,6 +626,25 @@ impl ToSql for PluginKind {
 	}
 }
 
+/// Plugin found in the database but not loaded
+#[derive(Debug, Clone)]
+pub(crate) struct RemovedPlugin {
+	/// Name of the plugin
+	pub(crate) name: String,
+	/// Kind of the plugin
+	pub(crate) kind: PluginKind,
+	/// Version of the plugin
+	pub(crate) version: Version,
+	/// Database table of the plugin
+	///
+	/// It is unknown if the plugin was removed before the tables were recorded.
+	pub(crate) table: Option<String>,
+	/// Is the plugin [disabled](crate::config::PluginsConfig#structfield.disabled)?
+	pub(crate) disabled: bool,
+	/// Was the data of the plugin dropped?
+	pub(crate) dropped: bool,
+}
+
 /// Structure of the `plugins` database table
 ///
 /// The [`PartialEq`], [`Eq`] and [`Hash`] implementations
@@ -223,6 +657,38 @@ struct DbPlugin {
 	kind: PluginKind,
 	/// Version of the plugin
 	version: Version,
+	/// Database table of the plugin
+	table: Option<String>,
+}
+impl DbPlugin {
+	/// Deletes the data of the plugin from the database
+	///
+	/// The user data is kept, so that it is available again once the plugin is restored.
+	fn drop_data(&self, conn: &mut Connection) -> rusqlite::Result<()> {
+		let transaction = conn.transaction()?;
+		if let Some(ref table) = self.table {
+			transaction.execute_batch(&format!("DROP TABLE IF EXISTS {table}"))?;
+		}
+		transaction.execute("DELETE FROM libraries WHERE name = ?", [&self.name])?;
+		for table in [
+			"media_generations",
+			"media_waveforms",
+			"media_group_keys",
+			"media_failures",
+			"media_raw_metadata",
+			"media_search",
+		] {
+			transaction.execute(
+				&format!("DELETE FROM {table} WHERE library = ?"),
+				[&self.name],
+			)?;
+		}
+		transaction.execute(
+			"DELETE FROM plugins WHERE name = ? AND kind = ?",
+			(&self.name, self.kind),
+		)?;
+		transaction.commit()
+	}
 }
 impl From<&MediaPlugin> for DbPlugin {
 	#[inline]
@@ -231,6 +697,7 @@ impl From<&MediaPlugin> for DbPlugin {
 			name: value.name.clone().into_string(),
 			kind: PluginKind::Media,
 			version: value.version,
+			table: Some(value.table_ident()),
 		}
 	}
 }
@@ -243,6 +710,7 @@ impl<'stmt> TryFrom<&'stmt Row<'stmt>> for DbPlugin {
 			name: row.get("name")?,
 			kind: row.get("kind")?,
 			version: row.get("version")?,
+			table: row.get("table_name")?,
 		})
 	}
 }
@@ -279,6 +747,22 @@ pub(super) enum PluginLoadError {
 		name: Box<str>,
 		/// Version of the plugin library that the plugin links to
 		plugin: Version,
+		/// Version of the plugin library that the server links to
+		server: Version,
+	},
+	/// The plugin was built for another platform or with other type layouts than the server
+	AbiMismatch {
+		/// Kind of the plugin
+		kind: PluginKind,
+		/// Name of the plugin
+		name: Box<str>,
+	},
+	/// The plugin does not export the fingerprint of its ABI
+	MissingAbiFingerprint {
+		/// Kind of the plugin
+		kind: PluginKind,
+		/// Name of the plugin
+		name: Box<str>,
 	},
 }
 impl From<libloading::Error> for PluginLoadError {
@@ -295,7 +779,32 @@ impl Display for PluginLoadError {
 				kind,
 				name,
 				plugin,
-			} => write!(f, "{kind:?} plugin <{name}> links to plugin library {plugin}, which is not compatible with the server's"),
+				server,
+			} => {
+				write!(f, "{kind} plugin <{name}> ")?;
+				match plugin.cmp(server) {
+					_ if *plugin == Version::default() => write!(
+						f,
+						"does not declare a valid plugin library version; the server provides {server}; rebuild the plugin"
+					),
+					Ordering::Greater => write!(
+						f,
+						"requires server plugin library >= {plugin}; the server provides {server}; upgrade the server"
+					),
+					Ordering::Less | Ordering::Equal => write!(
+						f,
+						"was built against plugin library {plugin}; the server provides {server}; upgrade the plugin"
+					),
+				}
+			}
+			Self::AbiMismatch { kind, name } => write!(
+				f,
+				"{kind} plugin <{name}> was built for another platform than the server; rebuild the plugin for this platform"
+			),
+			Self::MissingAbiFingerprint { kind, name } => write!(
+				f,
+				"{kind} plugin <{name}> does not declare an ABI fingerprint; rebuild the plugin"
+			),
 		}
 	}
 }
@@ -304,7 +813,9 @@ impl Error for PluginLoadError {
 	fn source(&self) -> Option<&(dyn Error + 'static)> {
 		match self {
 			Self::LibLoading(err) => Some(err),
-			Self::IncompatibleLibVersions { .. } => None,
+			Self::IncompatibleLibVersions { .. }
+			| Self::AbiMismatch { .. }
+			| Self::MissingAbiFingerprint { .. } => None,
 		}
 	}
 }
@@ -326,10 +837,15 @@ impl Error for InvalidPluginKind {
 }
 
 /// Trait for plugin structures
-trait Plugin: Debug + Display + for<'p> TryFrom<&'p Path, Error = PluginLoadError>
+trait Plugin: Debug + Display + Sized
 where
 	for<'this> &'this Self: Into<DbPlugin>,
 {
+	/// Loads the plugin from its dynamic library
+	///
+	/// Transient loading errors are retried according to `config`.
+	fn load(path: &Path, config: &PluginsConfig) -> Result<Self, PluginLoadError>;
+
 	/// Updates the database with the plugin
 	fn update_database(
 		&self,
@@ -337,3 +853,261 @@ where
 		db_plugin: DbPlugin,
 	) -> Result<(), Box<dyn Error>>;
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::source::SlowFs;
+	use pluglib::{
+		ffi::{FfiBoxedSlice, FfiError, FfiOption, FfiResult},
+		media::MetadataFieldValue,
+	};
+	use rusqlite::OptionalExtension;
+	use stub::Library;
+
+	/// Names of the threads that extracted the metadata of media files, by path
+	static EXTRACTION_THREADS: Mutex<Vec<(String, Option<String>)>> = Mutex::new(Vec::new());
+
+	/// Records the name of the current thread, then extracts the metadata like [`stub::extract_title`]
+	extern "C" fn extract_recording_thread(
+		path: FfiStr<'_>,
+	) -> FfiResult<FfiBoxedSlice<FfiOption<MetadataFieldValue>>, FfiError> {
+		EXTRACTION_THREADS.lock().unwrap().push((
+			path.to_str().to_owned(),
+			thread::current().name().map(ToOwned::to_owned),
+		));
+		stub::extract_title(path)
+	}
+
+	/// Paths of the media files whose metadata was extracted by [`extract_broken`]
+	static BROKEN_EXTRACTIONS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+	/// Fails to extract the metadata of the media files named `broken`,
+	/// and extracts the metadata of the other ones like [`stub::extract_title`]
+	extern "C" fn extract_broken(
+		path: FfiStr<'_>,
+	) -> FfiResult<FfiBoxedSlice<FfiOption<MetadataFieldValue>>, FfiError> {
+		BROKEN_EXTRACTIONS
+			.lock()
+			.unwrap()
+			.push(path.to_str().to_owned());
+		if Path::new(&*path).file_stem() == Some("broken".as_ref()) {
+			FfiResult::Err(FfiError::new(1, "stub failure"))
+		} else {
+			stub::extract_title(path)
+		}
+	}
+
+	#[test]
+	fn indexing_runs_on_dedicated_threads() {
+		let library = Library::new(extract_recording_thread, |config| {
+			config.indexing.threads = NonZeroUsize::new(2);
+		});
+		for i in 0..32 {
+			library.write(&format!("{i}.txt"), "media");
+		}
+		assert_eq!(library.plugins.threads.current_num_threads(), 2);
+		library.index();
+
+		let dir = library.dir.path().to_str().unwrap();
+		let threads = EXTRACTION_THREADS
+			.lock()
+			.unwrap()
+			.iter()
+			.filter(|(path, _)| path.starts_with(dir))
+			.map(|(_, thread)| thread.clone())
+			.collect::<Vec<_>>();
+		assert_eq!(threads.len(), 32);
+		let names = threads.into_iter().collect::<HashSet<_>>();
+		assert!(
+			names
+				.iter()
+				.all(|name| matches!(name.as_deref(), Some("indexing-0" | "indexing-1"))),
+			"metadata was extracted on {names:?}"
+		);
+	}
+
+	/// Extracts the metadata like [`stub::extract_title`], taking a millisecond
+	extern "C" fn extract_slowly(
+		path: FfiStr<'_>,
+	) -> FfiResult<FfiBoxedSlice<FfiOption<MetadataFieldValue>>, FfiError> {
+		thread::sleep(Duration::from_millis(1));
+		stub::extract_title(path)
+	}
+
+	#[test]
+	fn inserts_overlap_with_extractions() {
+		const COUNT: usize = 500;
+		let library = Library::new(extract_slowly, |config| {
+			config.indexing.threads = NonZeroUsize::new(2);
+			config.indexing.commit_interval = 100;
+		});
+		for i in 0..COUNT {
+			library.write(&format!("{i}.txt"), "media");
+		}
+		let (tx, rx) = std::sync::mpsc::channel();
+		library
+			.plugins
+			.load_media(&library.db_pool, &library.config.media, Some(&tx))
+			.unwrap();
+		drop(tx);
+
+		let events = rx.into_iter().collect::<Vec<_>>();
+		let inserted = events
+			.iter()
+			.enumerate()
+			.filter(|(_, event)| matches!(event, IndexingEvent::Inserted { .. }))
+			.map(|(i, _)| i)
+			.collect::<Vec<_>>();
+		let last_probed = events
+			.iter()
+			.rposition(|event| matches!(event, IndexingEvent::Probed { .. }))
+			.unwrap();
+		assert_eq!(inserted.len(), COUNT);
+		assert!(
+			inserted[0] < last_probed,
+			"media were only inserted after all extractions"
+		);
+		assert_eq!(library.titles().len(), COUNT);
+	}
+
+	#[test]
+	fn slow_files_are_kept() {
+		let mut library = Library::new(stub::extract_title, |config| {
+			config.indexing.stat_timeout = Some(100);
+		});
+		library.write("fast.txt", "media");
+		library.write("slow.txt", "media");
+		library.index();
+		assert_eq!(library.titles(), ["fast", "slow"]);
+
+		Arc::get_mut(&mut library.plugins).unwrap().source = Arc::new(SlowFs);
+		let start = std::time::Instant::now();
+		library.index();
+		assert!(
+			start.elapsed() < SlowFs::DELAY,
+			"the timeout did not expire"
+		);
+		assert_eq!(library.titles(), ["fast", "slow"]);
+	}
+
+	#[test]
+	fn failing_files_are_quarantined() {
+		let library = Library::new(extract_broken, |config| {
+			config.indexing.quarantine_after = 2;
+		});
+		library.write("fine.txt", "media");
+		let broken = library.write("broken.txt", "media");
+		let broken = broken.to_str().unwrap();
+		let extractions = || {
+			BROKEN_EXTRACTIONS
+				.lock()
+				.unwrap()
+				.iter()
+				.filter(|path| path.starts_with(broken))
+				.count()
+		};
+		let failures = || -> Option<u32> {
+			library
+				.db_pool
+				.get()
+				.unwrap()
+				.query_row(
+					"SELECT failures FROM media_failures WHERE library = ? AND path = ?",
+					[Library::NAME, broken],
+					|row| row.get(0),
+				)
+				.optional()
+				.unwrap()
+		};
+
+		library.index();
+		library.index();
+		assert_eq!(extractions(), 2);
+		assert_eq!(failures(), Some(2));
+		library.index();
+		assert_eq!(extractions(), 2, "the quarantined file was extracted");
+		assert_eq!(failures(), Some(2));
+		assert_eq!(library.titles(), ["fine"]);
+
+		library.write("broken.txt", "modified media");
+		library.index();
+		assert_eq!(extractions(), 3, "the modified file was not extracted");
+		assert_eq!(failures(), Some(1));
+	}
+
+	/// Returns the message of an [`IncompatibleLibVersions`](PluginLoadError::IncompatibleLibVersions) error
+	fn incompatible_lib_versions(plugin: &str, server: &str) -> String {
+		PluginLoadError::IncompatibleLibVersions {
+			kind: PluginKind::Media,
+			name: "stub".into(),
+			plugin: plugin.parse().unwrap_or_default(),
+			server: server.parse().unwrap(),
+		}
+		.to_string()
+	}
+
+	#[test]
+	fn newer_plugin_library_tells_to_upgrade_the_server() {
+		assert_eq!(
+			incompatible_lib_versions("0.3.0", "0.2.1"),
+			"media plugin <stub> requires server plugin library >= 0.3.0; the server provides 0.2.1; upgrade the server"
+		);
+	}
+
+	#[test]
+	fn older_plugin_library_tells_to_upgrade_the_plugin() {
+		assert_eq!(
+			incompatible_lib_versions("0.1.4", "0.2.1"),
+			"media plugin <stub> was built against plugin library 0.1.4; the server provides 0.2.1; upgrade the plugin"
+		);
+	}
+
+	#[test]
+	fn invalid_plugin_library_version_tells_to_rebuild_the_plugin() {
+		assert_eq!(
+			incompatible_lib_versions("invalid", "0.2.1"),
+			"media plugin <stub> does not declare a valid plugin library version; the server provides 0.2.1; rebuild the plugin"
+		);
+	}
+
+	/// Records logged under a `plugin::` target, with their level and message
+	static PLUGIN_RECORDS: Mutex<Vec<(String, log::Level, String)>> = Mutex::new(Vec::new());
+
+	/// [Logger](log::Log) recording the records logged under a `plugin::` target
+	struct PluginRecorder;
+	impl log::Log for PluginRecorder {
+		fn enabled(&self, metadata: &log::Metadata<'_>) -> bool {
+			metadata.target().starts_with("plugin::")
+		}
+
+		fn log(&self, record: &log::Record<'_>) {
+			if self.enabled(record.metadata()) {
+				PLUGIN_RECORDS.lock().unwrap().push((
+					record.target().to_owned(),
+					record.level(),
+					record.args().to_string(),
+				));
+			}
+		}
+
+		fn flush(&self) {}
+	}
+
+	#[test]
+	fn plugin_logs_are_forwarded() {
+		log::set_logger(&PluginRecorder).expect("another logger was set");
+		log::set_max_level(log::LevelFilter::Trace);
+		pluglib::log::set_callback(forward_plugin_log);
+
+		pluglib::log::log("stub_plugin", LogLevel::Warn, "Something happened");
+		assert_eq!(
+			*PLUGIN_RECORDS.lock().unwrap(),
+			[(
+				"plugin::stub_plugin".to_owned(),
+				log::Level::Warn,
+				"Something happened".to_owned(),
+			)]
+		);
+	}
+}
@@ -1,31 +1,102 @@
 //! Provides the [`MediaPlugin`] struct
 
-use super::{DbPlugin, Plugin, PluginKind, PluginLoadError};
-use crate::config::MediaConfig;
-use libloading::{Library, Symbol};
+use super::{DbPlugin, IndexingContext, Plugin, PluginKind, PluginLoadError};
+use crate::{
+	config::{FieldMismatch, MediaConfig, PluginsConfig},
+	db,
+	source::StatPool,
+	waveform,
+};
+use globset::GlobMatcher;
+use libloading::Library;
 use pluglib::{
-	media::{DescribeMedia, ExtractMetadata, Media, MetadataFieldValue, SupportedTypes},
-	PluginVersion, Version,
+	ffi::{FfiOption, FfiString},
+	log::SetLogger,
+	media::{
+		DescribeMedia, ExtractMetadata, ExtractRawMetadata, Media, MetadataFieldType,
+		MetadataFieldValue, SupportedExtensions, SupportedTypes,
+	},
+	PluginConfig, PluginInit, PluginShutdown, PluginVersion, Version,
 };
 use r2d2::{Pool, PooledConnection};
 use r2d2_sqlite::SqliteConnectionManager;
 use rayon::prelude::*;
-use rusqlite::ToSql;
+use rusqlite::{Connection, OptionalExtension, ToSql};
+use serde::Serialize;
 use std::{
 	collections::{HashMap, HashSet},
 	error::Error,
 	fmt::{self, Debug, Display, Formatter},
 	path::Path,
 	sync::{mpsc, Arc, Mutex},
-	time::Instant,
+	time::{Instant, SystemTime},
 };
 use time::OffsetDateTime;
 use walkdir::WalkDir;
 
+/// Progress event emitted while [loading media](MediaPlugin::load_media)
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "event", rename_all = "lowercase")]
+pub(crate) enum IndexingEvent {
+	/// A media file of a supported type was found
+	Seen {
+		/// Name of the library
+		library: Box<str>,
+		/// Path of the media file
+		path: String,
+	},
+	/// The metadata of a media file was extracted
+	Probed {
+		/// Name of the library
+		library: Box<str>,
+		/// Path of the media file
+		path: String,
+	},
+	/// A media was inserted or updated in the database
+	Inserted {
+		/// Name of the library
+		library: Box<str>,
+		/// Path of the media file
+		path: String,
+	},
+	/// Media whose files disappeared were removed from the database
+	Removed {
+		/// Name of the library
+		library: Box<str>,
+		/// Number of removed media
+		count: usize,
+	},
+}
+
+/// Optional channel to send [`IndexingEvent`]s to
+pub(crate) type Progress<'p> = Option<&'p mpsc::Sender<IndexingEvent>>;
+
+/// Sends the event built by the given function to the progress channel, if any
+///
+/// The event is only built if there is a channel.
+#[inline]
+fn emit(progress: Progress<'_>, event: impl FnOnce() -> IndexingEvent) {
+	if let Some(progress) = progress {
+		// The receiver may have hung up, which does not concern indexing
+		let _ = progress.send(event());
+	}
+}
+
 /// Structure of a [media plugin](pluglib::media)
 pub(crate) struct MediaPlugin {
 	/// Dynamic library
 	lib: Library,
+	/// [`SupportedTypes`] function of the plugin
+	///
+	/// Like the other function pointers, it is only valid while [`lib`](Self#structfield.lib) is loaded,
+	/// so it must not be copied out of this struct.
+	supported_types: SupportedTypes,
+	/// Optional [`SupportedExtensions`] function of the plugin
+	supported_extensions: Option<SupportedExtensions>,
+	/// [`ExtractMetadata`] function of the plugin
+	extract_metadata: ExtractMetadata,
+	/// Optional [`ExtractRawMetadata`] function of the plugin
+	extract_raw_metadata: Option<ExtractRawMetadata>,
 
 	/// Name of the plugin
 	pub(crate) name: Box<str>,
@@ -35,74 +106,24 @@ pub(crate) struct MediaPlugin {
 	/// Description of the media type provided by the plugin
 	pub(crate) media: Media,
 }
-impl TryFrom<&Path> for MediaPlugin {
-	type Error = PluginLoadError;
-
-	fn try_from(path: &Path) -> Result<Self, Self::Error> {
-		// SAFETY: Upheld by the plugin
-		let lib = unsafe { Library::new(path)? };
-
-		let Some(name) = path.file_stem().map(|s| s.to_string_lossy().into()) else {
-			unreachable!()
-		};
-
-		// SAFETY: Upheld by the plugin
-		let pluglib_version = unsafe { &**lib.get::<*const Version>(b"PLUGLIB_VERSION\0")? };
-		if !pluglib::media::PLUGLIB_VERSION.is_compatible(pluglib_version) {
-			return Err(PluginLoadError::IncompatibleLibVersions {
-				kind: PluginKind::Media,
-				name,
-				plugin: *pluglib_version,
-			});
-		}
-
-		// SAFETY: Upheld by the plugin
-		let plugin_version = unsafe { lib.get::<PluginVersion>(b"plugin_version\0")? };
-		let version = plugin_version();
-
-		// SAFETY: Upheld by the plugin
-		let describe_plugin = unsafe { lib.get::<DescribeMedia>(b"describe_media\0")? };
-		let media = describe_plugin();
-
-		// SAFETY: Upheld by the plugin
-		unsafe {
-			lib.get::<SupportedTypes>(Self::SUPPORTED_TYPES)?;
-			lib.get::<ExtractMetadata>(Self::EXTRACT_METADATA)?;
-		}
-
-		Ok(Self {
-			lib,
-			name,
-			version,
-			media,
-		})
-	}
-}
 impl MediaPlugin {
 	/// Symbol of the [`SupportedTypes`] function
 	const SUPPORTED_TYPES: &[u8] = b"supported_types\0";
+	/// Symbol of the optional [`SupportedExtensions`] function
+	const SUPPORTED_EXTENSIONS: &[u8] = b"supported_extensions\0";
 	/// Symbol of the [`ExtractMetadata`] function
 	const EXTRACT_METADATA: &[u8] = b"extract_metadata\0";
+	/// Symbol of the optional [`ExtractRawMetadata`] function
+	const EXTRACT_RAW_METADATA: &[u8] = b"extract_raw_metadata\0";
+	/// Number of inserted media between two checks of the free space
+	const FREE_SPACE_CHECK_INTERVAL: usize = 256;
 
-	/// Lists the types supported by the plugin
-	#[inline]
-	pub(super) fn supported_types(&self) -> Symbol<'_, SupportedTypes> {
+	/// Initializes the plugin, if it supports it
+	pub(super) fn init(&self, config: &PluginConfig) {
 		// SAFETY: Upheld by plugin
-		unsafe {
-			self.lib
-				.get(Self::SUPPORTED_TYPES)
-				.unwrap_or_else(|_err| unreachable!())
-		}
-	}
-
-	/// Extracts the metadata of the given media
-	#[inline]
-	pub(super) fn extract_metadata(&self) -> Symbol<'_, ExtractMetadata> {
-		// SAFETY: Upheld by plugin
-		unsafe {
-			self.lib
-				.get(Self::EXTRACT_METADATA)
-				.unwrap_or_else(|_err| unreachable!())
+		if let Ok(plugin_init) = unsafe { self.lib.get::<PluginInit>(b"plugin_init\0") } {
+			log::debug!("Initializing {self}");
+			plugin_init(config);
 		}
 	}
 
@@ -111,17 +132,103 @@ impl MediaPlugin {
 		format!("media_{}", self.media.ident)
 	}
 
+	/// Returns the SQL statements that create the indexes of the database table
+	///
+	/// They are idempotent, so they can be executed on tables created before an index was added.
+	pub(crate) fn indexes(&self) -> String {
+		format!(
+			"CREATE INDEX IF NOT EXISTS {table}_mtime ON {table}(mtime);\n",
+			table = self.table_ident(),
+		)
+	}
+
+	/// Returns the SQL statement that creates the database table, as stored in `sqlite_master`
+	///
+	/// Ids are allocated with `AUTOINCREMENT`, so that they are never reused:
+	/// an id found in a link may only refer to the media it was given to.
+	fn create_table(&self) -> String {
+		let mut fields = vec![
+			"id INTEGER PRIMARY KEY AUTOINCREMENT".to_owned(),
+			"path TEXT NOT NULL UNIQUE".to_owned(),
+			"mtime TEXT NOT NULL".to_owned(),
+			"size INTEGER NOT NULL".to_owned(),
+		];
+		fields.extend(self.media.fields.iter().map(|field| {
+			format!(
+				"{} {}",
+				field.ident,
+				if field.is_list {
+					"TEXT NOT NULL DEFAULT (json_array())"
+				} else {
+					field.r#type.to_sql()
+				}
+			)
+		}));
+
+		format!(
+			"CREATE TABLE {} ({}) STRICT",
+			self.table_ident(),
+			fields.join(","),
+		)
+	}
+
+	/// Returns the SQL statements that create the database table
+	///
+	/// They are the ones executed by [`update_database`](Plugin::update_database).
+	pub(crate) fn schema(&self) -> String {
+		format!("{};\n{}", self.create_table(), self.indexes())
+	}
+
+	/// Checks if the database table is missing or was created from another schema
+	pub(crate) fn is_table_outdated(&self, conn: &Connection) -> rusqlite::Result<bool> {
+		conn.query_row(
+			"SELECT sql FROM sqlite_master WHERE type = 'table' AND name = ?",
+			[self.table_ident()],
+			|row| row.get::<_, String>(0),
+		)
+		.optional()
+		.map(|sql| sql.map_or(true, |sql| sql != self.create_table()))
+	}
+
 	/// Loads media files using this plugin
 	///
-	/// # Panics
-	/// This function panics if a [`libloading::Error`] occurs.
+	/// If `only` is given, only the files matching it are extracted, even if they did not change or are quarantined,
+	/// and the other ones are left as they are.
+	/// Files whose metadata cannot be read (e.g. because the [`stat_timeout`](IndexingContext#structfield.stat_timeout) expired)
+	/// are left as they are; the metadata is read by a [`StatPool`] with as many threads as the pool of the indexing.
+	/// The timeout does not cover the walk itself, which lists the directories
+	/// and reads the metadata of symbolic links on the calling thread.
+	/// The free space on the filesystem of the database is checked before writing
+	/// and periodically while writing, and the update is rolled back if it runs low.
+	/// Extracted media are inserted by the calling thread while the next ones are being extracted.
+	/// Media are committed in batches of [`commit_interval`](IndexingContext#structfield.commit_interval),
+	/// so that an interrupted indexing only rolls back the last ones;
+	/// removed media and normalized keys are only updated once all media are indexed.
 	pub(super) fn load_media(
 		&self,
 		mut conn: PooledConnection<SqliteConnectionManager>,
 		config: &MediaConfig,
+		IndexingContext {
+			extractions,
+			threads,
+			min_free_space,
+			source,
+			stat_timeout,
+			quarantine_after,
+			commit_interval,
+		}: IndexingContext<'_>,
+		only: Option<&GlobMatcher>,
+		progress: Progress<'_>,
 	) -> rusqlite::Result<()> {
-		let extract_metadata = self.extract_metadata();
-		let supported_types = self.supported_types();
+		let extract_metadata = self.extract_metadata;
+		let supported_types = self.supported_types;
+		let extract_raw_metadata = config
+			.raw_metadata
+			.then_some(self.extract_raw_metadata)
+			.flatten();
+		if config.raw_metadata && extract_raw_metadata.is_none() {
+			log::warn!("{self} does not provide raw metadata");
+		}
 
 		// List supported types
 		let supported_types = supported_types();
@@ -130,6 +237,15 @@ impl MediaPlugin {
 			.map(|s| s.to_str())
 			.collect::<HashSet<_>>();
 		log::debug!("Supported MIME types by {self}: {supported_types:?}");
+		let supported_extensions = self.supported_extensions.map(|supported_extensions| {
+			supported_extensions()
+				.iter()
+				.map(|s| s.to_str().trim_start_matches('.').to_ascii_lowercase())
+				.collect::<HashSet<_>>()
+		});
+		if let Some(ref supported_extensions) = supported_extensions {
+			log::debug!("Supported extensions by {self}: {supported_extensions:?}");
+		}
 
 		// List previously cached media
 		let cached_media = {
@@ -147,12 +263,43 @@ impl MediaPlugin {
 		log::debug!("{cached_count} {} media are cached", self.media.name);
 		let cached_media = Arc::new(Mutex::new(cached_media));
 
+		// List previous extraction failures
+		let failures = {
+			let mut stmt =
+				conn.prepare("SELECT path, mtime, failures FROM media_failures WHERE library = ?")?;
+			let rows = stmt.query_map([&*self.name], |row| {
+				Ok((row.get(0)?, (row.get(1)?, row.get(2)?)))
+			})?;
+			let ret = rows.collect::<rusqlite::Result<HashMap<String, (OffsetDateTime, u32)>>>()?;
+			stmt.finalize()?;
+			ret
+		};
+		let failures = Mutex::new(failures);
+		let new_failures = Mutex::new(Vec::new());
+
+		let required_fields = config
+			.required_fields
+			.iter()
+			.filter_map(|ident| {
+				let index = self
+					.media
+					.fields
+					.iter()
+					.position(|field| field.ident.to_str() == ident);
+				if index.is_none() {
+					log::warn!("Ignoring the unknown required field {ident:?} of {self}");
+				}
+				index
+			})
+			.collect::<Vec<_>>();
+
 		// Prepare database update
+		db::check_free_space(min_free_space)?;
 		let transaction = conn.transaction()?;
 
-		let mut fields = vec!["path", "mtime"];
+		let mut fields = vec!["path", "mtime", "size"];
 		fields.extend(self.media.fields.iter().map(|field| field.ident.to_str()));
-		let mut value_binds = vec!["?"; 2];
+		let mut value_binds = vec!["?"; 3];
 		value_binds.extend(self.media.fields.iter().map(|field| {
 			if field.is_list {
 				"ifnull(?, json_array())"
@@ -161,117 +308,341 @@ impl MediaPlugin {
 			}
 		}));
 		let mut stmt = transaction.prepare(&format!(
-			"INSERT INTO {table}({fields}) VALUES ({value_binds})",
+			"INSERT INTO {table}({fields}) VALUES ({value_binds}) ON CONFLICT (path) DO UPDATE SET {updates}",
 			table = self.table_ident(),
+			updates = fields
+				.iter()
+				.skip(1)
+				.map(|field| format!("{field} = excluded.{field}"))
+				.collect::<Vec<_>>()
+				.join(", "),
 			fields = fields.join(", "),
 			value_binds = value_binds.join(", "),
 		))?;
 
-		// List all media
+		let mut waveform_stmt = transaction
+			.prepare("INSERT INTO media_waveforms(library, path, peaks) VALUES (?, ?, ?)")?;
+		let mut raw_metadata_stmt = transaction.prepare(
+			"INSERT INTO media_raw_metadata(library, path, raw_metadata) VALUES (?, ?, ?)",
+		)?;
+		let mut clear_failures_stmt =
+			transaction.prepare("DELETE FROM media_failures WHERE library = ? AND path = ?")?;
+
+		// List all media, while this thread inserts the extracted ones
+		// NOTE: SQLite has a single writer, so inserts on parallel connections would only take turns,
+		//       and bulk inserts would wait for whole batches to be extracted.
+		//       Inserting each media as soon as it is extracted hides the inserts behind the extractions,
+		//       which take much longer, so indexing ends shortly after the last extraction.
 		let (tx, rx) = mpsc::channel();
+		// NOTE: The walk reads at most one metadata per thread of the pool at a time
+		let stat_pool = StatPool::new(source, stat_timeout, threads.current_num_threads());
 		let start = Instant::now();
-		config.paths.par_iter().for_each_with(
-			(Arc::clone(&cached_media), tx),
-			|(cached_media, tx), path| {
-				log::info!(
-					"Searching {:?} for {} media...",
-					path.display(),
-					self.media.name
-				);
-				WalkDir::new(path)
-					.follow_links(true)
-					.into_iter()
-					.filter_entry(|entry| {
-						#[cfg(unix)]
-						{
-							entry
-								.file_name()
-								.to_str()
-								.map_or(false, |s| !s.starts_with('.'))
-						}
-						#[cfg(not(unix))]
-						{
-							true
-						}
-					})
-					.par_bridge()
-					.filter_map(|res| {
-						let entry = res.ok()?;
-						if entry.metadata().ok()?.is_dir() {
-							return None;
-						}
-						if let Some(mime) = entry.file_name().to_str().and_then(mime_db::lookup) {
-							if supported_types.contains(mime) {
-								return Some(entry);
-							}
+		let added_count = std::thread::scope(|scope| {
+			// NOTE: The walk is installed from this thread rather than from a worker of the pool,
+			//       which would be blocked by the inserts below while the extractions wait for it
+			scope.spawn(|| {
+				threads.install(|| {
+					config.paths.par_iter().for_each_with(
+						(Arc::clone(&cached_media), tx),
+						|(cached_media, tx), path| {
+							log::info!(
+								"Searching {:?} for {} media...",
+								path.display(),
+								self.media.name
+							);
+							WalkDir::new(path)
+								.follow_links(true)
+								.into_iter()
+								.filter_entry(|entry| {
+									#[cfg(unix)]
+									{
+										entry
+											.file_name()
+											.to_str()
+											.map_or(false, |s| !s.starts_with('.'))
+									}
+									#[cfg(not(unix))]
+									{
+										true
+									}
+								})
+								.par_bridge()
+								.filter_map(|res| {
+									let entry = res.ok()?;
+									let info = match stat_pool.metadata(entry.path()) {
+										Ok(info) => info,
+										Err(err) => {
+											log::warn!(
+												"Could not read the metadata of {:?}: {err}",
+												entry.path()
+											);
+											// NOTE: The file may still exist, so its media must not be removed
+											if let Some(path) = entry.path().to_str() {
+												cached_media.lock().unwrap().remove(path);
+												failures.lock().unwrap().remove(path);
+											}
+											return None;
+										}
+									};
+									if info.is_dir {
+										return None;
+									}
+									let is_supported = entry
+										.file_name()
+										.to_str()
+										.and_then(mime_db::lookup)
+										.is_some_and(|mime| supported_types.contains(mime))
+										|| supported_extensions.as_ref().is_some_and(
+											|supported_extensions| {
+												entry
+													.path()
+													.extension()
+													.and_then(|ext| ext.to_str())
+													.is_some_and(|ext| {
+														supported_extensions
+															.contains(&ext.to_ascii_lowercase())
+													})
+											},
+										);
+									if !is_supported {
+										return None;
+									}
+									if info.len == 0 {
+										log::debug!("Skipping empty {:?}", entry.path());
+										return None;
+									}
+									if only.is_some_and(|only| !only.is_match(entry.path())) {
+										// NOTE: The media stays as it is, so it must not be removed
+										if let Some(path) = entry.path().to_str() {
+											cached_media.lock().unwrap().remove(path);
+											failures.lock().unwrap().remove(path);
+										}
+										return None;
+									}
+									emit(progress, || IndexingEvent::Seen {
+										library: self.name.clone(),
+										path: entry.path().to_string_lossy().into_owned(),
+									});
+									Some((entry, info))
+								})
+								.filter_map(|(entry, info)| {
+									let mtime = info.modified;
+									let size = info.len;
+									let mut path =
+										entry.into_path().into_os_string().into_string().ok()?;
+
+									let forced = only.is_some();
+									if cached_media
+										.lock()
+										.unwrap()
+										.remove(path.as_str())
+										.map(|cached_mtime| {
+											OffsetDateTime::from(mtime) <= cached_mtime
+										})
+										.unwrap_or_default() && !forced
+									{
+										log::debug!("Skipping {path:?}");
+										return None;
+									}
+									let previous_failures = failures
+										.lock()
+										.unwrap()
+										.remove(path.as_str())
+										.filter(|(failed_mtime, _)| {
+											*failed_mtime == OffsetDateTime::from(mtime)
+										})
+										.map_or(0, |(_, count)| count);
+									if !forced
+										&& quarantine_after > 0 && previous_failures
+										>= quarantine_after
+									{
+										log::debug!("Skipping quarantined {path:?}");
+										new_failures.lock().unwrap().push((
+											path,
+											mtime,
+											previous_failures,
+										));
+										return None;
+									}
+
+									path.push('\0');
+									let (metadata, raw_metadata) = {
+										let _permit = extractions.acquire();
+										let ffi_path = path
+											.as_str()
+											.try_into()
+											.unwrap_or_else(|_err| unreachable!());
+										(
+											extract_metadata(ffi_path),
+											extract_raw_metadata.as_ref().and_then(
+												|extract_raw_metadata| {
+													Option::from(extract_raw_metadata(ffi_path))
+												},
+											),
+										)
+									};
+									path.pop();
+									match metadata.into() {
+										Ok(mut data) => {
+											emit(progress, || IndexingEvent::Probed {
+												library: self.name.clone(),
+												path: path.clone(),
+											});
+											let expected = self.media.fields.len();
+											if data.len() != expected {
+												match config.field_mismatch {
+													FieldMismatch::Skip => {
+														log::warn!(
+												"{self} returned {} values instead of {expected} for {path:?}, skipping it",
+												data.len(),
+											);
+														return None;
+													}
+													FieldMismatch::Pad => {
+														log::warn!(
+												"{self} returned {} values instead of {expected} for {path:?}, padding them",
+												data.len(),
+											);
+														data = data
+															.iter()
+															.cloned()
+															.chain(std::iter::repeat_with(|| {
+																FfiOption::None
+															}))
+															.take(expected)
+															.collect();
+													}
+												}
+											}
+											if let Some(&index) =
+												required_fields.iter().find(|&&index| {
+													data.get(index).map_or(true, |value| {
+														value.as_ref().is_none()
+													})
+												}) {
+												log::debug!(
+													"Skipping {path:?}, as it has no {}",
+													self.media.fields[index].ident
+												);
+												return None;
+											}
+											log::trace!(
+												"Extracted metadata from {path:?}: {data:?}"
+											);
+											let waveform = config
+												.waveform
+												.then(|| {
+													let _permit = extractions.acquire();
+													waveform::compute_peaks(Path::new(&path))
+												})
+												.and_then(|res| {
+													res.map_err(|err| {
+														log::warn!(
+												"Could not compute the waveform of {path:?}: {err}"
+											);
+													})
+													.ok()
+												});
+											let raw_metadata =
+												raw_metadata.and_then(|raw_metadata: FfiString| {
+													db::compress(raw_metadata.as_bytes())
+														.map_err(|err| {
+															log::warn!(
+												"Could not compress the raw metadata of {path:?}: {err}"
+											);
+														})
+														.ok()
+												});
+											Some((path, mtime, size, data, waveform, raw_metadata))
+										}
+										Err(err) => {
+											log::warn!(
+												"Could not extract metadata from {path:?}: {err}"
+											);
+											let count = previous_failures + 1;
+											if count == quarantine_after {
+												log::warn!(
+										"Quarantined {path:?} after {count} failed extractions"
+									);
+											}
+											new_failures.lock().unwrap().push((path, mtime, count));
+											None
+										}
+									}
+								})
+								.for_each(|(path, mtime, size, data, waveform, raw_metadata)| {
+									let mut values: Vec<Box<dyn ToSql + Send + Sync>> = vec![
+										Box::new(path.clone()),
+										Box::new(OffsetDateTime::from(mtime)),
+										Box::new(size),
+									];
+									values.extend(data.into_iter().cloned().map(|value| {
+										Box::new(Option::<MetadataFieldValue>::from(value))
+											as Box<dyn ToSql + Send + Sync>
+									}));
+									// NOTE: The receiver is dropped if an insert failed,
+									//       then the error is returned once the extraction ends
+									let _ = tx.send((path, values, waveform, raw_metadata));
+								});
+						},
+					);
+				});
+			});
+
+			rx.into_iter()
+				.enumerate()
+				.map(|(i, (path, values, waveform, raw_metadata))| {
+					if i > 0 && i % Self::FREE_SPACE_CHECK_INTERVAL == 0 {
+						db::check_free_space(min_free_space)?;
+					}
+					if i > 0 && commit_interval > 0 && i % commit_interval == 0 {
+						// NOTE: The statements are reset after each execution,
+						//       so the transaction can be restarted under them
+						db::bump_generation(&transaction, &self.name)?;
+						transaction.execute_batch("COMMIT; BEGIN")?;
+						log::debug!("Committed {i} {} media", self.media.name);
+					}
+					if let Some(waveform) = waveform {
+						if let Err(err) = waveform_stmt.execute((&*self.name, &path, waveform)) {
+							log::trace!("Could not insert the waveform of {path:?}: {err}");
 						}
-						None
-					})
-					.filter_map(|entry| {
-						let mtime = entry
-							.metadata()
-							.map_err(From::from)
-							.and_then(|meta| meta.modified())
-							.expect("the last modification time of a file should be available");
-						let mut path = entry.into_path().into_os_string().into_string().ok()?;
-
-						if cached_media
-							.lock()
-							.unwrap()
-							.remove(path.as_str())
-							.map(|cached_mtime| OffsetDateTime::from(mtime) <= cached_mtime)
-							.unwrap_or_default()
+					}
+					if let Some(raw_metadata) = raw_metadata {
+						if let Err(err) =
+							raw_metadata_stmt.execute((&*self.name, &path, raw_metadata))
 						{
-							log::debug!("Skipping {path:?}");
-							return None;
-						}
-
-						path.push('\0');
-						let metadata = extract_metadata(
-							path.as_str()
-								.try_into()
-								.unwrap_or_else(|_err| unreachable!()),
-						);
-						path.pop();
-						match metadata.into() {
-							Ok(data) => {
-								log::trace!("Extracted metadata from {path:?}: {data:?}");
-								Some((path, mtime, data))
-							}
-							Err(()) => {
-								log::warn!("Could not extract metadata from {path:?}");
-								None
-							}
+							log::trace!("Could not insert the raw metadata of {path:?}: {err}");
 						}
-					})
-					.for_each(|(path, mtime, data)| {
-						let mut values: Vec<Box<dyn ToSql + Send + Sync>> = vec![
-							Box::new(path.clone()),
-							Box::new(OffsetDateTime::from(mtime)),
-						];
-						values.extend(data.into_iter().cloned().map(|value| {
-							Box::new(Option::<MetadataFieldValue>::from(value))
-								as Box<dyn ToSql + Send + Sync>
-						}));
-						tx.send((path, values))
-							.unwrap_or_else(|_err| unreachable!());
-					});
-			},
-		);
+					}
+					let count = stmt
+						.execute(rusqlite::params_from_iter(values))
+						.unwrap_or_else(|err| {
+							log::trace!("Could not insert media {path:?}: {err}");
+							0
+						});
+					if count > 0 {
+						clear_failures_stmt.execute((&*self.name, &path))?;
+						emit(progress, || IndexingEvent::Inserted {
+							library: self.name.clone(),
+							path,
+						});
+					}
+					Ok(count)
+				})
+				.sum::<rusqlite::Result<usize>>()
+		})?;
 
 		// Update database
 		let cached_media = cached_media.lock().unwrap();
-		let added_count = rx
-			.into_iter()
-			.map(|(path, values)| {
-				stmt.execute(rusqlite::params_from_iter(values))
-					.unwrap_or_else(|err| {
-						log::trace!("Could not insert media {path:?}: {err}");
-						0
-					})
-			})
-			.sum::<usize>();
 		stmt.finalize()?;
+		waveform_stmt.finalize()?;
+		raw_metadata_stmt.finalize()?;
+		clear_failures_stmt.finalize()?;
+		self.update_failures(
+			&transaction,
+			&failures.into_inner().unwrap(),
+			new_failures.into_inner().unwrap(),
+		)?;
 		let removed_count = transaction.execute(
 			&format!(
 				"DELETE FROM {table} WHERE path IN ({})",
@@ -280,6 +651,35 @@ impl MediaPlugin {
 			),
 			rusqlite::params_from_iter(cached_media.keys()),
 		)?;
+		transaction.execute(
+			&format!(
+				"DELETE FROM media_waveforms WHERE library = ? AND path IN ({})",
+				vec!["?"; cached_media.len()].join(", "),
+			),
+			rusqlite::params_from_iter(
+				[&*self.name]
+					.into_iter()
+					.chain(cached_media.keys().map(String::as_str)),
+			),
+		)?;
+		transaction.execute(
+			&format!(
+				"DELETE FROM media_raw_metadata WHERE library = ? AND path IN ({})",
+				vec!["?"; cached_media.len()].join(", "),
+			),
+			rusqlite::params_from_iter(
+				[&*self.name]
+					.into_iter()
+					.chain(cached_media.keys().map(String::as_str)),
+			),
+		)?;
+		self.update_group_keys(&transaction, config)?;
+		self.update_search_text(&transaction, config)?;
+		db::bump_generation(&transaction, &self.name)?;
+		emit(progress, || IndexingEvent::Removed {
+			library: self.name.clone(),
+			count: removed_count,
+		});
 		log::info!(
 			"Added {added_count}, kept {}, removed {removed_count} {} media in {:.3}s",
 			cached_count - removed_count,
@@ -290,6 +690,147 @@ impl MediaPlugin {
 		transaction.commit()
 	}
 }
+impl MediaPlugin {
+	/// Records the failed extractions of the last indexing
+	///
+	/// `stale` contains the previous failures of files that were not found anymore.
+	fn update_failures(
+		&self,
+		transaction: &rusqlite::Transaction<'_>,
+		stale: &HashMap<String, (OffsetDateTime, u32)>,
+		failures: Vec<(String, SystemTime, u32)>,
+	) -> rusqlite::Result<()> {
+		transaction.execute(
+			&format!(
+				"DELETE FROM media_failures WHERE library = ? AND path IN ({})",
+				vec!["?"; stale.len()].join(", "),
+			),
+			rusqlite::params_from_iter(
+				[&*self.name]
+					.into_iter()
+					.chain(stale.keys().map(String::as_str)),
+			),
+		)?;
+
+		let mut stmt = transaction.prepare(
+			"INSERT INTO media_failures(library, path, mtime, failures) VALUES (?, ?, ?, ?)",
+		)?;
+		for (path, mtime, count) in failures {
+			stmt.execute((&*self.name, path, OffsetDateTime::from(mtime), count))?;
+		}
+		Ok(())
+	}
+
+	/// Recomputes the searchable text of the media, if the library is [searchable](MediaConfig#structfield.search)
+	///
+	/// It is made of the lowercased values of the text fields, one per line,
+	/// so that a searched substring does not span several values.
+	fn update_search_text(
+		&self,
+		transaction: &rusqlite::Transaction<'_>,
+		config: &MediaConfig,
+	) -> rusqlite::Result<()> {
+		transaction.execute("DELETE FROM media_search WHERE library = ?", [&*self.name])?;
+
+		let fields = self
+			.media
+			.fields
+			.iter()
+			.filter(|field| field.r#type == MetadataFieldType::Text)
+			.collect::<Vec<_>>();
+		if !config.search || fields.is_empty() {
+			return Ok(());
+		}
+
+		let mut insert_stmt = transaction
+			.prepare("INSERT INTO media_search(library, path, text) VALUES (?, ?, ?)")?;
+		let mut select_stmt = transaction.prepare(&format!(
+			"SELECT path, {fields} FROM {table}",
+			fields = fields
+				.iter()
+				.map(|field| field.ident.to_str())
+				.collect::<Vec<_>>()
+				.join(", "),
+			table = self.table_ident(),
+		))?;
+		let mut rows = select_stmt.query(())?;
+		while let Some(row) = rows.next()? {
+			let path = row.get::<_, String>(0)?;
+			let mut text = String::new();
+			for (i, field) in fields.iter().enumerate() {
+				let Some(value) = row.get::<_, Option<String>>(i + 1)? else {
+					continue;
+				};
+				let values = if field.is_list {
+					serde_json::from_str::<Vec<String>>(&value).unwrap_or_default()
+				} else {
+					vec![value]
+				};
+				for value in values {
+					text.push_str(&value.to_lowercase());
+					text.push('\n');
+				}
+			}
+			insert_stmt.execute((&*self.name, &path, text))?;
+		}
+		Ok(())
+	}
+
+	/// Recomputes the normalized keys of the fields configured to be normalized
+	fn update_group_keys(
+		&self,
+		transaction: &rusqlite::Transaction<'_>,
+		config: &MediaConfig,
+	) -> rusqlite::Result<()> {
+		transaction.execute(
+			"DELETE FROM media_group_keys WHERE library = ?",
+			[&*self.name],
+		)?;
+
+		let mut insert_stmt = transaction.prepare(
+			"INSERT INTO media_group_keys(library, path, field, value, key) VALUES (?, ?, ?, ?, ?)",
+		)?;
+		for field in self.media.fields.iter().filter(|field| {
+			field.r#type == MetadataFieldType::Text
+				&& config
+					.normalize
+					.fields
+					.iter()
+					.any(|ident| *ident == *field.ident)
+		}) {
+			let mut select_stmt = transaction.prepare(&format!(
+				"SELECT path, {field} FROM {table} WHERE {field} IS NOT NULL",
+				field = field.ident,
+				table = self.table_ident(),
+			))?;
+			let mut rows = select_stmt.query(())?;
+			while let Some(row) = rows.next()? {
+				let path = row.get::<_, String>(0)?;
+				let value = row.get::<_, String>(1)?;
+				let values = if field.is_list {
+					serde_json::from_str::<Vec<String>>(&value).unwrap_or_default()
+				} else {
+					vec![value]
+				};
+				for value in values {
+					let key = config.normalize.key(&value);
+					insert_stmt.execute((&*self.name, &path, &*field.ident, &value, key))?;
+				}
+			}
+		}
+		Ok(())
+	}
+}
+impl Drop for MediaPlugin {
+	fn drop(&mut self) {
+		// SAFETY: Upheld by plugin
+		if let Ok(plugin_shutdown) = unsafe { self.lib.get::<PluginShutdown>(b"plugin_shutdown\0") }
+		{
+			log::debug!("Shutting down {self}");
+			plugin_shutdown();
+		}
+	}
+}
 impl Debug for MediaPlugin {
 	fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
 		write!(f, "{self} ({:?})", self.lib)
@@ -300,7 +841,88 @@ impl Display for MediaPlugin {
 		write!(f, "media plugin <{} {}>", self.name, self.version)
 	}
 }
+
+/// Checks the ABI fingerprint exported by a media plugin, if any, against the one of the server
+fn check_abi_fingerprint(name: &str, abi_fingerprint: Option<u64>) -> Result<(), PluginLoadError> {
+	match abi_fingerprint {
+		Some(abi_fingerprint) if abi_fingerprint == pluglib::media::ABI_FINGERPRINT => Ok(()),
+		Some(_) => Err(PluginLoadError::AbiMismatch {
+			kind: PluginKind::Media,
+			name: name.into(),
+		}),
+		None => Err(PluginLoadError::MissingAbiFingerprint {
+			kind: PluginKind::Media,
+			name: name.into(),
+		}),
+	}
+}
 impl Plugin for MediaPlugin {
+	fn load(path: &Path, config: &PluginsConfig) -> Result<Self, PluginLoadError> {
+		let lib = super::load_library(path, config)?;
+
+		let Some(name): Option<Box<str>> = path.file_stem().map(|s| s.to_string_lossy().into())
+		else {
+			unreachable!()
+		};
+
+		// NOTE: Plugins built against older plugin libraries do not export a fingerprint
+		// SAFETY: A `u64` has the same layout on all platforms the server runs on
+		let abi_fingerprint = unsafe { lib.get::<*const u64>(b"ABI_FINGERPRINT\0") }
+			.ok()
+			// SAFETY: The symbol points to a `u64`
+			.map(|abi_fingerprint| unsafe { **abi_fingerprint });
+		check_abi_fingerprint(&name, abi_fingerprint)?;
+
+		// SAFETY: Upheld by the plugin
+		let pluglib_version = unsafe { &**lib.get::<*const Version>(b"PLUGLIB_VERSION\0")? };
+		if !pluglib::media::PLUGLIB_VERSION.is_compatible(pluglib_version) {
+			return Err(PluginLoadError::IncompatibleLibVersions {
+				kind: PluginKind::Media,
+				name,
+				plugin: *pluglib_version,
+				server: pluglib::media::PLUGLIB_VERSION,
+			});
+		}
+
+		// SAFETY: Upheld by the plugin
+		if let Ok(set_logger) = unsafe { lib.get::<SetLogger>(b"set_logger\0") } {
+			set_logger(super::forward_plugin_log);
+		}
+
+		// SAFETY: Upheld by the plugin
+		let plugin_version = unsafe { lib.get::<PluginVersion>(b"plugin_version\0")? };
+		let version = plugin_version();
+
+		// SAFETY: Upheld by the plugin
+		let describe_plugin = unsafe { lib.get::<DescribeMedia>(b"describe_media\0")? };
+		let media = describe_plugin();
+
+		// SAFETY: Upheld by the plugin
+		let (supported_types, supported_extensions, extract_metadata, extract_raw_metadata) = unsafe {
+			(
+				*lib.get::<SupportedTypes>(Self::SUPPORTED_TYPES)?,
+				lib.get::<SupportedExtensions>(Self::SUPPORTED_EXTENSIONS)
+					.ok()
+					.map(|symbol| *symbol),
+				*lib.get::<ExtractMetadata>(Self::EXTRACT_METADATA)?,
+				lib.get::<ExtractRawMetadata>(Self::EXTRACT_RAW_METADATA)
+					.ok()
+					.map(|symbol| *symbol),
+			)
+		};
+
+		Ok(Self {
+			lib,
+			supported_types,
+			supported_extensions,
+			extract_metadata,
+			extract_raw_metadata,
+			name,
+			version,
+			media,
+		})
+	}
+
 	fn update_database(
 		&self,
 		db_pool: &Pool<SqliteConnectionManager>,
@@ -309,42 +931,289 @@ impl Plugin for MediaPlugin {
 		let mut conn = db_pool.get()?;
 		let transaction = conn.transaction()?;
 
-		let mut fields = vec![
-			"path TEXT NOT NULL PRIMARY KEY ON CONFLICT REPLACE".to_owned(),
-			"mtime TEXT NOT NULL".to_owned(),
-		];
-		fields.extend(self.media.fields.iter().map(|field| {
-			format!(
-				"{} {}",
-				field.ident,
-				if field.is_list {
-					"TEXT NOT NULL DEFAULT (json_array())"
-				} else {
-					field.r#type.to_sql()
-				}
-			)
-		}));
-
-		transaction.execute_batch(
-			format!(
-				"
-					DROP TABLE IF EXISTS {table};
-					CREATE TABLE {table} ({}) STRICT, WITHOUT ROWID;
-				",
-				fields.join(","),
-				table = self.table_ident(),
-			)
-			.trim(),
-		)?;
+		transaction.execute_batch(&format!(
+			"DROP TABLE IF EXISTS {table};\n{schema}",
+			table = self.table_ident(),
+			schema = self.schema(),
+		))?;
 		transaction.execute(
-			"INSERT INTO plugins(name, kind, version) VALUES (:name, :kind, :version)",
+			"INSERT INTO plugins(name, kind, version, table_name) VALUES (:name, :kind, :version, :table_name)",
 			rusqlite::named_params! {
 				":name": db_plugin.name,
 				":kind": db_plugin.kind,
 				":version": db_plugin.version,
+				":table_name": db_plugin.table,
 			},
 		)?;
 
 		transaction.commit().map_err(From::from)
 	}
 }
+
+/// Stub media plugin for tests
+///
+/// Its functions are defined in the test binary, which is loaded as the library of the plugin.
+/// It supports plain text and MP3 files, and describes media with `title` and `album` text fields,
+/// and a `track` integer field.
+#[cfg(test)]
+pub(crate) mod stub {
+	use super::MediaPlugin;
+	use crate::{
+		config::{Config, DatabaseConfig, MediaConfig},
+		db,
+		plugins::PluginStore,
+	};
+	use pluglib::{
+		ffi::{new_ffistr, FfiBoxedSlice, FfiError, FfiOption, FfiResult, FfiStr},
+		media::{
+			ExtractMetadata, MediaBuilder, MetadataFieldBuilder, MetadataFieldType,
+			MetadataFieldValue,
+		},
+		Version,
+	};
+	use r2d2::Pool;
+	use r2d2_sqlite::SqliteConnectionManager;
+	use std::{
+		path::{Path, PathBuf},
+		sync::Arc,
+	};
+	use tempfile::TempDir;
+
+	/// Lists the types supported by the plugin
+	extern "C" fn supported_types() -> FfiBoxedSlice<FfiStr<'static>> {
+		[
+			new_ffistr!("text/plain"),
+			new_ffistr!("audio/mp3"),
+			new_ffistr!("audio/mpeg"),
+		]
+		.into_iter()
+		.collect()
+	}
+
+	/// Extracts the file stem of the media file as its title, and nothing else
+	pub(crate) extern "C" fn extract_title(
+		path: FfiStr<'_>,
+	) -> FfiResult<FfiBoxedSlice<FfiOption<MetadataFieldValue>>, FfiError> {
+		let title = Path::new(&*path)
+			.file_stem()
+			.and_then(|stem| stem.to_str())
+			.and_then(|stem| MetadataFieldValue::try_from(stem).ok());
+		FfiResult::Ok(
+			[title, None, None, None]
+				.into_iter()
+				.map(FfiOption::from)
+				.collect(),
+		)
+	}
+
+	/// Extracts the metadata of the media file from its `field=value` lines
+	pub(crate) extern "C" fn extract_tags(
+		path: FfiStr<'_>,
+	) -> FfiResult<FfiBoxedSlice<FfiOption<MetadataFieldValue>>, FfiError> {
+		let contents = std::fs::read_to_string(&*path).unwrap_or_default();
+		let tag = |field: &str| {
+			contents
+				.lines()
+				.find_map(|line| line.strip_prefix(field)?.strip_prefix('='))
+		};
+		FfiResult::Ok(
+			[
+				tag("title").and_then(|title| MetadataFieldValue::try_from(title).ok()),
+				tag("album").and_then(|album| MetadataFieldValue::try_from(album).ok()),
+				tag("track")
+					.and_then(|track| track.parse::<i64>().ok())
+					.map(MetadataFieldValue::from),
+				tag("duration")
+					.and_then(|duration| duration.parse::<u64>().ok())
+					.map(|millis| {
+						MetadataFieldValue::from(std::time::Duration::from_millis(millis))
+					}),
+			]
+			.into_iter()
+			.map(FfiOption::from)
+			.collect(),
+		)
+	}
+
+	/// Constructs the stub plugin with the given name and extraction function
+	fn plugin(name: &str, extract_metadata: ExtractMetadata) -> MediaPlugin {
+		#[cfg(unix)]
+		let lib = libloading::os::unix::Library::this().into();
+		#[cfg(windows)]
+		let lib = libloading::os::windows::Library::this()
+			.expect("the test binary could not be loaded")
+			.into();
+
+		MediaPlugin {
+			lib,
+			supported_types,
+			supported_extensions: None,
+			extract_metadata,
+			extract_raw_metadata: None,
+			name: name.into(),
+			version: Version::default(),
+			media: MediaBuilder::new(new_ffistr!("stub"), new_ffistr!("Stub"))
+				.field(MetadataFieldBuilder::new(
+					new_ffistr!("title"),
+					new_ffistr!("Title"),
+					MetadataFieldType::Text,
+				))
+				.field(MetadataFieldBuilder::new(
+					new_ffistr!("album"),
+					new_ffistr!("Album"),
+					MetadataFieldType::Text,
+				))
+				.field(MetadataFieldBuilder::new(
+					new_ffistr!("track"),
+					new_ffistr!("Track"),
+					MetadataFieldType::Integer,
+				))
+				.field(MetadataFieldBuilder::new(
+					new_ffistr!("duration"),
+					new_ffistr!("Duration"),
+					MetadataFieldType::Duration,
+				))
+				.build(),
+		}
+	}
+
+	/// Creates a new temporary directory
+	///
+	/// Unlike the default ones, its name does not start with a `.`, so that it is not skipped by the walk.
+	pub(crate) fn tempdir() -> TempDir {
+		tempfile::Builder::new()
+			.prefix("aepa-")
+			.tempdir()
+			.expect("the temporary directory could not be created")
+	}
+
+	/// Library of the stub plugin, stored in a temporary directory with its database
+	#[derive(Debug)]
+	pub(crate) struct Library {
+		/// Directory of the media files
+		pub(crate) dir: TempDir,
+		/// Directory of the database
+		_data: TempDir,
+		/// Configuration of the server
+		pub(crate) config: Config,
+		/// Pool of connections to the database
+		pub(crate) db_pool: Pool<SqliteConnectionManager>,
+		/// Store of the stub plugin
+		pub(crate) plugins: Arc<PluginStore>,
+	}
+	impl Library {
+		/// Name of the library
+		pub(crate) const NAME: &'static str = "stub";
+
+		/// Creates an empty library whose metadata is extracted by the given function
+		///
+		/// The configuration may be changed by `configure` before the plugin store is created.
+		/// The check of the free space is disabled.
+		pub(crate) fn new(
+			extract_metadata: ExtractMetadata,
+			configure: impl FnOnce(&mut Config),
+		) -> Self {
+			let dir = tempdir();
+			let data = tempdir();
+			let mut config = Config::default();
+			config.indexing.min_free_space = 0;
+			config.media.insert(
+				Self::NAME.to_owned(),
+				MediaConfig {
+					paths: vec![PathBuf::from(dir.path())],
+					..Default::default()
+				},
+			);
+			configure(&mut config);
+
+			let db_pool = db::open(data.path().join("db.sqlite"), &DatabaseConfig::default())
+				.expect("the database could not be opened");
+			let mut plugins =
+				PluginStore::new(&config).expect("the plugin store could not be created");
+			plugins
+				.media
+				.insert(Self::NAME.to_owned(), plugin(Self::NAME, extract_metadata));
+			plugins
+				.update_database(&db_pool)
+				.expect("the database could not be updated");
+			Self {
+				dir,
+				_data: data,
+				config,
+				db_pool,
+				plugins: Arc::new(plugins),
+			}
+		}
+
+		/// Writes a media file in the library, and returns its path
+		pub(crate) fn write(&self, name: &str, contents: impl AsRef<[u8]>) -> PathBuf {
+			let path = self.dir.path().join(name);
+			std::fs::write(&path, contents).expect("the media file could not be written");
+			path
+		}
+
+		/// Indexes the media of the library
+		pub(crate) fn index(&self) {
+			self.plugins
+				.load_media(&self.db_pool, &self.config.media, None)
+				.expect("the media could not be indexed");
+		}
+
+		/// Returns the titles of the indexed media, in order
+		pub(crate) fn titles(&self) -> Vec<String> {
+			let conn = self.db_pool.get().unwrap();
+			let mut stmt = conn
+				.prepare("SELECT title FROM media_stub ORDER BY title")
+				.unwrap();
+			let rows = stmt.query_map((), |row| row.get(0)).unwrap();
+			rows.collect::<rusqlite::Result<_>>().unwrap()
+		}
+
+		/// Returns the identifier of the indexed media with the given title
+		pub(crate) fn id(&self, title: &str) -> i64 {
+			self.db_pool
+				.get()
+				.unwrap()
+				.query_row(
+					"SELECT id FROM media_stub WHERE title = ?",
+					[title],
+					|row| row.get(0),
+				)
+				.unwrap()
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn matching_abi_fingerprint_is_accepted() {
+		assert!(check_abi_fingerprint("stub", Some(pluglib::media::ABI_FINGERPRINT)).is_ok());
+	}
+
+	#[test]
+	fn mismatched_abi_fingerprint_is_rejected() {
+		let err = check_abi_fingerprint("stub", Some(!pluglib::media::ABI_FINGERPRINT));
+		assert!(
+			matches!(
+				err,
+				Err(PluginLoadError::AbiMismatch { kind: PluginKind::Media, ref name }) if &**name == "stub"
+			),
+			"{err:?}"
+		);
+	}
+
+	#[test]
+	fn missing_abi_fingerprint_is_rejected() {
+		let err = check_abi_fingerprint("stub", None);
+		assert!(
+			matches!(
+				err,
+				Err(PluginLoadError::MissingAbiFingerprint { kind: PluginKind::Media, ref name }) if &**name == "stub"
+			),
+			"{err:?}"
+		);
+	}
+}
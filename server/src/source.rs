@@ -0,0 +1,232 @@
+//! Provides an abstraction over the storage of media files
+
+use std::{
+	fmt::Debug,
+	fs, io,
+	path::{Path, PathBuf},
+	pin::Pin,
+	sync::{mpsc, Arc, Mutex, PoisonError},
+	thread,
+	time::{Duration, SystemTime},
+};
+use tokio::io::AsyncRead;
+
+/// Reader of the content of a media file
+pub(crate) type MediaReader = Pin<Box<dyn AsyncRead + Send>>;
+
+/// Information about a media file
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct FileInfo {
+	/// Whether the path is a directory
+	pub(crate) is_dir: bool,
+	/// Size of the file (in bytes)
+	pub(crate) len: u64,
+	/// Last modification time of the file
+	pub(crate) modified: SystemTime,
+}
+
+/// Storage that media files are read from
+pub(crate) trait MediaSource: Debug + Send + Sync {
+	/// Returns information about a file, following symbolic links
+	fn metadata(&self, path: &Path) -> io::Result<FileInfo>;
+
+	/// Opens a file for reading
+	///
+	/// This function may block, so it must not be called directly from an asynchronous context.
+	fn open(&self, path: &Path) -> io::Result<MediaReader>;
+}
+
+/// [`MediaSource`] that reads from the local filesystem
+#[derive(Debug, Default, Clone, Copy)]
+pub(crate) struct LocalFs;
+impl MediaSource for LocalFs {
+	fn metadata(&self, path: &Path) -> io::Result<FileInfo> {
+		let meta = fs::metadata(path)?;
+		Ok(FileInfo {
+			is_dir: meta.is_dir(),
+			len: meta.len(),
+			modified: meta.modified()?,
+		})
+	}
+
+	#[inline]
+	fn open(&self, path: &Path) -> io::Result<MediaReader> {
+		fs::File::open(path).map(|file| Box::pin(tokio::fs::File::from_std(file)) as MediaReader)
+	}
+}
+
+/// Request to read the metadata of a file, with the channel to send the result on
+type StatRequest = (PathBuf, mpsc::SyncSender<io::Result<FileInfo>>);
+
+/// Pool of threads reading the metadata of files, giving up after a timeout
+///
+/// Without a timeout, [`MediaSource::metadata`] is called on the calling thread.
+/// Otherwise it is called on one of the long-lived threads of the pool,
+/// which is left behind if it does not finish in time (e.g. because a network filesystem is unreachable).
+/// The number of threads is bounded, so an unreachable filesystem holds at most all of them,
+/// after which every read times out.
+/// The threads stop once the pool is dropped and their current read returns.
+#[derive(Debug)]
+pub(crate) struct StatPool {
+	/// Storage that the metadata is read from
+	source: Arc<dyn MediaSource>,
+	/// Maximum time to wait for a read
+	timeout: Option<Duration>,
+	/// Sender of the requests to the threads
+	///
+	/// It is [`None`] if there is no timeout or if no thread could be started.
+	requests: Option<mpsc::Sender<StatRequest>>,
+}
+impl StatPool {
+	/// Starts a pool of `threads` threads
+	pub(crate) fn new(
+		source: &Arc<dyn MediaSource>,
+		timeout: Option<Duration>,
+		threads: usize,
+	) -> Self {
+		let requests = timeout.and_then(|_| {
+			let (tx, rx) = mpsc::channel::<StatRequest>();
+			let rx = Arc::new(Mutex::new(rx));
+			let mut started = 0_usize;
+			for i in 0..threads.max(1) {
+				let source = Arc::clone(source);
+				let rx = Arc::clone(&rx);
+				let res = thread::Builder::new()
+					.name(format!("stat-{i}"))
+					.spawn(move || loop {
+						// NOTE: The lock is released before the metadata is read
+						let Ok((path, tx)) =
+							rx.lock().unwrap_or_else(PoisonError::into_inner).recv()
+						else {
+							break;
+						};
+						// NOTE: The receiver is gone if the timeout expired
+						let _res = tx.send(source.metadata(&path));
+					});
+				match res {
+					Ok(_handle) => started += 1,
+					Err(err) => {
+						log::warn!("Could not start a thread to read the metadata of files: {err}");
+					}
+				}
+			}
+			(started > 0).then_some(tx)
+		});
+		Self {
+			source: Arc::clone(source),
+			timeout,
+			requests,
+		}
+	}
+
+	/// Returns information about a file, giving up after the timeout
+	///
+	/// # Errors
+	/// This function returns a [`TimedOut`](io::ErrorKind::TimedOut) error if the timeout expires.
+	pub(crate) fn metadata(&self, path: &Path) -> io::Result<FileInfo> {
+		let (Some(timeout), Some(requests)) = (self.timeout, &self.requests) else {
+			return self.source.metadata(path);
+		};
+
+		let (tx, rx) = mpsc::sync_channel(1);
+		requests
+			.send((PathBuf::from(path), tx))
+			.map_err(|_err| io::Error::new(io::ErrorKind::Other, "the metadata threads stopped"))?;
+		rx.recv_timeout(timeout).unwrap_or_else(|_err| {
+			Err(io::Error::new(
+				io::ErrorKind::TimedOut,
+				format!("reading the metadata of {path:?} timed out after {timeout:?}"),
+			))
+		})
+	}
+}
+
+/// [`MediaSource`] that takes [too long](Self::DELAY) to read the metadata of the files whose name contains `slow`
+///
+/// It reads from the local filesystem otherwise.
+#[cfg(test)]
+#[derive(Debug, Default, Clone, Copy)]
+pub(crate) struct SlowFs;
+#[cfg(test)]
+impl SlowFs {
+	/// Time taken to read the metadata of slow files
+	pub(crate) const DELAY: Duration = Duration::from_secs(2);
+}
+#[cfg(test)]
+impl MediaSource for SlowFs {
+	fn metadata(&self, path: &Path) -> io::Result<FileInfo> {
+		if path
+			.file_name()
+			.and_then(|name| name.to_str())
+			.is_some_and(|name| name.contains("slow"))
+		{
+			thread::sleep(Self::DELAY);
+		}
+		LocalFs.metadata(path)
+	}
+
+	#[inline]
+	fn open(&self, path: &Path) -> io::Result<MediaReader> {
+		LocalFs.open(path)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::time::Instant;
+
+	#[test]
+	fn metadata_times_out() {
+		let dir = tempfile::tempdir().unwrap();
+		let path = dir.path().join("slow.txt");
+		fs::write(&path, "media").unwrap();
+		let source: Arc<dyn MediaSource> = Arc::new(SlowFs);
+		let pool = StatPool::new(&source, Some(Duration::from_millis(50)), 1);
+
+		let start = Instant::now();
+		let err = pool
+			.metadata(&path)
+			.expect_err("reading the metadata should have timed out");
+		assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+		assert!(start.elapsed() < SlowFs::DELAY);
+
+		let pool = StatPool::new(&source, Some(Duration::from_millis(500)), 1);
+		let info = pool
+			.metadata(dir.path())
+			.expect("reading the metadata of a fast file should not time out");
+		assert!(info.is_dir);
+	}
+
+	#[test]
+	fn stat_threads_are_bounded() {
+		let dir = tempfile::tempdir().unwrap();
+		let source: Arc<dyn MediaSource> = Arc::new(SlowFs);
+		let pool = StatPool::new(&source, Some(Duration::from_millis(50)), 2);
+		for i in 0..8 {
+			let path = dir.path().join(format!("slow{i}.txt"));
+			fs::write(&path, "media").unwrap();
+			let err = pool
+				.metadata(&path)
+				.expect_err("reading the metadata should have timed out");
+			assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+		}
+
+		// NOTE: Both threads are still busy with the slow files
+		let err = pool
+			.metadata(dir.path())
+			.expect_err("no thread should be available");
+		assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+	}
+
+	#[test]
+	fn metadata_without_timeout_is_read_directly() {
+		let dir = tempfile::tempdir().unwrap();
+		let source: Arc<dyn MediaSource> = Arc::new(SlowFs);
+		let pool = StatPool::new(&source, None, 2);
+		assert!(pool.requests.is_none());
+
+		let info = pool.metadata(dir.path()).unwrap();
+		assert!(info.is_dir);
+	}
+}
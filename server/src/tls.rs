@@ -6,10 +6,11 @@ use hyper::server::{
 	conn::{AddrIncoming, AddrStream},
 };
 use hyper_rustls::{acceptor::TlsStream, TlsAcceptor};
-use rustls::{Certificate, PrivateKey};
+use rustls::{Certificate, PrivateKey, SignatureScheme};
 #[cfg(unix)]
 use std::os::unix::prelude::PermissionsExt;
 use std::{
+	error::Error,
 	fs::File,
 	io::{self, BufReader, IoSlice, Write},
 	net::SocketAddr,
@@ -85,6 +86,59 @@ impl Identity {
 		// See https://github.com/rustls/rcgen/issues/62
 		Self::read(key, certificate)
 	}
+
+	/// Checks that the private key matches the certificate of the end entity
+	///
+	/// A message is signed with the key, then the signature is verified with the public key of the certificate.
+	///
+	/// # Errors
+	/// This function returns an error if the key or the certificate cannot be used.
+	pub(crate) fn key_matches(&self) -> Result<bool, Box<dyn Error>> {
+		/// Signature schemes that may be chosen, with their verification algorithm
+		static SCHEMES: [(SignatureScheme, &webpki::SignatureAlgorithm); 4] = [
+			(SignatureScheme::ED25519, &webpki::ED25519),
+			(
+				SignatureScheme::ECDSA_NISTP256_SHA256,
+				&webpki::ECDSA_P256_SHA256,
+			),
+			(
+				SignatureScheme::ECDSA_NISTP384_SHA384,
+				&webpki::ECDSA_P384_SHA384,
+			),
+			(
+				SignatureScheme::RSA_PSS_SHA256,
+				&webpki::RSA_PSS_2048_8192_SHA256_LEGACY_KEY,
+			),
+		];
+		/// Signed message
+		const MESSAGE: &[u8] = b"aedron-patchouli key check";
+
+		let signer = rustls::sign::any_supported_type(&self.key)?
+			.choose_scheme(&SCHEMES.map(|(scheme, _)| scheme))
+			.ok_or("the type of the private key is not supported")?;
+		let Some((_, algorithm)) = SCHEMES
+			.iter()
+			.find(|(scheme, _)| *scheme == signer.scheme())
+		else {
+			unreachable!()
+		};
+		let signature = signer.sign(MESSAGE)?;
+
+		let cert = self
+			.cert_chain
+			.first()
+			.ok_or("the certificate chain is empty")?;
+		match webpki::EndEntityCert::try_from(cert.0.as_slice())?
+			.verify_signature(algorithm, MESSAGE, &signature)
+		{
+			Ok(()) => Ok(true),
+			Err(
+				webpki::Error::InvalidSignatureForPublicKey
+				| webpki::Error::UnsupportedSignatureAlgorithmForPublicKey,
+			) => Ok(false),
+			Err(err) => Err(err.into()),
+		}
+	}
 }
 impl Zeroize for Identity {
 	fn zeroize(&mut self) {
@@ -0,0 +1,64 @@
+//! Provides the computation of audio waveforms
+
+use std::{
+	io::{self, Read},
+	path::Path,
+	process::{Command, Stdio},
+};
+
+/// Maximum number of peaks in a waveform
+const PEAKS_COUNT: usize = 1000;
+/// Sample rate at which the audio is decoded
+const SAMPLE_RATE: u64 = 8000;
+/// Number of bytes in a window of 10ms of decoded audio
+const WINDOW_LEN: u64 = SAMPLE_RATE / 100 * 2;
+
+/// Computes the waveform of an audio file using `ffmpeg`
+///
+/// The waveform is made of at most [`PEAKS_COUNT`] peaks, each scaled to `0..=255`.
+///
+/// # Errors
+/// This function returns an error if `ffmpeg` could not be run or did not succeed.
+pub(crate) fn compute_peaks(path: &Path) -> io::Result<Box<[u8]>> {
+	let mut child = Command::new("ffmpeg")
+		.args(["-v", "quiet", "-i"])
+		.arg(path)
+		.args(["-vn", "-ac", "1", "-ar", &SAMPLE_RATE.to_string()])
+		.args(["-f", "s16le", "-"])
+		.stdin(Stdio::null())
+		.stdout(Stdio::piped())
+		.stderr(Stdio::null())
+		.spawn()?;
+	let Some(mut stdout) = child.stdout.take() else {
+		unreachable!()
+	};
+
+	let mut windows = Vec::new();
+	let mut buf = Vec::with_capacity(WINDOW_LEN as usize);
+	loop {
+		buf.clear();
+		if stdout.by_ref().take(WINDOW_LEN).read_to_end(&mut buf)? == 0 {
+			break;
+		}
+		windows.push(
+			buf.chunks_exact(2)
+				.map(|sample| i16::from_le_bytes([sample[0], sample[1]]).unsigned_abs())
+				.max()
+				.unwrap_or_default(),
+		);
+	}
+	if !child.wait()?.success() {
+		return Err(io::Error::new(
+			io::ErrorKind::Other,
+			"ffmpeg did not exit successfully",
+		));
+	}
+
+	Ok(windows
+		.chunks(windows.len().div_ceil(PEAKS_COUNT).max(1))
+		.map(|chunk| {
+			let peak = chunk.iter().copied().max().unwrap_or_default();
+			u8::try_from(peak >> 7).unwrap_or(u8::MAX)
+		})
+		.collect())
+}
@@ -0,0 +1,82 @@
+//! Tests of the `--index-only` mode of the server executable
+
+use std::{
+	env::consts::{DLL_PREFIX, DLL_SUFFIX},
+	fs,
+	path::{Path, PathBuf},
+	process::{Command, Output},
+	sync::OnceLock,
+};
+use tempfile::TempDir;
+
+/// Builds the music plugin, and returns the path of its dynamic library
+///
+/// It is built in its own target directory, since the one of the tests is locked while they run.
+fn music_plugin() -> &'static Path {
+	/// Path of the built dynamic library
+	static PLUGIN: OnceLock<PathBuf> = OnceLock::new();
+	PLUGIN.get_or_init(|| {
+		let target_dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("plugins");
+		let status = Command::new(env!("CARGO"))
+			.args(["build", "--package", "aedron_patchouli-plugin-media-music"])
+			.arg("--target-dir")
+			.arg(&target_dir)
+			.current_dir(env!("CARGO_MANIFEST_DIR"))
+			.status()
+			.expect("cargo could not be run");
+		assert!(status.success(), "the music plugin could not be built");
+		target_dir.join("debug").join(format!(
+			"{DLL_PREFIX}aedron_patchouli_plugin_media_music{DLL_SUFFIX}"
+		))
+	})
+}
+
+/// Runs the server in index-only mode with the music plugin and a library of a single file
+///
+/// The given TOML is appended to the configuration.
+fn index_only(config: &str) -> (TempDir, Output) {
+	let root = tempfile::Builder::new().prefix("aepa-").tempdir().unwrap();
+	let plugins = root.path().join("data/aedron-patchouli/plugins");
+	fs::create_dir_all(&plugins).unwrap();
+	fs::copy(music_plugin(), plugins.join("music.media")).unwrap();
+	let library = root.path().join("library");
+	fs::create_dir(&library).unwrap();
+	fs::write(library.join("song.mp3"), "not really a song").unwrap();
+	fs::write(
+		root.path().join("config.toml"),
+		format!("[media.music]\npaths = [{library:?}]\n{config}"),
+	)
+	.unwrap();
+
+	let output = Command::new(env!("CARGO_BIN_EXE_aedron-patchouli"))
+		.arg("--index-only")
+		.current_dir(root.path())
+		.env("AEPA_DB", root.path().join("db.sqlite"))
+		.env("XDG_CONFIG_HOME", root.path().join("config"))
+		.env("XDG_DATA_HOME", root.path().join("data"))
+		.env_remove("XDG_DATA_DIRS")
+		.output()
+		.expect("the server could not be run");
+	(root, output)
+}
+
+#[test]
+fn exits_after_indexing() {
+	let (root, output) = index_only("");
+	let stdout = String::from_utf8_lossy(&output.stdout);
+	assert!(output.status.success(), "{stdout}");
+	// NOTE: The file is only seen if `ffprobe` is installed
+	assert!(stdout.contains("Library music: "), "{stdout}");
+	assert!(root.path().join("db.sqlite").exists());
+}
+
+#[test]
+fn fails_if_a_library_could_not_be_indexed() {
+	let (_root, output) = index_only("[indexing]\nmin_free_space = 9223372036854775807\n");
+	let stdout = String::from_utf8_lossy(&output.stdout);
+	assert!(!output.status.success(), "{stdout}");
+	assert!(
+		stdout.contains("Could not index the media of the libraries music"),
+		"{stdout}"
+	);
+}